@@ -0,0 +1,45 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use limit_stream::codegen::{
+    format_idl,
+    formatter::{Formatter, Style},
+    idl2rust,
+    rust::Rust,
+};
+use limit_stream::config::{GenMode, Target};
+
+/// `n` definitions covering every kind of node
+fn schema(n: usize) -> String {
+    let mut src = String::new();
+    for i in 0..n / 5 {
+        src += &format!(
+            "const LEN{i}: uint = 4;\n\
+            type Id{i} = uint;\n\
+            struct User{i}<T> {{\n\
+            \x20   id: Id{i} = 0 #[range=\"0..\"],\n\
+            \x20   name: string = 1 #[max_len=64],\n\
+            \x20   tags: [string; LEN{i}] = 2,\n\
+            \x20   extra: dict<string, option<T>> = 3,\n\
+            }}\n\
+            enum Event{i} {{ Joined(User{i}<int>) = 0, Left(uint) = 1 }}\n\
+            channel chat{i} = recv User{i}<int> -> offer | chat{i} | send Event{i} -> recv \"bye\" -> end\n",
+        );
+    }
+    src
+}
+
+fn codegen(c: &mut Criterion) {
+    let src = schema(10_000);
+    c.bench_function("idl2rust 10k", |b| {
+        b.iter(|| idl2rust(&src, &mut Rust::new(&Target::default(), GenMode::All)).unwrap())
+    });
+    c.bench_function("format_idl 10k", |b| {
+        b.iter(|| format_idl(&src, &mut Formatter::new(Style::default())).unwrap())
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = codegen
+}
+criterion_main!(benches);
@@ -1,13 +1,15 @@
-use limit_stream::codegen::formatter::Formatter;
+use limit_stream::codegen::formatter::{Formatter, Style};
 use limit_stream::codegen::Codegen;
 use limit_stream::parser::*;
 
 fn main() {
     let (_, ast) =
         macrod_def("channel sum = recv int -> offer | sum | recv Done -> send int -> end").unwrap();
-    let src = ast.generate(&mut Formatter {
-        tab_size: 4,
-        indent: 0,
-    });
+    let src = ast
+        .generate(&mut Formatter::new(Style {
+            max_width: 40,
+            ..Style::default()
+        }))
+        .unwrap();
     println!("{}", src);
 }
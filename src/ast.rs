@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, PartialEq)]
 pub struct MacrodDef<'a>(pub Macro<'a, Def<'a>>);
 
 pub trait GetName {
@@ -5,14 +6,16 @@ pub trait GetName {
 }
 
 pub trait GetFields {
-    fn get_fields(&self) -> Vec<TypeOrName>;
+    fn get_fields(&self) -> Vec<TypeOrName<'_>>;
 }
 
 /// ```pest
 /// defs = {
 ///   session_def |
 ///   struct_def |
-///   enum_def
+///   enum_def |
+///   const_def |
+///   type_def
 /// }
 /// ```
 #[derive(Debug, Clone, PartialEq)]
@@ -20,6 +23,8 @@ pub enum Def<'a> {
     SessionDef(SessionDef<'a>),
     StructDef(StructDef<'a>),
     EnumDef(EnumDef<'a>),
+    ConstDef(ConstDef<'a>),
+    TypeDef(TypeDef<'a>),
 }
 
 impl<'a> From<Def<'a>> for Type<'a> {
@@ -28,6 +33,8 @@ impl<'a> From<Def<'a>> for Type<'a> {
             Def::SessionDef(s) => Type::SessionType(*s.session.body),
             Def::StructDef(s) => Type::Struct(s),
             Def::EnumDef(e) => Type::Enum(e),
+            Def::ConstDef(c) => Type::Constant(c.value),
+            Def::TypeDef(t) => Type::TypeDef(t),
         }
     }
 }
@@ -38,16 +45,20 @@ impl<'a> GetName for Def<'a> {
             Def::SessionDef(d) => d.get_name(),
             Def::StructDef(d) => d.get_name(),
             Def::EnumDef(d) => d.get_name(),
+            Def::ConstDef(d) => d.get_name(),
+            Def::TypeDef(d) => d.get_name(),
         }
     }
 }
 
 impl<'a> GetFields for Def<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         match self {
             Def::SessionDef(d) => d.get_fields(),
             Def::StructDef(d) => d.get_fields(),
             Def::EnumDef(d) => d.get_fields(),
+            Def::ConstDef(d) => d.get_fields(),
+            Def::TypeDef(d) => d.get_fields(),
         }
     }
 }
@@ -55,12 +66,13 @@ impl<'a> GetFields for Def<'a> {
 /// ```pest
 /// session_def = {
 ///   anotation ~
-///   "channel" ~ name ~ "=" ~ session_type
+///   "channel" ~ name ~ generics? ~ "=" ~ session_type
 /// }
 /// ```
 #[derive(Debug, Clone, PartialEq)]
 pub struct SessionDef<'a> {
     pub name: &'a str,
+    pub generics: Vec<&'a str>,
     pub session: Macro<'a, SessionType<'a>>,
 }
 
@@ -71,7 +83,7 @@ impl<'a> GetName for SessionDef<'a> {
 }
 
 impl<'a> GetFields for SessionDef<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         self.session.get_fields()
     }
 }
@@ -87,7 +99,7 @@ impl<'a> GetFields for SessionDef<'a> {
 /// construct_session_type = {
 ///   "->" ~ session_type
 /// }
-
+///
 /// session_kind =
 ///  { "recv"
 ///  | "send"
@@ -97,15 +109,60 @@ impl<'a> GetFields for SessionDef<'a> {
 pub struct SessionType<'a>(pub Vec<Macro<'a, Session<'a>>>);
 
 impl<'a> GetFields for SessionType<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         self.0.iter().flat_map(GetFields::get_fields).collect()
     }
 }
 
+impl<'a> SessionType<'a> {
+    /// the session seen from the other endpoint, `send` and `recv` swap,
+    /// so do `offer` and `choose`
+    ///
+    /// Named sessions in a union are left as they are, each side reads
+    /// them from its own endpoint. Delegated sessions keep their type.
+    pub fn dual(&self) -> SessionType<'a> {
+        SessionType(
+            self.0
+                .iter()
+                .map(|m| Macro {
+                    appends: m.appends.clone(),
+                    body: Box::new(m.body.dual()),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl<'a> Session<'a> {
+    pub fn dual(&self) -> Session<'a> {
+        let dual = |u: &SessionUnion<'a>| {
+            SessionUnion(
+                u.0.iter()
+                    .map(|s| match s {
+                        SessionOrName::Session(s) => SessionOrName::Session(Box::new(s.dual())),
+                        s => s.clone(),
+                    })
+                    .collect(),
+            )
+        };
+        match self {
+            Session::Recv(t) => Session::Send(t.clone()),
+            Session::Send(t) => Session::Recv(t.clone()),
+            Session::Offer(u) => Session::Choose(dual(u)),
+            Session::Choose(u) => Session::Offer(dual(u)),
+            Session::Endpoint => Session::Endpoint,
+        }
+    }
+}
+
 /// ```pest
+/// generics = {
+///   "<" ~ name ~ ("," ~ name)* ~ ","? ~ ">"
+/// }
+///
 /// struct_def = {
 ///   anotation ~
-///   "struct" ~ name ~ "{" ~
+///   "struct" ~ name ~ generics? ~ "{" ~
 ///     (struct_item ~ ("," ~ struct_item) ~ ","?)?
 ///   ~ "}"
 /// }
@@ -113,6 +170,7 @@ impl<'a> GetFields for SessionType<'a> {
 #[derive(Debug, Clone, PartialEq)]
 pub struct StructDef<'a> {
     pub name: &'a str,
+    pub generics: Vec<&'a str>,
     pub items: Vec<Macro<'a, StructItem<'a>>>,
 }
 
@@ -123,32 +181,43 @@ impl<'a> GetName for StructDef<'a> {
 }
 
 impl<'a> GetFields for StructDef<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         self.items.iter().flat_map(GetFields::get_fields).collect()
     }
 }
 
 /// struct_item = {
-///   anotation ~ name ~ ":" ~ type_or_name ~ ("=" ~ int_lit)?
+///   anotation ~ name ~ "?"? ~ ":" ~ type_or_name ~
+///   ("=" ~ (int_lit ~ default_value? | default_value))?
 /// }
+///
+/// default_value = { "default" ~ constant }
+///
+/// `name?: T` is sugar for `name: option<T>`.
 #[derive(Debug, Clone, PartialEq)]
-pub struct StructItem<'a>(pub &'a str, pub TypeOrName<'a>, pub Option<u64>);
+pub struct StructItem<'a>(
+    pub &'a str,
+    pub TypeOrName<'a>,
+    pub Option<u64>,
+    pub Option<Constant>,
+);
 
 impl<'a> GetFields for StructItem<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         vec![self.1.clone()]
     }
 }
 
 /// enum_def = {
 ///   anotation ~
-///   "enum" ~ name ~ "{" ~
+///   "enum" ~ name ~ generics? ~ "{" ~
 ///   (enum_item ~ ("," ~ enum_item) ~ ","?)?
 ///    ~ "}"
 /// }
 #[derive(Debug, Clone, PartialEq)]
 pub struct EnumDef<'a> {
     pub name: &'a str,
+    pub generics: Vec<&'a str>,
     pub items: Vec<Macro<'a, EnumItem<'a>>>,
 }
 
@@ -159,7 +228,7 @@ impl<'a> GetName for EnumDef<'a> {
 }
 
 impl<'a> GetFields for EnumDef<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         self.items.iter().flat_map(GetFields::get_fields).collect()
     }
 }
@@ -171,32 +240,84 @@ impl<'a> GetFields for EnumDef<'a> {
 pub struct EnumItem<'a>(pub &'a str, pub TypeOrName<'a>, pub Option<u64>);
 
 impl<'a> GetFields for EnumItem<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         vec![self.1.clone()]
     }
 }
 
 /// ```pest
-/// type_or_name = { _type | name }
+/// const_def = {
+///   anotation ~
+///   "const" ~ name ~ ":" ~ type_or_name ~ "=" ~ constant ~ ";"
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstDef<'a> {
+    pub name: &'a str,
+    pub ty: TypeOrName<'a>,
+    pub value: Constant,
+}
+
+impl<'a> GetName for ConstDef<'a> {
+    fn get_name(&self) -> &str {
+        self.name
+    }
+}
+
+impl<'a> GetFields for ConstDef<'a> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
+        vec![self.ty.clone()]
+    }
+}
+
+/// ```pest
+/// type_def = {
+///   anotation ~
+///   "type" ~ name ~ generics? ~ "=" ~ type_or_name ~ ";"
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDef<'a> {
+    pub name: &'a str,
+    pub generics: Vec<&'a str>,
+    pub ty: TypeOrName<'a>,
+}
+
+impl<'a> GetName for TypeDef<'a> {
+    fn get_name(&self) -> &str {
+        self.name
+    }
+}
+
+impl<'a> GetFields for TypeDef<'a> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
+        vec![self.ty.clone()]
+    }
+}
+
+/// ```pest
+/// type_or_name = { _type | generic | name }
 ///
+/// generic = { name ~ "<" ~ type_or_name ~ ("," ~ type_or_name)* ~ ","? ~ ">" }
 /// ```
 #[derive(Debug, Clone, PartialEq)]
 pub enum TypeOrName<'a> {
     Name(&'a str),
+    Generic(&'a str, Vec<TypeOrName<'a>>),
     Type(Box<Type<'a>>),
 }
 
-impl<'a> GetName for TypeOrName<'a> {
-    fn get_name(&self) -> &str {
+impl<'a> TypeOrName<'a> {
+    /// `None` for sessions, containers and constants, which have no name
+    pub fn name(&self) -> Option<&str> {
         match self {
-            TypeOrName::Name(s) => s,
+            TypeOrName::Name(s) | TypeOrName::Generic(s, _) => Some(s),
             TypeOrName::Type(t) => match t.as_ref() {
-                Type::SessionType(_) => unreachable!(),
-                Type::Struct(s) => s.get_name(),
-                Type::Enum(e) => e.get_name(),
-                Type::ContainerType(_) => todo!(),
-                Type::SimpleType(t) => t.get_name(),
-                Type::Constant(_) => unreachable!(),
+                Type::Struct(s) => Some(s.get_name()),
+                Type::Enum(e) => Some(e.get_name()),
+                Type::TypeDef(t) => Some(t.get_name()),
+                Type::SimpleType(t) => Some(t.get_name()),
+                Type::SessionType(_) | Type::ContainerType(_) | Type::Constant(_) => None,
             },
         }
     }
@@ -207,28 +328,31 @@ pub enum Type<'a> {
     SessionType(SessionType<'a>),
     Struct(StructDef<'a>),
     Enum(EnumDef<'a>),
-    ContainerType(ContainerType<'a>), // todo
+    TypeDef(TypeDef<'a>),
+    ContainerType(ContainerType<'a>),
     SimpleType(SimpleType),
     Constant(Constant),
 }
 
 /// ```pest
-/// session_or_name = { session | name }
+/// session_or_name = { session | generic | name }
 ///
 /// ```
 #[derive(Debug, Clone, PartialEq)]
 pub enum SessionOrName<'a> {
     Name(&'a str),
+    Generic(&'a str, Vec<TypeOrName<'a>>),
     Session(Box<SessionType<'a>>),
 }
 
 impl<'a> GetFields for SessionOrName<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         match self {
             SessionOrName::Name(_) => {
                 // FIXME
                 vec![]
             }
+            SessionOrName::Generic(_, args) => args.clone(),
             SessionOrName::Session(s) => s.get_fields(),
         }
     }
@@ -245,7 +369,7 @@ pub enum Session<'a> {
 }
 
 impl<'a> GetFields for Session<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         match self {
             Session::Recv(r) | Session::Send(r) => vec![r.clone()],
             Session::Offer(u) | Session::Choose(u) => u.get_fields(),
@@ -264,15 +388,32 @@ impl<'a> GetFields for Session<'a> {
 pub struct SessionUnion<'a>(pub Vec<SessionOrName<'a>>);
 
 impl<'a> GetFields for SessionUnion<'a> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
-        todo!()
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
+        self.0.iter().flat_map(GetFields::get_fields).collect()
     }
 }
 
+/// ```pest
+/// container_type =
+///  { "[" ~ type_or_name ~ (";" ~ array_length)? ~ "]"
+///  | "dict" ~ "<" ~ simple_type ~ "," ~ type_or_name ~ ">"
+///  | "option" ~ "<" ~ type_or_name ~ ">"
+///  }
+/// ```
 #[derive(Debug, Clone, PartialEq)]
 pub enum ContainerType<'a> {
-    Array(Box<Type<'a>>, Option<usize>),
-    Dict(SimpleType, Box<Type<'a>>),
+    Array(Box<TypeOrName<'a>>, Option<ArrayLength<'a>>),
+    Dict(SimpleType, Box<TypeOrName<'a>>),
+    Option(Box<TypeOrName<'a>>),
+}
+
+/// ```pest
+/// array_length = { uint_lit | name }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArrayLength<'a> {
+    Literal(usize),
+    Const(&'a str),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -313,8 +454,17 @@ pub struct Macro<'a, T> {
     pub body: Box<T>,
 }
 
+impl<'a, T> Macro<'a, T> {
+    pub fn annotations(&self) -> impl Iterator<Item = &Annotation<'a>> {
+        self.appends.iter().filter_map(|a| match a {
+            Append::Annotation(a) => Some(a),
+            _ => None,
+        })
+    }
+}
+
 impl<'a, T: GetFields> GetFields for Macro<'a, T> {
-    fn get_fields(&self) -> Vec<TypeOrName> {
+    fn get_fields(&self) -> Vec<TypeOrName<'_>> {
         self.body.get_fields()
     }
 }
@@ -326,5 +476,42 @@ pub enum Append<'a> {
     Annotation(Annotation<'a>),
 }
 
+/// ```pest
+/// annotation = { "#[" ~ name ~ ("=" ~ annotation_value)? ~ "]" }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation<'a>(pub &'a str, pub AnnotationValue<'a>);
+
+/// ```pest
+/// annotation_value = { constant | name }
+/// ```
 #[derive(Debug, Clone, PartialEq)]
-pub struct Annotation<'a>(pub &'a str, pub Constant);
+pub enum AnnotationValue<'a> {
+    Constant(Constant),
+    Const(&'a str),
+}
+
+/// refinement annotations on struct items
+///
+/// ```pest
+/// constraint =
+///  { "#[" ~ "range" ~ "=" ~ "\"" ~ constant? ~ (".." | "..=") ~ constant? ~ "\"" ~ "]"
+///  | "#[" ~ ("min_len" | "max_len") ~ "=" ~ uint_lit ~ "]"
+///  | "#[" ~ "pattern" ~ "=" ~ string_lit ~ "]"
+///  }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    Range {
+        start: Option<Constant>,
+        end: Option<Constant>,
+        inclusive: bool,
+    },
+    MinLen(u64),
+    MaxLen(u64),
+    Pattern(String),
+}
+
+impl Constraint {
+    pub const NAMES: &'static [&'static str] = &["range", "min_len", "max_len", "pattern"];
+}
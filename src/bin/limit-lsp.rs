@@ -0,0 +1,8 @@
+use lsp_server::Connection;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    limit_stream::lsp::run(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
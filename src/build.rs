@@ -0,0 +1,213 @@
+//! Code generation over IDL roots
+//!
+//! Maps every IDL file of the inputs to its output, next to it or mirrored
+//! under an output directory, and reports errors per file instead of
+//! stopping at the first one.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::codegen::idl2rust;
+use crate::codegen::rust::Rust;
+use crate::diagnostic::Diagnostic;
+use crate::sources::{Source, Sources};
+
+#[derive(Debug, Clone)]
+pub struct Build {
+    pub rust: Rust,
+    /// IDL files or directories
+    pub inputs: Vec<PathBuf>,
+    /// the output directory, or the output file of a single file input
+    pub out: Option<PathBuf>,
+    pub sources: Sources,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Generated(PathBuf),
+    /// the source was deleted, so was its output
+    Removed(PathBuf),
+    Failed(Diagnostic),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub source: PathBuf,
+    pub outcome: Outcome,
+}
+
+impl Report {
+    pub fn is_err(&self) -> bool {
+        matches!(self.outcome, Outcome::Failed(_))
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            Outcome::Generated(out) => {
+                write!(
+                    f,
+                    "generated {} from {}",
+                    out.display(),
+                    self.source.display()
+                )
+            }
+            Outcome::Removed(out) => write!(f, "removed {}", out.display()),
+            Outcome::Failed(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+impl Build {
+    /// generates every IDL file of the inputs
+    pub fn all(&self) -> Vec<Report> {
+        let mut reports = vec![];
+        for input in self.inputs.iter() {
+            match self.sources.files(input) {
+                Ok(files) => reports.extend(
+                    files
+                        .iter()
+                        .map(|source| self.generate(&source.path, &self.output(input, source))),
+                ),
+                Err(e) => reports.push(Report {
+                    source: input.clone(),
+                    outcome: Outcome::Failed(Diagnostic::from(e).in_file(input)),
+                }),
+            }
+        }
+        reports
+    }
+
+    /// regenerates the outputs affected by changes to `paths`, or removes
+    /// them when their source was deleted
+    ///
+    /// IDL files don't import each other, so a file only affects its own
+    /// output.
+    pub fn update<'p>(&self, paths: impl IntoIterator<Item = &'p Path>) -> Vec<Report> {
+        let mut affected = BTreeMap::new();
+        for path in paths {
+            // watchers report absolute paths
+            let path = absolute(path);
+            for input in self.inputs.iter() {
+                let input = absolute(input);
+                for source in self.affected(&input, &path) {
+                    let out = self.output(&input, &source);
+                    affected.insert(source.path, out);
+                }
+            }
+        }
+        affected
+            .into_iter()
+            .map(|(source, out)| {
+                if source.exists() {
+                    self.generate(&source, &out)
+                } else {
+                    remove(&source, &out)
+                }
+            })
+            .collect()
+    }
+
+    /// the IDL files of `input` at or under the changed `path`
+    fn affected(&self, input: &Path, path: &Path) -> Vec<Source> {
+        if path == input {
+            if path.is_dir() {
+                return self.sources.files(input).unwrap_or_default();
+            }
+            let relative = path.file_name().map(PathBuf::from).unwrap_or_default();
+            return vec![Source {
+                path: path.to_path_buf(),
+                relative,
+            }];
+        }
+        let Ok(relative) = path.strip_prefix(input) else {
+            return vec![];
+        };
+        // skipped by `Sources::files` too
+        if relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+        {
+            return vec![];
+        }
+        if path.is_dir() {
+            // a directory moved in
+            return self
+                .sources
+                .files(path)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|source| Source {
+                    relative: relative.join(&source.relative),
+                    path: source.path,
+                })
+                .filter(|source| self.sources.matches(&source.relative))
+                .collect();
+        }
+        if !self.sources.matches(relative) {
+            return vec![];
+        }
+        vec![Source {
+            path: path.to_path_buf(),
+            relative: relative.to_path_buf(),
+        }]
+    }
+
+    /// `.rs` next to the source, or mirroring the input tree in `out`
+    pub fn output(&self, input: &Path, source: &Source) -> PathBuf {
+        match &self.out {
+            // `out` is the output file itself when it is a `.rs`
+            Some(out) if source.path == input && out.extension().is_some_and(|e| e == "rs") => {
+                out.clone()
+            }
+            Some(out) => out.join(&source.relative).with_extension("rs"),
+            None => source.path.with_extension("rs"),
+        }
+    }
+
+    fn generate(&self, source: &Path, out: &Path) -> Report {
+        Report {
+            source: source.to_path_buf(),
+            outcome: match generate_file(&self.rust, source, out) {
+                Ok(()) => Outcome::Generated(out.to_path_buf()),
+                Err(d) => Outcome::Failed(d.in_file(source)),
+            },
+        }
+    }
+}
+
+fn generate_file(rs: &Rust, source: &Path, out: &Path) -> Result<(), Diagnostic> {
+    let src = fs::read_to_string(source)?;
+    // anonymous types are numbered per file
+    let mut rs = Rust {
+        enum_id: Rc::new(Cell::new(0)),
+        codegen_regester: Rc::new(RefCell::new(vec![])),
+        ..rs.clone()
+    };
+    let code = idl2rust(&src, &mut rs)?;
+    if let Some(dir) = out.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(fs::write(out, code)?)
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn remove(source: &Path, out: &Path) -> Report {
+    let outcome = match fs::remove_file(out) {
+        Ok(()) => Outcome::Removed(out.to_path_buf()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Outcome::Removed(out.to_path_buf()),
+        Err(e) => Outcome::Failed(Diagnostic::from(e).in_file(out)),
+    };
+    Report {
+        source: source.to_path_buf(),
+        outcome,
+    }
+}
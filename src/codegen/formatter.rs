@@ -1,20 +1,52 @@
+use serde::Deserialize;
+
 use crate::ast::{
-    Annotation, Append, Constant, Def, EnumDef, EnumItem, Macro, MacrodDef, Session, SessionDef,
-    SessionOrName, SessionType, SessionUnion, SimpleType, StructDef, StructItem, Type, TypeOrName,
+    Annotation, AnnotationValue, Append, ArrayLength, ConstDef, Constant, ContainerType, Def,
+    EnumDef, EnumItem, Macro, MacrodDef, Session, SessionDef, SessionOrName, SessionType,
+    SessionUnion, SimpleType, StructDef, StructItem, Type, TypeDef, TypeOrName,
 };
 
-use super::Codegen;
+use super::pretty::{concat, group, join, nest, render, render_to, text, Doc};
+use super::writer::Writer;
+use super::{Codegen, CodegenError};
+
+/// the `[format]` table of `limit.toml`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Style {
+    pub tab_size: usize,
+    /// sessions which don't fit are broken at each `->` and `|`
+    pub max_width: usize,
+    pub trailing_comma: bool,
+    /// pad items so their types and `=` line up
+    pub align_fields: bool,
+    /// order struct items by tag, untagged ones last
+    pub sort_fields: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            tab_size: 4,
+            max_width: 100,
+            trailing_comma: true,
+            align_fields: false,
+            sort_fields: false,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Formatter {
-    // ...
     // The current indentation level.
-    pub tab_size: usize,
     pub indent: usize,
-    // ...
+    pub style: Style,
 }
 
 impl Formatter {
+    pub fn new(style: Style) -> Self {
+        Self { indent: 0, style }
+    }
     pub fn append_indent(&self) -> Self {
         Self {
             indent: self.indent + 1,
@@ -22,225 +54,418 @@ impl Formatter {
         }
     }
     pub fn get_tab(&self) -> String {
-        // TODO
-        " ".repeat(self.tab_size).repeat(self.indent)
+        " ".repeat(self.style.tab_size).repeat(self.indent)
+    }
+}
+
+/// layout of a node, before it is fitted to `Style::max_width`
+pub trait Pretty {
+    fn doc(&self, f: &Formatter) -> Doc;
+}
+
+impl<T: Pretty> Codegen<Formatter> for T {
+    fn write(&self, generator: &mut Formatter, out: &mut Writer) -> Result<(), CodegenError> {
+        Ok(render_to(
+            &self.doc(generator),
+            generator.style.max_width,
+            generator.indent * generator.style.tab_size,
+            out,
+        )?)
+    }
+}
+
+/// single line rendering, for columns that get aligned
+fn flat(doc: &Doc) -> String {
+    render(doc, usize::MAX, 0)
+}
+
+fn generics(generics: &[&str]) -> String {
+    if generics.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", generics.join(", "))
+    }
+}
+
+fn type_args(args: &[TypeOrName], f: &Formatter) -> Doc {
+    concat([
+        text("<"),
+        join(args.iter().map(|a| a.doc(f)), text(", ")),
+        text(">"),
+    ])
+}
+
+fn string_lit(s: &str) -> String {
+    let mut res = String::from('"');
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c => res.push(c),
+        }
     }
+    res.push('"');
+    res
 }
 
-impl<'a> Codegen<Formatter> for MacrodDef<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        self.0.generate(generator)
+/// `{ items }` one item per line
+fn block(head: String, items: Vec<Doc>, f: &Formatter) -> Doc {
+    if items.is_empty() {
+        return text(format!("{} {{}}", head));
     }
+    let last = items.len() - 1;
+    let items = items.into_iter().enumerate().map(|(i, item)| {
+        let comma = if i != last || f.style.trailing_comma {
+            text(",")
+        } else {
+            Doc::Nil
+        };
+        concat([Doc::HardLine, item, comma])
+    });
+    concat([
+        text(format!("{} {{", head)),
+        nest(f.style.tab_size, concat(items)),
+        Doc::HardLine,
+        text("}"),
+    ])
 }
 
-impl<'a> Codegen<Formatter> for Def<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
+/// `item` with its comments and annotations, and `body` in place of its own layout
+fn with_appends<T>(item: &Macro<T>, body: Doc, f: &Formatter) -> Doc {
+    concat(
+        item.appends
+            .iter()
+            .map(|a| concat([a.doc(f), Doc::HardLine]))
+            .chain([body]),
+    )
+}
+
+/// `name: ty = tag default value`, with the suffix split off for alignment
+fn struct_item_parts(item: &StructItem, f: &Formatter) -> (String, Doc, String) {
+    let suffix = match (item.2, &item.3) {
+        (Some(s), Some(d)) => format!(" = {} default {}", s, flat(&d.doc(f))),
+        (Some(s), None) => format!(" = {}", s),
+        (None, Some(d)) => format!(" = default {}", flat(&d.doc(f))),
+        (None, None) => "".to_string(),
+    };
+    (format!("{}:", item.0), item.1.doc(f), suffix)
+}
+
+fn pad(s: &str, width: usize) -> String {
+    format!("{:<width$}", s, width = width)
+}
+
+impl<'a> Pretty for MacrodDef<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        self.0.doc(f)
+    }
+}
+
+impl<'a> Pretty for Def<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
         match self {
-            Def::SessionDef(d) => d.generate(generator),
-            Def::StructDef(d) => d.generate(generator),
-            Def::EnumDef(d) => d.generate(generator),
+            Def::SessionDef(d) => d.doc(f),
+            Def::StructDef(d) => d.doc(f),
+            Def::EnumDef(d) => d.doc(f),
+            Def::ConstDef(d) => d.doc(f),
+            Def::TypeDef(d) => d.doc(f),
         }
     }
 }
 
-impl<'a> Codegen<Formatter> for SessionDef<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        let session = self.session.generate(&mut generator.append_indent());
-        format!(
-            "{}channel {} = \n{}{}",
-            generator.get_tab(),
-            self.name,
-            generator.get_tab(),
-            session
-        )
+impl<'a> Pretty for ConstDef<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        concat([
+            text(format!("const {}: ", self.name)),
+            self.ty.doc(f),
+            text(" = "),
+            self.value.doc(f),
+            text(";"),
+        ])
     }
 }
 
-impl<'a> Codegen<Formatter> for StructDef<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        let items = self
-            .items
+impl<'a> Pretty for TypeDef<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        group(concat([
+            text(format!("type {}{} =", self.name, generics(&self.generics))),
+            nest(f.style.tab_size, concat([Doc::Line, self.ty.doc(f)])),
+            text(";"),
+        ]))
+    }
+}
+
+impl<'a> Pretty for SessionDef<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        group(concat([
+            text(format!(
+                "channel {}{} =",
+                self.name,
+                generics(&self.generics)
+            )),
+            nest(f.style.tab_size, concat([Doc::Line, self.session.doc(f)])),
+        ]))
+    }
+}
+
+impl<'a> Pretty for StructDef<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        let mut items = self.items.iter().collect::<Vec<_>>();
+        if f.style.sort_fields {
+            items.sort_by_key(|i| (i.body.2.is_none(), i.body.2));
+        }
+        let parts = items
+            .iter()
+            .map(|i| struct_item_parts(&i.body, f))
+            .collect::<Vec<_>>();
+        let bodies = if f.style.align_fields {
+            let name_width = parts.iter().map(|p| p.0.len()).max().unwrap_or(0);
+            let types = parts.iter().map(|p| flat(&p.1)).collect::<Vec<_>>();
+            // only types followed by `=` are padded, so lines don't end in spaces
+            let type_width = parts
+                .iter()
+                .zip(&types)
+                .filter(|(p, _)| !p.2.is_empty())
+                .map(|(_, t)| t.chars().count())
+                .max()
+                .unwrap_or(0);
+            parts
+                .into_iter()
+                .zip(types)
+                .map(|((name, _, suffix), ty)| {
+                    let ty = if suffix.is_empty() {
+                        ty
+                    } else {
+                        pad(&ty, type_width)
+                    };
+                    text(format!("{} {}{}", pad(&name, name_width), ty, suffix))
+                })
+                .collect::<Vec<_>>()
+        } else {
+            parts
+                .into_iter()
+                .map(|(name, ty, suffix)| concat([text(name + " "), ty, text(suffix)]))
+                .collect()
+        };
+        let items = items
             .iter()
-            .map(|i| format!("{},\n", i.generate(&mut generator.append_indent())))
-            .collect::<String>();
-        format!(
-            "{}struct {} {{\n{}{}}}",
-            generator.get_tab(),
-            self.name,
+            .zip(bodies)
+            .map(|(i, body)| with_appends(i, body, f))
+            .collect();
+        block(
+            format!("struct {}{}", self.name, generics(&self.generics)),
             items,
-            generator.get_tab()
+            f,
         )
     }
 }
 
-impl<'a> Codegen<Formatter> for EnumDef<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
+impl<'a> Pretty for EnumDef<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        let variants = self
+            .items
+            .iter()
+            .map(|i| concat([text(format!("{}(", i.body.0)), i.body.1.doc(f), text(")")]))
+            .collect::<Vec<_>>();
+        let width = if f.style.align_fields {
+            variants
+                .iter()
+                .zip(&self.items)
+                .filter(|(_, i)| i.body.2.is_some())
+                .map(|(v, _)| flat(v).chars().count())
+                .max()
+                .unwrap_or(0)
+        } else {
+            0
+        };
         let items = self
             .items
             .iter()
-            .map(|i| format!("{},\n", i.generate(&mut generator.append_indent())))
-            .collect::<String>();
-        format!(
-            "{}enum {} {{\n{}{}}}",
-            generator.get_tab(),
-            self.name,
+            .zip(variants)
+            .map(|(i, variant)| {
+                let body = match i.body.2 {
+                    Some(tag) if f.style.align_fields => {
+                        text(format!("{} = {}", pad(&flat(&variant), width), tag))
+                    }
+                    Some(tag) => concat([variant, text(format!(" = {}", tag))]),
+                    None => variant,
+                };
+                with_appends(i, body, f)
+            })
+            .collect();
+        block(
+            format!("enum {}{}", self.name, generics(&self.generics)),
             items,
-            generator.get_tab()
+            f,
         )
     }
 }
 
-impl<'a> Codegen<Formatter> for StructItem<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        format!(
-            "{}{}: {}{}",
-            generator.get_tab(),
-            self.0,
-            self.1.generate(generator),
-            if let Some(s) = self.2 {
-                format!(" = {}", s)
-            } else {
-                "".to_string()
-            }
-        )
+impl<'a> Pretty for StructItem<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        let (name, ty, suffix) = struct_item_parts(self, f);
+        concat([text(name + " "), ty, text(suffix)])
     }
 }
 
-impl<'a> Codegen<Formatter> for EnumItem<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        format!(
-            "{}{}({}){}",
-            generator.get_tab(),
-            self.0,
-            self.1.generate(generator),
-            if let Some(s) = self.2 {
-                format!(" = {}", s)
-            } else {
-                "".to_string()
-            }
-        )
+impl<'a> Pretty for EnumItem<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        concat([
+            text(format!("{}(", self.0)),
+            self.1.doc(f),
+            text(")"),
+            match self.2 {
+                Some(s) => text(format!(" = {}", s)),
+                None => Doc::Nil,
+            },
+        ])
     }
 }
 
-impl<'a> Codegen<Formatter> for TypeOrName<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
+impl<'a> Pretty for TypeOrName<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
         match self {
-            TypeOrName::Name(name) => name.to_string(),
-            TypeOrName::Type(ty) => ty.generate(generator),
+            TypeOrName::Name(name) => text(*name),
+            TypeOrName::Generic(name, args) => concat([text(*name), type_args(args, f)]),
+            TypeOrName::Type(ty) => ty.doc(f),
         }
     }
 }
 
-impl<'a> Codegen<Formatter> for Type<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
+impl<'a> Pretty for Type<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
         match self {
-            Type::SessionType(session) => session.generate(generator),
-            Type::ContainerType(_) => todo!(),
-            Type::SimpleType(st) => st.generate(generator),
-            Type::Constant(c) => c.generate(generator),
-            Type::Struct(_s) => unimplemented!(),
-            Type::Enum(_e) => unimplemented!(),
+            Type::SessionType(session) => group(session.doc(f)),
+            Type::ContainerType(container) => container.doc(f),
+            Type::SimpleType(st) => st.doc(f),
+            Type::Constant(c) => c.doc(f),
+            // inlined definitions are referred to by name
+            Type::Struct(s) => text(s.name),
+            Type::Enum(e) => text(e.name),
+            Type::TypeDef(t) => text(t.name),
         }
     }
 }
 
-impl<'a> Codegen<Formatter> for SessionUnion<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        generator.indent += 1;
-        let mut res = String::new();
-        for s in self.0.iter() {
-            res += &format!(
-                "\n{}| {}",
-                generator.get_tab(),
-                &s.generate(generator)[generator.indent * generator.tab_size..]
-                    .replace('\n', "\n  ")
-            );
+impl<'a> Pretty for ContainerType<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        match self {
+            ContainerType::Array(ty, None) => concat([text("["), ty.doc(f), text("]")]),
+            ContainerType::Array(ty, Some(len)) => {
+                concat([text("["), ty.doc(f), text("; "), len.doc(f), text("]")])
+            }
+            ContainerType::Dict(k, v) => {
+                concat([text("dict<"), k.doc(f), text(", "), v.doc(f), text(">")])
+            }
+            ContainerType::Option(ty) => concat([text("option<"), ty.doc(f), text(">")]),
         }
-        generator.indent -= 1;
-        res
     }
 }
 
-impl<'a> Codegen<Formatter> for SessionOrName<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
+impl<'a> Pretty for ArrayLength<'a> {
+    fn doc(&self, _f: &Formatter) -> Doc {
         match self {
-            SessionOrName::Name(name) => format!("{}{}", generator.get_tab(), name),
-            SessionOrName::Session(session) => session.generate(generator),
+            ArrayLength::Literal(len) => text(len.to_string()),
+            ArrayLength::Const(name) => text(*name),
         }
     }
 }
 
-impl<'a> Codegen<Formatter> for SessionType<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        self.0
+impl<'a> Pretty for SessionUnion<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        // continuation lines of a branch line up after its `| `
+        let branches = self
+            .0
             .iter()
-            .map(|m| format!("{}{}", generator.get_tab(), m.generate(generator)))
-            .collect::<Vec<String>>()
-            .join(" ->\n") // FIXME
+            .map(|s| concat([Doc::Line, text("| "), nest(2, s.doc(f))]));
+        nest(f.style.tab_size, concat(branches))
     }
 }
 
-impl<'a> Codegen<Formatter> for Session<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
+impl<'a> Pretty for SessionOrName<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
         match self {
-            Session::Recv(ty) => format!("recv {}", ty.generate(generator)),
-            Session::Send(ty) => format!("send {}", ty.generate(generator)),
-            Session::Offer(union) => format!("offer {}", union.generate(generator)),
-            Session::Choose(union) => format!("choose {}", union.generate(generator)),
-            Session::Endpoint => "end".to_string(),
+            SessionOrName::Name(name) => text(*name),
+            SessionOrName::Generic(name, args) => concat([text(*name), type_args(args, f)]),
+            SessionOrName::Session(session) => group(session.doc(f)),
         }
     }
 }
 
-impl Codegen<Formatter> for SimpleType {
-    fn generate(&self, _generator: &mut Formatter) -> String {
+impl<'a> Pretty for SessionType<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        join(
+            self.0.iter().map(|m| m.doc(f)),
+            concat([text(" ->"), Doc::Line]),
+        )
+    }
+}
+
+impl<'a> Pretty for Session<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
         match self {
+            Session::Recv(ty) => concat([text("recv "), ty.doc(f)]),
+            Session::Send(ty) => concat([text("send "), ty.doc(f)]),
+            Session::Offer(union) => concat([text("offer"), union.doc(f)]),
+            Session::Choose(union) => concat([text("choose"), union.doc(f)]),
+            Session::Endpoint => text("end"),
+        }
+    }
+}
+
+impl Pretty for SimpleType {
+    fn doc(&self, _f: &Formatter) -> Doc {
+        text(match self {
             SimpleType::Bool => "bool",
             SimpleType::Int => "int",
             SimpleType::Uint => "uint",
             SimpleType::Float => "float",
             SimpleType::Double => "double",
             SimpleType::String => "string",
-        }
-        .to_string()
+        })
     }
 }
 
-impl Codegen<Formatter> for Constant {
-    fn generate(&self, _generator: &mut Formatter) -> String {
-        match self {
-            Constant::String(s) => s.to_string(),
+impl Pretty for Constant {
+    fn doc(&self, _f: &Formatter) -> Doc {
+        text(match self {
+            Constant::String(s) => string_lit(s),
+            // keep the `.` and the sign, otherwise these parse back as ints / uints
+            Constant::Float(f) if f.fract() == 0.0 && f.is_finite() => format!("{}.0", f),
             Constant::Float(f) => f.to_string(),
-            Constant::Int(i) => i.to_string(),
+            Constant::Int(i) => format!("{:+}", i),
             Constant::Uint(u) => u.to_string(),
             Constant::Bool(b) => b.to_string(),
-        }
+        })
     }
 }
 
-impl<'a, T: Codegen<Formatter>> Codegen<Formatter> for Macro<'a, T> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        let append = self
-            .appends
-            .iter()
-            .map(|f| format!("{}{}\n", generator.get_tab(), f.generate(generator)))
-            .collect::<String>();
-        let body = self.body.generate(generator);
-        format!("{}{}", append, body)
+impl<'a, T: Pretty> Pretty for Macro<'a, T> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        with_appends(self, self.body.doc(f), f)
     }
 }
 
-impl<'a> Codegen<Formatter> for Append<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
+impl<'a> Pretty for Append<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
         match self {
-            Append::LineComment(s) | Append::DocsComment(s) => s.to_string(),
-            Append::Annotation(a) => a.generate(generator),
+            Append::LineComment(s) | Append::DocsComment(s) => {
+                text(s.trim_end_matches(['\n', '\r']))
+            }
+            Append::Annotation(a) => a.doc(f),
         }
     }
 }
 
-impl<'a> Codegen<Formatter> for Annotation<'a> {
-    fn generate(&self, generator: &mut Formatter) -> String {
-        format!("#[{}={}]", self.0, self.1.generate(generator))
+impl<'a> Pretty for Annotation<'a> {
+    fn doc(&self, f: &Formatter) -> Doc {
+        match &self.1 {
+            AnnotationValue::Constant(Constant::Bool(true)) => text(format!("#[{}]", self.0)),
+            AnnotationValue::Constant(c) => text(format!("#[{}={}]", self.0, flat(&c.doc(f)))),
+            AnnotationValue::Const(name) => text(format!("#[{}={}]", self.0, name)),
+        }
     }
 }
@@ -1,8 +1,17 @@
+use std::fmt::{self, Display, Write};
+use std::rc::Rc;
+
+use crate::config::GenMode;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::ir::{lower, Unit};
 use crate::parser::parse;
+use crate::type_checker::{extern_check, inline_cycles, param_edges, toposort};
 
-use self::{formatter::Formatter, rust::Rust};
+use self::{formatter::Formatter, rust::Rust, writer::Writer};
 
 pub mod formatter;
+pub mod pretty;
+pub mod writer;
 
 pub mod rust;
 // pub mod go;
@@ -10,26 +19,86 @@ pub mod rust;
 // pub mod python;
 
 pub trait Codegen<Generator> {
-    fn generate(&self, generator: &mut Generator) -> String;
+    fn write(&self, generator: &mut Generator, out: &mut Writer) -> Result<(), CodegenError>;
+
+    fn generate(&self, generator: &mut Generator) -> Result<String, CodegenError> {
+        let mut code = String::new();
+        self.write(generator, &mut Writer::new(&mut code))?;
+        Ok(code)
+    }
+}
+
+/// a node the generator can't turn into code
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodegenError {
+    pub message: String,
+    /// where the node was written in the IDL file
+    pub span: Option<Span>,
+}
+
+impl CodegenError {
+    pub fn new(message: impl Into<String>, span: Option<Span>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error("codegen-error", self.message.clone()).with_span(self.span)
+    }
+}
+
+impl From<fmt::Error> for CodegenError {
+    fn from(_: fmt::Error) -> Self {
+        Self::new("failed to write the generated code", None)
+    }
+}
+
+impl Display for CodegenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
 
-pub fn format_idl(src: &str, rs: &mut Formatter) -> String {
-    let asts = parse(&src).unwrap();
-    asts.into_iter()
-        .map(|ast| ast.generate(rs))
-        .collect::<Vec<_>>()
-        .join("\n")
+pub fn format_idl(src: &str, rs: &mut Formatter) -> Result<String, Diagnostic> {
+    let asts = parse(src)?;
+    let mut code = String::with_capacity(src.len());
+    let out = &mut Writer::new(&mut code);
+    for ast in asts.iter() {
+        ast.write(rs, out)
+            .and_then(|()| Ok(out.write_char('\n')?))
+            .map_err(|e| e.diagnostic())?;
+    }
+    Ok(code)
 }
 
-pub fn idl2rust(src: &str, rs: &mut Rust) -> String {
-    let asts = parse(&src).unwrap();
-    let code_body = asts
-        .into_iter()
-        .map(|ast| ast.generate(rs))
-        .collect::<Vec<_>>()
-        .join("\n");
-    let mut code = rs.codegen_regester.as_ref().borrow().join("\n");
-    code.push_str("\n");
+pub fn idl2rust(src: &str, rs: &mut Rust) -> Result<String, Diagnostic> {
+    let asts = parse(src)?;
+    let ir = Rc::new(lower(&[Unit::new(src, &asts)]));
+    // code for an ill-typed file wouldn't compile
+    let externs = rs.types.keys().map(String::as_str).collect::<Vec<_>>();
+    extern_check(&ir, &externs).map_err(|e| e.diagnostic(&ir))?;
+    rs.ir = Rc::clone(&ir);
+    rs.cycles = Rc::new(inline_cycles(&ir));
+    rs.params = Rc::new(param_edges(&ir));
+    if rs.gen_mode == GenMode::Mock {
+        return Ok(rs.mock(src));
+    }
+    let mut code_body = String::new();
+    let out = &mut Writer::new(&mut code_body);
+    // definitions come after the ones they use, and otherwise in source order
+    for (i, id) in toposort(&ir).into_iter().enumerate() {
+        let def = ir.def(id);
+        out.write_str(if i == 0 { "" } else { "\n" })
+            .map_err(CodegenError::from)
+            .and_then(|()| def.write(rs, out))
+            .map_err(|e| e.diagnostic())?;
+    }
+    // anonymous types are registered while the definitions are written
+    let mut code = "use limit_stream::runtime::*;\n\n".to_string();
+    code.push_str(&rs.codegen_regester.as_ref().borrow().join("\n"));
+    code.push('\n');
     code.push_str(&code_body);
-    code
+    Ok(code)
 }
@@ -0,0 +1,133 @@
+//! Pretty printing
+//!
+//! A small version of Wadler's "A prettier printer". A `Group` is laid out
+//! on one line when it fits in the line width, otherwise every `Line`
+//! directly inside it becomes a newline.
+
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Doc {
+    Nil,
+    Text(String),
+    /// a space when flat, a newline when broken
+    Line,
+    /// always a newline, the enclosing groups can't be flat
+    HardLine,
+    Nest(usize, Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+pub fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+pub fn nest(indent: usize, doc: Doc) -> Doc {
+    Doc::Nest(indent, Box::new(doc))
+}
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+pub fn concat(docs: impl IntoIterator<Item = Doc>) -> Doc {
+    Doc::Concat(docs.into_iter().collect())
+}
+
+/// `docs` with `sep` between each of them
+pub fn join(docs: impl IntoIterator<Item = Doc>, sep: Doc) -> Doc {
+    let mut res = vec![];
+    for (i, d) in docs.into_iter().enumerate() {
+        if i != 0 {
+            res.push(sep.clone());
+        }
+        res.push(d);
+    }
+    Doc::Concat(res)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// lay out `doc` in `width` columns, starting at column `indent`
+pub fn render(doc: &Doc, width: usize, indent: usize) -> String {
+    let mut out = String::new();
+    render_to(doc, width, indent, &mut out).expect("writing to a string can't fail");
+    out
+}
+
+/// `render` to `out`
+pub fn render_to(doc: &Doc, width: usize, indent: usize, out: &mut dyn Write) -> fmt::Result {
+    let mut col = indent;
+    // indentation is written with the next text, so blank lines stay empty
+    let mut pending = 0;
+    let mut stack = vec![(indent, Mode::Break, doc)];
+    while let Some((i, mode, d)) = stack.pop() {
+        match d {
+            Doc::Nil => {}
+            Doc::Text(s) => {
+                if !s.is_empty() {
+                    for _ in 0..pending {
+                        out.write_char(' ')?;
+                    }
+                    pending = 0;
+                }
+                out.write_str(s)?;
+                col += s.chars().count();
+            }
+            Doc::Line if mode == Mode::Flat => {
+                out.write_char(' ')?;
+                col += 1;
+            }
+            Doc::Line | Doc::HardLine => {
+                out.write_char('\n')?;
+                pending = i;
+                col = i;
+            }
+            Doc::Nest(j, d) => stack.push((i + j, mode, d)),
+            Doc::Concat(ds) => stack.extend(ds.iter().rev().map(|d| (i, mode, d))),
+            Doc::Group(d) if mode == Mode::Flat => stack.push((i, mode, d)),
+            Doc::Group(d) => {
+                let mode = if fits(width as isize - col as isize, d, &stack) {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                stack.push((i, mode, d));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// whether `doc` laid out flat, and what follows it up to the next
+/// newline, take at most `rest` columns
+fn fits(mut rest: isize, doc: &Doc, after: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut after = after.iter().rev();
+    loop {
+        if rest < 0 {
+            return false;
+        }
+        let (mode, d) = match stack.pop() {
+            Some(top) => top,
+            None => match after.next() {
+                Some((_, mode, d)) => (*mode, *d),
+                None => return true,
+            },
+        };
+        match d {
+            Doc::Nil => {}
+            Doc::Text(s) => rest -= s.chars().count() as isize,
+            Doc::Line if mode == Mode::Flat => rest -= 1,
+            Doc::HardLine if mode == Mode::Flat => return false,
+            Doc::Line | Doc::HardLine => return true,
+            Doc::Nest(_, d) | Doc::Group(d) => stack.push((mode, d)),
+            Doc::Concat(ds) => stack.extend(ds.iter().rev().map(|d| (mode, d))),
+        }
+    }
+}
@@ -1,253 +1,912 @@
 use std::{
     cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::{self, Write},
     rc::Rc,
 };
 
-use crate::ast::{
-    Constant, Def, EnumDef, EnumItem, GetName, Macro, MacrodDef, Session, SessionDef,
-    SessionOrName, SessionType, SessionUnion, SimpleType, StructDef, StructItem, Type, TypeOrName,
+use crate::ast::{Constant, Constraint, GetName, SimpleType};
+use crate::ir::{
+    self, wire_order, Annotation, Branch, Def, DefId, DefKind, Field, Ir, Length, Ref, Session,
+    SessionType, Ty, Variant,
 };
 
-use super::Codegen;
+use crate::config::{GenMode, Serialization, Target};
+use crate::type_checker::Edge;
+
+use super::{writer::Writer, Codegen, CodegenError};
 
 #[derive(Debug, Clone)]
 pub struct Rust {
     pub tab_size: usize,
-    pub indent: usize,
     pub enum_id: Rc<Cell<usize>>,
     pub codegen_regester: Rc<RefCell<Vec<String>>>,
+    pub generics: Vec<String>,
+    /// IDL type name to rust type, see `config::Target::types`
+    pub types: Rc<HashMap<String, String>>,
+    pub serialization: Serialization,
+    pub gen_mode: GenMode,
+    /// the definitions being generated, names are looked up in it
+    pub ir: Rc<Ir>,
+    /// see `type_checker::inline_cycles`
+    pub cycles: Rc<Vec<Option<usize>>>,
+    /// see `type_checker::param_edges`
+    pub params: Rc<Vec<Vec<Edge>>>,
+    /// the definition being written
+    pub def: Option<DefId>,
 }
 
 impl Rust {
-    pub fn append_indent(&self) -> Self {
+    pub fn new(target: &Target, gen_mode: GenMode) -> Self {
+        Self {
+            tab_size: target.tab_size,
+            enum_id: Rc::new(Cell::new(0)),
+            codegen_regester: Rc::new(RefCell::new(vec![])),
+            generics: vec![],
+            types: Rc::new(target.types.clone()),
+            serialization: target.serialization,
+            gen_mode,
+            ir: Rc::default(),
+            cycles: Rc::default(),
+            params: Rc::default(),
+            def: None,
+        }
+    }
+
+    fn codecs(&self) -> bool {
+        self.serialization == Serialization::Msgpack
+    }
+
+    pub fn with_generics(&self, generics: &[&str]) -> Self {
         Self {
-            indent: self.indent + 1,
+            generics: generics.iter().map(ToString::to_string).collect(),
             ..self.clone()
         }
     }
-    pub fn get_tab(&self) -> String {
-        // TODO
-        " ".repeat(self.tab_size).repeat(self.indent)
+
+    /// whether `r` refers back to the cycle of the definition being written,
+    /// to a definition before it. every cycle has such a reference, boxing
+    /// only those keeps the others unboxed.
+    fn recursive(&self, r: &Ref) -> bool {
+        let cycle = |id: DefId| self.cycles.get(id.index()).copied().flatten();
+        match (self.def, r.target) {
+            (Some(def), ir::Target::Def(target)) => {
+                target <= def && cycle(def).is_some() && cycle(target) == cycle(def)
+            }
+            _ => false,
+        }
+    }
+
+    /// whether `r` stores its `i`th argument inline
+    fn inline_arg(&self, r: &Ref, i: usize) -> bool {
+        match r.target {
+            ir::Target::Def(id) => self
+                .params
+                .get(id.index())
+                .and_then(|params| params.get(i))
+                .is_some_and(|edge| *edge != Edge::Indirect),
+            _ => false,
+        }
+    }
+
+    /// generic parameters of the current definition which are used in `body`
+    fn used_generics(&self, body: &str) -> String {
+        generics(&self.used_generic_names(body))
+    }
+
+    fn used_generic_names(&self, body: &str) -> Vec<String> {
+        self.generics
+            .iter()
+            .filter(|g| {
+                body.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .any(|word| word == g.as_str())
+            })
+            .cloned()
+            .collect()
     }
 
     fn new_union_id(&self) -> String {
+        format!("E{}", self.new_id())
+    }
+
+    fn new_id(&self) -> usize {
         let id = self.enum_id.as_ref().get();
         self.enum_id.as_ref().set(id + 1);
-        format!("E{}", id)
+        id
     }
 
     fn add_to_register(&self, source: String) {
         self.codegen_regester.as_ref().borrow_mut().push(source);
     }
 
+    /// the arms of an `offer`/`choose` as an enum over the role `R` running
+    /// it, each arm holding the channel for that branch
     pub fn anonymous_union_register(&self, union_body: &[String]) -> String {
-        let items = union_body
-            .iter()
-            // .enumerate()
-            .map(|typename| {
-                format!(
-                    "{}T{}({}),\n",
-                    " ".repeat(self.tab_size),
-                    typename,
-                    typename
-                )
-            })
-            .collect::<String>();
         let name = self.new_union_id();
-        self.add_to_register(format!(
-            "#[rustfmt::skip]\n#[allow(non_camel_case_types)]\npub enum {} {{\n{}}}\n",
-            name, items
-        ));
+        let params = self.used_generic_names(&union_body.join(" "));
+        let arms = union_body
+            .iter()
+            .map(|typename| (format!("T{}", variant_name(typename)), typename))
+            .collect::<Vec<_>>();
+        let mut source = String::new();
+        self.branches(&mut Writer::new(&mut source), &name, &params, &arms)
+            .expect("write to String");
+        self.add_to_register(source);
+        format!("{}{}", name, generics(&params))
+    }
+
+    fn branches(
+        &self,
+        out: &mut Writer,
+        name: &str,
+        params: &[String],
+        arms: &[(String, &String)],
+    ) -> fmt::Result {
+        let tab = self.tab_size;
+        // `name` with `params` and the role
+        let with = |role: &str| {
+            let mut params = params.to_vec();
+            params.push(role.to_string());
+            format!("{}{}", name, generics(&params))
+        };
+        write!(
+            out,
+            "#[rustfmt::skip]\n#[allow(non_camel_case_types)]\npub enum {} {{\n",
+            with("R = ()")
+        )?;
+        out.indented(tab, |out| {
+            for (variant, typename) in arms {
+                writeln!(out, "{}(Chan<{}, R>),", variant, typename)?;
+            }
+            Ok(())
+        })?;
+        out.write_str("}\n")?;
+        let mut bound = params.to_vec();
+        bound.push("R".to_string());
+        writeln!(
+            out,
+            "impl{} Branches<R> for {} {{",
+            generics(&bound),
+            with("R")
+        )?;
+        out.indented(tab, |out| {
+            out.write_str("fn tag(&self) -> u64 {\n")?;
+            out.indented(tab, |out| {
+                out.write_str("match self {\n")?;
+                out.indented(tab, |out| {
+                    for (tag, (variant, _)) in arms.iter().enumerate() {
+                        writeln!(out, "Self::{}(_) => {},", variant, tag)?;
+                    }
+                    Ok(())
+                })?;
+                out.write_str("}\n")
+            })?;
+            out.write_str("}\n")?;
+            out.write_str("fn branch(tag: u64, role: R) -> Option<Self> {\n")?;
+            out.indented(tab, |out| {
+                out.write_str("match tag {\n")?;
+                out.indented(tab, |out| {
+                    for (tag, (variant, _)) in arms.iter().enumerate() {
+                        writeln!(out, "{} => Some(Self::{}(Chan::new(role))),", tag, variant)?;
+                    }
+                    out.write_str("_ => None,\n")
+                })?;
+                out.write_str("}\n")
+            })?;
+            out.write_str("}\n")?;
+            out.write_str("fn into_role(self) -> R {\n")?;
+            out.indented(tab, |out| {
+                out.write_str("match self {\n")?;
+                out.indented(tab, |out| {
+                    for (variant, _) in arms {
+                        writeln!(out, "Self::{}(c) => c.into_role(),", variant)?;
+                    }
+                    Ok(())
+                })?;
+                out.write_str("}\n")
+            })?;
+            out.write_str("}\n")
+        })?;
+        out.write_str("}\n")?;
+        writeln!(out, "impl{} Select for {} {{", generics(&bound), with("R"))?;
+        out.indented(tab, |out| writeln!(out, "type On<Q> = {};", with("Q")))?;
+        out.write_str("}\n")
+    }
+
+    /// zero-sized marker type for a literal payload like `recv 1`,
+    /// which always encodes its constant and only decodes from it
+    pub fn literal_register(&self, c: &Constant) -> String {
+        let (ty, value) = match c {
+            Constant::String(s) => ("String", format!("{:?}.to_string()", s)),
+            Constant::Float(f) => ("Double", format!("{:?}", f)),
+            Constant::Int(i) => ("Int", i.to_string()),
+            Constant::Uint(u) => ("Uint", u.to_string()),
+            Constant::Bool(b) => ("Bool", b.to_string()),
+        };
+        let name = format!("L{}", self.new_id());
+        let tab = " ".repeat(self.tab_size);
+        let mut source = format!(
+            "#[rustfmt::skip]\n#[allow(non_camel_case_types)]\n#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]\npub struct {name};\n\
+            impl Literal for {name} {{\n{tab}type Value = {ty};\n{tab}fn value() -> {ty} {{\n{tab}{tab}{value}\n{tab}}}\n}}\n",
+        );
+        if self.codecs() {
+            source += &format!(
+                "impl Encode for {name} {{\n{tab}fn encode(&self, buf: &mut Vec<u8>) {{\n{tab}{tab}encode_literal::<Self>(buf)\n{tab}}}\n}}\n\
+                impl Decode for {name} {{\n{tab}fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {{\n{tab}{tab}decode_literal(buf)\n{tab}}}\n}}\n",
+            );
+        }
+        self.add_to_register(source);
         name
     }
 
     pub fn anonymous_session_register(&self, session: &str) -> String {
         let name = self.new_union_id();
+        let generics = self.used_generics(session);
         self.add_to_register(format!(
-            "#[rustfmt::skip]\n#[allow(non_camel_case_types)]\npub type {} = {};\n",
-            name, session
+            "#[rustfmt::skip]\n#[allow(non_camel_case_types)]\npub type {}{} = {};\n",
+            name, generics, session
         ));
-        name
+        format!("{}{}", name, generics)
+    }
+
+    /// a program mocking every non-generic channel of `src`, see
+    /// `runtime::mock`
+    pub fn mock(&self, src: &str) -> String {
+        let tab = " ".repeat(self.tab_size);
+        let mut code = "use limit_stream::runtime::mock::{self, Io, Options};\n".to_string();
+        code.push_str("use limit_stream::runtime::Error;\n\n");
+        code.push_str(&format!("pub const IDL: &str = {:?};\n", src));
+        for (_, def) in self.ir.defs() {
+            if !matches!(def.kind, DefKind::Session(_)) || !def.generics.is_empty() {
+                continue;
+            }
+            let name = self.ir.name(def.name);
+            code.push_str(&format!(
+                "\n/// mock peer of `channel {name}`\n#[allow(dead_code, non_snake_case)]\n\
+                 pub async fn mock_{name}(io: impl Io, options: &Options) -> Result<(), Error> {{\n\
+                 {tab}mock::run(mock::session(IDL, {name:?})?, io, options).await\n}}\n",
+            ));
+        }
+        code.push_str(&format!(
+            "\nfn main() -> std::io::Result<()> {{\n{tab}mock::main(IDL)\n}}\n"
+        ));
+        code
     }
 }
 
-impl<'a> Codegen<Rust> for MacrodDef<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        self.0.generate(generator)
+fn generics<S: AsRef<str>>(generics: &[S]) -> String {
+    if generics.is_empty() {
+        String::new()
+    } else {
+        let generics = generics.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        format!("<{}>", generics.join(", "))
     }
 }
 
-impl<'a> Codegen<Rust> for Def<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        match self {
-            Def::SessionDef(d) => d.generate(generator),
-            Def::StructDef(d) => d.generate(generator),
-            Def::EnumDef(d) => d.generate(generator),
+/// `Page<User>` -> `Page_User`
+fn variant_name(typename: &str) -> String {
+    typename
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+fn type_args(args: &[Ty], generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+    out.write_char('<')?;
+    for (i, a) in args.iter().enumerate() {
+        if i != 0 {
+            out.write_str(", ")?;
         }
+        a.write(generator, out)?;
     }
+    Ok(out.write_char('>')?)
 }
 
-impl<'a> Codegen<Rust> for SessionDef<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        let session_name = self.session.generate(generator);
-        format!(
-            "{}#[rustfmt::skip]\n{}#[allow(non_camel_case_types)]\n{}pub type {} = {};\n",
-            generator.get_tab(),
-            generator.get_tab(),
-            generator.get_tab(),
-            self.name,
-            session_name
-        )
+impl Codegen<Rust> for Def {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+        let ir = Rc::clone(&generator.ir);
+        let name = ir.name(self.name);
+        let params = self
+            .generics
+            .iter()
+            .map(|g| ir.name(*g))
+            .collect::<Vec<_>>();
+        let generator = &mut generator.with_generics(&params);
+        generator.def = ir.lookup(name);
+        match &self.kind {
+            DefKind::Const(ty, value) => {
+                write!(out, "pub const {}: ", name)?;
+                match ir.resolve_alias(ty) {
+                    Some(Ty::Simple(SimpleType::String)) => out.write_str("&str")?,
+                    _ => ty.write(generator, out)?,
+                }
+                Ok(writeln!(out, " = {};", literal(&ir, ty, value, false))?)
+            }
+            DefKind::Alias(ty) => {
+                write!(
+                    out,
+                    "#[allow(non_camel_case_types)]\npub type {}{} = ",
+                    name,
+                    generics(&params),
+                )?;
+                inline_ty(ty, generator, out)?;
+                Ok(out.write_str(";\n")?)
+            }
+            DefKind::Session(session) => {
+                write!(
+                    out,
+                    "#[rustfmt::skip]\n#[allow(non_camel_case_types)]\npub type {}{} = ",
+                    name,
+                    generics(&params),
+                )?;
+                session.write(generator, out)?;
+                Ok(out.write_str(";\n")?)
+            }
+            DefKind::Struct(fields) => {
+                write!(
+                    out,
+                    "#[rustfmt::skip]\n#[allow(non_camel_case_types)]\n#[derive(Debug, Clone, PartialEq)]\npub struct {}{} {{\n",
+                    name,
+                    generics(&params),
+                )?;
+                out.indented(generator.tab_size, |out| -> Result<(), CodegenError> {
+                    for i in fields.iter() {
+                        out.write_str("pub ")?;
+                        i.write(generator, out)?;
+                        out.write_str(",\n")?;
+                    }
+                    Ok(())
+                })?;
+                out.write_str("}\n")?;
+                let def = Item {
+                    name,
+                    params: &params,
+                    items: fields,
+                };
+                default_impl(&def, generator, out)?;
+                validate_impl(&def, generator, out)?;
+                Ok(struct_codec(&def, generator, out)?)
+            }
+            DefKind::Enum(variants) => {
+                write!(
+                    out,
+                    "#[rustfmt::skip]\n#[allow(non_camel_case_types)]\n#[derive(Debug, Clone, PartialEq)]\n#[repr(usize)]\npub enum {}{} {{\n",
+                    name,
+                    generics(&params),
+                )?;
+                out.indented(generator.tab_size, |out| -> Result<(), CodegenError> {
+                    for i in variants.iter() {
+                        i.write(generator, out)?;
+                        out.write_str(",\n")?;
+                    }
+                    Ok(())
+                })?;
+                out.write_str("}\n")?;
+                let def = Item {
+                    name,
+                    params: &params,
+                    items: variants,
+                };
+                Ok(enum_codec(&def, generator, out)?)
+            }
+        }
     }
 }
 
-impl<'a> Codegen<Rust> for StructDef<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        let items = self
-            .items
-            .iter()
-            .map(|i| {
-                format!(
-                    "{}pub {},\n",
-                    generator.append_indent().get_tab(),
-                    i.generate(generator)
-                )
-            })
-            .collect::<String>();
-        format!(
-            "{}#[rustfmt::skip]\n{}#[allow(non_camel_case_types)]\n{}#[derive(RmpSer, RmpDeSer)]\n{}pub struct {} {{\n{}{}}}\n",
-            generator.get_tab(),
-            generator.get_tab(),
-            generator.get_tab(),
-            generator.get_tab(),
-            self.name,
-            items,
-            generator.get_tab()
-        )
+/// a struct or enum, with the names of its definition resolved
+struct Item<'d, T> {
+    name: &'d str,
+    params: &'d [&'d str],
+    items: &'d [T],
+}
+
+/// `impl Validate` checking the refinement annotations of each field
+fn validate_impl(
+    def: &Item<Field>,
+    generator: &Rust,
+    out: &mut Writer,
+) -> Result<(), CodegenError> {
+    let ir = &generator.ir;
+    let tab = generator.tab_size;
+    // `0 <= *v` is vacuous for uint fields
+    write!(
+        out,
+        "#[allow(unused_comparisons)]\nimpl{} Validate for {}{} {{\n",
+        generics(def.params),
+        def.name,
+        generics(def.params),
+    )?;
+    out.indented(tab, |out| -> Result<(), CodegenError> {
+        out.write_str("fn validate(&self) -> Result<(), ValidationError> {\n")?;
+        out.indented(tab, |out| -> Result<(), CodegenError> {
+            for i in def.items.iter() {
+                let field = ir.name(i.name);
+                let mut checks = vec![];
+                for a in i.annotations.iter() {
+                    let value = ir.annotation_value(a).ok_or_else(|| {
+                        CodegenError::new(
+                            "annotation value is not a constant",
+                            a.loc.map(|l| l.span),
+                        )
+                    })?;
+                    let constraint = match crate::parser::constraint(ir.name(a.name), value) {
+                        Some(Ok(constraint)) => constraint,
+                        Some(Err(e)) => {
+                            return Err(CodegenError::new(e, a.loc.map(|loc| loc.span)))
+                        }
+                        None => continue,
+                    };
+                    // constraints on `option<T>` apply to the value when present
+                    let ty = match ir.resolve_alias(&i.ty) {
+                        Some(Ty::Option(t)) => t.as_ref(),
+                        _ => &i.ty,
+                    };
+                    let cond = match constraint {
+                        Constraint::Range {
+                            start,
+                            end,
+                            inclusive,
+                        } => start
+                            .map(|c| format!("{} <= *v", literal(&generator.ir, ty, &c, false)))
+                            .into_iter()
+                            .chain(end.map(|c| {
+                                let op = if inclusive { "<=" } else { "<" };
+                                format!("*v {} {}", op, literal(&generator.ir, ty, &c, false))
+                            }))
+                            .collect::<Vec<_>>()
+                            .join(" && "),
+                        Constraint::MinLen(n) => format!("v.length() >= {}", n),
+                        Constraint::MaxLen(n) => format!("v.length() <= {}", n),
+                        Constraint::Pattern(p) => format!("matches_pattern(v, {:?})", p),
+                    };
+                    if !cond.is_empty() {
+                        checks.push((cond, ir.name(a.name)));
+                    }
+                }
+                if checks.is_empty() {
+                    continue;
+                }
+                let optional = matches!(ir.resolve_alias(&i.ty), Some(Ty::Option(_)));
+                let deref = if ir.annotated(&i.annotations, "boxed") {
+                    "*"
+                } else {
+                    ""
+                };
+                if optional {
+                    writeln!(out, "if let Some(v) = &{}self.{} {{", deref, field)?;
+                } else {
+                    out.write_str("{\n")?;
+                }
+                out.indented(tab, |out| {
+                    if !optional {
+                        writeln!(out, "let v = &{}self.{};", deref, field)?;
+                    }
+                    for (cond, constraint) in checks {
+                        writeln!(out, "if !({}) {{", cond)?;
+                        out.indented(tab, |out| {
+                            writeln!(
+                                out,
+                                "return Err(ValidationError {{ field: {:?}, constraint: {:?} }});",
+                                field, constraint,
+                            )
+                        })?;
+                        out.write_str("}\n")?;
+                    }
+                    fmt::Result::Ok(())
+                })?;
+                out.write_str("}\n")?;
+            }
+            Ok(out.write_str("Ok(())\n")?)
+        })?;
+        Ok(out.write_str("}\n")?)
+    })?;
+    Ok(out.write_str("}\n")?)
+}
+
+/// `impl Default` for structs with `default` fields, other fields use their own default
+fn default_impl(def: &Item<Field>, generator: &Rust, out: &mut Writer) -> fmt::Result {
+    if def.items.iter().all(|i| i.default.is_none()) {
+        return Ok(());
     }
+    let tab = generator.tab_size;
+    let bounds = def
+        .params
+        .iter()
+        .map(|g| format!("{}: Default", g))
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "impl{} Default for {}{} {{",
+        generics(&bounds),
+        def.name,
+        generics(def.params),
+    )?;
+    out.indented(tab, |out| {
+        out.write_str("fn default() -> Self {\n")?;
+        out.indented(tab, |out| {
+            out.write_str("Self {\n")?;
+            out.indented(tab, |out| {
+                for i in def.items.iter() {
+                    let value = match &i.default {
+                        Some(c) if generator.ir.annotated(&i.annotations, "boxed") => {
+                            format!("Box::new({})", literal(&generator.ir, &i.ty, c, true))
+                        }
+                        Some(c) => literal(&generator.ir, &i.ty, c, true),
+                        None => "Default::default()".to_string(),
+                    };
+                    writeln!(out, "{}: {},", generator.ir.name(i.name), value)?;
+                }
+                Ok(())
+            })?;
+            out.write_str("}\n")
+        })?;
+        out.write_str("}\n")
+    })?;
+    out.write_str("}\n")
 }
 
-impl<'a> Codegen<Rust> for EnumDef<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        let items = self
-            .items
-            .iter()
-            .map(|i| {
-                format!(
-                    "{}{},\n",
-                    generator.append_indent().get_tab(),
-                    i.generate(generator)
-                )
-            })
-            .collect::<String>();
-        format!(
-            "{}#[rustfmt::skip]\n{}#[allow(non_camel_case_types)]\n{}#[derive(RmpSer, RmpDeSer)]\n{}#[repr(usize)]\n{}enum {} {{\n{}{}}}\n",
-            generator.get_tab(),
-            generator.get_tab(),
-            generator.get_tab(),
-            generator.get_tab(),
-            generator.get_tab(),
-            self.name,
-            items,
-            generator.get_tab()
-        )
+fn bounded_generics(generics: &[&str], bound: &str) -> String {
+    let bounded = generics
+        .iter()
+        .map(|g| format!("{}: {}", g, bound))
+        .collect::<Vec<_>>();
+    self::generics(&bounded)
+}
+
+/// `Encode`/`Decode` for a struct, as an array of its fields
+fn struct_codec(def: &Item<Field>, generator: &Rust, out: &mut Writer) -> fmt::Result {
+    if !generator.codecs() {
+        return Ok(());
+    }
+    let ir = &generator.ir;
+    let tab = generator.tab_size;
+    let name = format!("{}{}", def.name, generics(def.params));
+    let len = def.items.len();
+    writeln!(
+        out,
+        "impl{} Encode for {} {{",
+        bounded_generics(def.params, "Encode"),
+        name
+    )?;
+    out.indented(tab, |out| {
+        out.write_str("fn encode(&self, buf: &mut Vec<u8>) {\n")?;
+        out.indented(tab, |out| {
+            writeln!(out, "encode_array_len(buf, {});", len)?;
+            for i in wire_order(def.items) {
+                writeln!(out, "self.{}.encode(buf);", ir.name(i.name))?;
+            }
+            Ok(())
+        })?;
+        out.write_str("}\n")
+    })?;
+    out.write_str("}\n")?;
+    writeln!(
+        out,
+        "impl{} Decode for {} {{",
+        bounded_generics(def.params, "Decode"),
+        name
+    )?;
+    out.indented(tab, |out| {
+        out.write_str("fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {\n")?;
+        out.indented(tab, |out| {
+            writeln!(out, "decode_array_len(buf, {})?;", len)?;
+            out.write_str("let value = Self {\n")?;
+            out.indented(tab, |out| {
+                for i in wire_order(def.items) {
+                    writeln!(out, "{}: Decode::decode(buf)?,", ir.name(i.name))?;
+                }
+                Ok(())
+            })?;
+            out.write_str("};\nvalue.validate()?;\nOk(value)\n")
+        })?;
+        out.write_str("}\n")
+    })?;
+    out.write_str("}\n")
+}
+
+/// `Encode`/`Decode` for an enum, as `[tag, payload]`
+fn enum_codec(def: &Item<Variant>, generator: &Rust, out: &mut Writer) -> fmt::Result {
+    if !generator.codecs() {
+        return Ok(());
+    }
+    let tab = generator.tab_size;
+    let name = format!("{}{}", def.name, generics(def.params));
+    // implicit tags follow the previous one, like rust discriminants
+    let tags = def
+        .items
+        .iter()
+        .scan(None, |prev: &mut Option<u64>, i| {
+            let tag = i.tag.unwrap_or(prev.map_or(0, |p| p + 1));
+            *prev = Some(tag);
+            Some((generator.ir.name(i.name), tag))
+        })
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "impl{} Encode for {} {{",
+        bounded_generics(def.params, "Encode"),
+        name
+    )?;
+    out.indented(tab, |out| {
+        out.write_str("fn encode(&self, buf: &mut Vec<u8>) {\n")?;
+        out.indented(tab, |out| {
+            out.write_str("match self {\n")?;
+            out.indented(tab, |out| {
+                for (variant, tag) in tags.iter() {
+                    writeln!(
+                        out,
+                        "Self::{}(v) => encode_variant(buf, {}, v),",
+                        variant, tag
+                    )?;
+                }
+                Ok(())
+            })?;
+            out.write_str("}\n")
+        })?;
+        out.write_str("}\n")
+    })?;
+    out.write_str("}\n")?;
+    writeln!(
+        out,
+        "impl{} Decode for {} {{",
+        bounded_generics(def.params, "Decode"),
+        name
+    )?;
+    out.indented(tab, |out| {
+        out.write_str("fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {\n")?;
+        out.indented(tab, |out| {
+            out.write_str("match decode_variant(buf)? {\n")?;
+            out.indented(tab, |out| {
+                for (variant, tag) in tags.iter() {
+                    writeln!(
+                        out,
+                        "{} => Ok(Self::{}(Decode::decode(buf)?)),",
+                        tag, variant
+                    )?;
+                }
+                out.write_str("tag => Err(DecodeError::UnknownVariant(tag)),\n")
+            })?;
+            out.write_str("}\n")
+        })?;
+        out.write_str("}\n")
+    })?;
+    out.write_str("}\n")
+}
+
+/// `owned` turns string literals into `String`s, for field values
+fn literal(ir: &Ir, ty: &Ty, c: &Constant, owned: bool) -> String {
+    match (ir.resolve_alias(ty).unwrap_or(ty), c) {
+        (Ty::Option(ty), c) => format!("Some({})", literal(ir, ty, c, owned)),
+        (_, Constant::String(s)) if owned => format!("{:?}.to_string()", s),
+        (_, Constant::String(s)) => format!("{:?}", s),
+        (_, Constant::Float(f)) => format!("{:?}", f),
+        (Ty::Simple(SimpleType::Float | SimpleType::Double), Constant::Int(i)) => {
+            format!("{}.0", i)
+        }
+        (Ty::Simple(SimpleType::Float | SimpleType::Double), Constant::Uint(u)) => {
+            format!("{}.0", u)
+        }
+        (_, Constant::Int(i)) => i.to_string(),
+        (_, Constant::Uint(u)) => u.to_string(),
+        (_, Constant::Bool(b)) => b.to_string(),
+    }
+}
+
+/// the type of a field or variant, boxed by `#[boxed]`
+fn item_ty(
+    ty: &Ty,
+    annotations: &[Annotation],
+    generator: &mut Rust,
+    out: &mut Writer,
+) -> Result<(), CodegenError> {
+    if !generator.ir.annotated(annotations, "boxed") {
+        return inline_ty(ty, generator, out);
+    }
+    out.write_str("Box<")?;
+    ty.write(generator, out)?;
+    Ok(out.write_char('>')?)
+}
+
+/// `ty` with the references back into the cycle of the definition being
+/// written boxed, where rust would store them inline
+fn inline_ty(ty: &Ty, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+    match ty {
+        Ty::Ref(r) if generator.recursive(r) => {
+            out.write_str("Box<")?;
+            r.write(generator, out)?;
+            out.write_char('>')?;
+        }
+        // arguments stored inline are boxed like the fields of the target
+        Ty::Ref(r) if (0..r.args.len()).any(|i| generator.inline_arg(r, i)) => {
+            out.write_str(Rc::clone(&generator.ir).name(r.name))?;
+            out.write_char('<')?;
+            for (i, a) in r.args.iter().enumerate() {
+                if i != 0 {
+                    out.write_str(", ")?;
+                }
+                if generator.inline_arg(r, i) {
+                    inline_ty(a, generator, out)?;
+                } else {
+                    a.write(generator, out)?;
+                }
+            }
+            out.write_char('>')?;
+        }
+        Ty::Option(t) => {
+            out.write_str("Option<")?;
+            inline_ty(t, generator, out)?;
+            out.write_char('>')?;
+        }
+        Ty::Array(t, Some(len)) => {
+            out.write_char('[')?;
+            inline_ty(t, generator, out)?;
+            out.write_str("; ")?;
+            len.write(generator, out)?;
+            out.write_char(']')?;
+        }
+        ty => ty.write(generator, out)?,
     }
+    Ok(())
 }
 
-impl<'a> Codegen<Rust> for StructItem<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        format!("{}: {}", self.0, self.1.generate(generator))
+impl Codegen<Rust> for Field {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+        write!(out, "{}: ", generator.ir.name(self.name))?;
+        item_ty(&self.ty, &self.annotations, generator, out)
     }
 }
 
-impl<'a> Codegen<Rust> for EnumItem<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        if let Some(tag) = self.2 {
-            format!("{}({}) = {}", self.0, self.1.generate(generator), tag)
-        } else {
-            format!("{}({})", self.0, self.1.generate(generator))
+impl Codegen<Rust> for Variant {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+        write!(out, "{}(", generator.ir.name(self.name))?;
+        item_ty(&self.ty, &self.annotations, generator, out)?;
+        out.write_char(')')?;
+        if let Some(tag) = self.tag {
+            write!(out, " = {}", tag)?;
         }
+        Ok(())
     }
 }
 
-impl<'a> Codegen<Rust> for TypeOrName<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
+impl Codegen<Rust> for Ref {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+        let ir = Rc::clone(&generator.ir);
+        let name = ir.name(self.name);
+        if !self.args.is_empty() {
+            out.write_str(name)?;
+            return type_args(&self.args, generator, out);
+        }
+        match generator.types.get(name) {
+            Some(ty) => out.write_str(ty)?,
+            None => out.write_str(name)?,
+        }
+        Ok(())
+    }
+}
+
+impl Codegen<Rust> for Ty {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
         match self {
-            TypeOrName::Name(name) => name.to_string(),
-            TypeOrName::Type(ty) => ty.generate(generator),
+            Ty::Simple(st) => return st.write(generator, out),
+            Ty::Literal(c) => out.write_str(&generator.literal_register(c))?,
+            Ty::Ref(r) => return r.write(generator, out),
+            Ty::Session(session) => return session.write(generator, out),
+            Ty::Array(ty, None) => {
+                out.write_str("Vec<")?;
+                ty.write(generator, out)?;
+                out.write_char('>')?;
+            }
+            Ty::Array(ty, Some(len)) => {
+                out.write_char('[')?;
+                ty.write(generator, out)?;
+                out.write_str("; ")?;
+                len.write(generator, out)?;
+                out.write_char(']')?;
+            }
+            Ty::Dict(k, v) => {
+                out.write_str("std::collections::HashMap<")?;
+                k.write(generator, out)?;
+                out.write_str(", ")?;
+                v.write(generator, out)?;
+                out.write_char('>')?;
+            }
+            Ty::Option(ty) => {
+                out.write_str("Option<")?;
+                ty.write(generator, out)?;
+                out.write_char('>')?;
+            }
         }
+        Ok(())
     }
 }
 
-impl<'a> Codegen<Rust> for Type<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
+impl Codegen<Rust> for Length {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
         match self {
-            Type::SessionType(session) => session.generate(generator),
-            Type::ContainerType(_) => todo!(),
-            Type::SimpleType(st) => st.generate(generator),
-            Type::Constant(c) => c.generate(generator),
-            Type::Struct(s) => s.get_name().to_string(),
-            Type::Enum(e) => e.get_name().to_string(),
+            Length::Literal(len) => write!(out, "{}", len)?,
+            Length::Const(r) => write!(out, "{} as usize", generator.ir.name(r.name))?,
         }
+        Ok(())
     }
 }
 
-impl<'a> Codegen<Rust> for SessionUnion<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
+/// the arms of an `offer` or `choose`
+impl Codegen<Rust> for Vec<Branch> {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
         let enumitem = self
-            .0
             .iter()
             .map(|s| s.generate(generator))
-            .collect::<Vec<String>>();
+            .collect::<Result<Vec<String>, _>>()?;
         // register anonymous session union and get name
-        generator.anonymous_union_register(&enumitem)
+        Ok(out.write_str(&generator.anonymous_union_register(&enumitem))?)
     }
 }
 
-impl<'a> Codegen<Rust> for SessionOrName<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
+impl Codegen<Rust> for Branch {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
         match self {
-            SessionOrName::Name(n) => n.to_string(),
-            SessionOrName::Session(session) => {
-                let session = session.generate(generator);
+            Branch::Ref(r) => {
+                out.write_str(generator.ir.name(r.name))?;
+                if !r.args.is_empty() {
+                    type_args(&r.args, generator, out)?
+                }
+            }
+            Branch::Session(session) => {
+                let session = session.generate(generator)?;
                 // register session
-                generator.anonymous_session_register(&session)
+                out.write_str(&generator.anonymous_session_register(&session))?
             }
         }
+        Ok(())
     }
 }
 
-impl<'a> Codegen<Rust> for SessionType<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        // todo: register anonymous session and get name
-        let mut r = String::new();
-        assert!(!self.0.is_empty());
-        for i in self.0.iter().rev() {
-            r = if r.is_empty() {
-                i.generate(generator)
-            } else {
-                format!("Next<{}, {}>", i.generate(generator), r)
-            };
+impl Codegen<Rust> for SessionType {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+        if self.0.is_empty() {
+            return Err(CodegenError::new("empty session", None));
         }
-        r
+        // generated from the end, which numbers the anonymous types
+        let mut sessions = self
+            .0
+            .iter()
+            .rev()
+            .map(|s| s.generate(generator))
+            .collect::<Result<Vec<_>, _>>()?;
+        let last = sessions.remove(0);
+        for s in sessions.iter().rev() {
+            write!(out, "Next<{}, ", s)?;
+        }
+        out.write_str(&last)?;
+        for _ in sessions.iter() {
+            out.write_char('>')?;
+        }
+        Ok(())
     }
 }
 
-impl<'a> Codegen<Rust> for Session<'a> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        match self {
-            Session::Recv(ty) => format!("Recv<{}>", ty.generate(generator)),
-            Session::Send(ty) => format!("Send<{}>", ty.generate(generator)),
-            Session::Offer(union) => format!("Offer<{}>", union.generate(generator)),
-            Session::Choose(union) => format!("Choose<{}>", union.generate(generator)),
-            Session::Endpoint => "Endpoint".to_string(),
-        }
+impl Codegen<Rust> for Session {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+        let (name, ty): (_, &dyn Codegen<Rust>) = match self {
+            Session::Recv(ty) => ("Recv", ty),
+            Session::Send(ty) => ("Send", ty),
+            Session::Offer(union) => ("Offer", union),
+            Session::Choose(union) => ("Choose", union),
+            Session::Endpoint => return Ok(out.write_str("Endpoint")?),
+        };
+        write!(out, "{}<", name)?;
+        ty.write(generator, out)?;
+        Ok(out.write_char('>')?)
     }
 }
 
 // /*
 impl Codegen<Rust> for SimpleType {
-    fn generate(&self, _generator: &mut Rust) -> String {
+    fn write(&self, generator: &mut Rust, out: &mut Writer) -> Result<(), CodegenError> {
+        if let Some(ty) = generator.types.get(&self.get_name().to_lowercase()) {
+            return Ok(out.write_str(ty)?);
+        }
         /*
         match self {
             SimpleType::Bool => "bool",
@@ -259,28 +918,10 @@ impl Codegen<Rust> for SimpleType {
         }
         .to_string()
         // */
-        self.get_name().to_string()
+        Ok(out.write_str(self.get_name())?)
     }
 }
 // */
-impl Codegen<Rust> for Constant {
-    fn generate(&self, _generator: &mut Rust) -> String {
-        match self {
-            Constant::String(s) => s.to_string(),
-            Constant::Float(f) => f.to_string(),
-            Constant::Int(i) => i.to_string(),
-            Constant::Uint(u) => u.to_string(),
-            Constant::Bool(b) => b.to_string(),
-        }
-    }
-}
-
-impl<'a, T: Codegen<Rust>> Codegen<Rust> for Macro<'a, T> {
-    fn generate(&self, generator: &mut Rust) -> String {
-        self.body.generate(generator)
-    }
-}
-
 // impl<'a> Codegen<Rust> for Append<'a> {
 // fn generate(&self, generator: &mut Rust) -> String {
 // }
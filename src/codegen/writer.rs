@@ -0,0 +1,62 @@
+//! Code writer
+//!
+//! Backends write their output to a `Writer`, which indents every line
+//! written inside `Writer::indented`, instead of building it from nested
+//! `format!`s and passing the indentation down.
+
+use std::fmt::{self, Write};
+
+pub struct Writer<'w> {
+    out: &'w mut dyn Write,
+    /// in columns
+    indent: usize,
+    /// indentation is written with the first text of a line, so blank
+    /// lines stay empty
+    line_start: bool,
+}
+
+impl<'w> Writer<'w> {
+    pub fn new(out: &'w mut dyn Write) -> Self {
+        Self {
+            out,
+            indent: 0,
+            line_start: true,
+        }
+    }
+
+    /// runs `f` with every line it writes indented by `by` more columns
+    pub fn indented<T>(&mut self, by: usize, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.indent += by;
+        let res = f(self);
+        self.indent -= by;
+        res
+    }
+
+    fn write_line(&mut self, line: &str) -> fmt::Result {
+        if line.is_empty() {
+            return Ok(());
+        }
+        if self.line_start {
+            for _ in 0..self.indent {
+                self.out.write_char(' ')?;
+            }
+            self.line_start = false;
+        }
+        self.out.write_str(line)
+    }
+}
+
+impl Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.write_line(first)?;
+        }
+        for line in lines {
+            self.out.write_char('\n')?;
+            self.line_start = true;
+            self.write_line(line)?;
+        }
+        Ok(())
+    }
+}
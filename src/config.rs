@@ -0,0 +1,166 @@
+//! Project configuration
+//!
+//! `limit.toml`, found in the working directory or one of its parents.
+//! Every key is optional, paths are relative to the file:
+//!
+//! ```toml
+//! [project]
+//! roots = ["idl"]
+//! include = ["**/*.lstr"]
+//! exclude = ["vendor/**"]
+//! gen_mode = "client"
+//!
+//! [format]
+//! tab_size = 4
+//! max_width = 100
+//! trailing_comma = true
+//! align_fields = false
+//! sort_fields = false
+//!
+//! [codegen.rust]
+//! out_dir = "src/generated"
+//! tab_size = 2
+//! serialization = "msgpack"
+//!
+//! [codegen.rust.types]
+//! uint = "u32"
+//! Timestamp = "std::time::SystemTime"
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::codegen::formatter::Style;
+use crate::sources::Sources;
+
+pub const CONFIG_FILE: &str = "limit.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// directory of the `limit.toml`
+    #[serde(skip)]
+    pub dir: PathBuf,
+    pub project: Project,
+    pub format: Style,
+    /// keyed by `--lang`
+    pub codegen: BTreeMap<String, Target>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Project {
+    /// IDL files or directories
+    pub roots: Vec<PathBuf>,
+    /// globs relative to each root, see `sources::Sources`
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub gen_mode: GenMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GenMode {
+    #[default]
+    Client,
+    Server,
+    All,
+    /// a program playing either side of every channel, see `runtime::mock`
+    Mock,
+}
+
+impl FromStr for GenMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(GenMode::Client),
+            "server" => Ok(GenMode::Server),
+            "all" => Ok(GenMode::All),
+            "mock" => Ok(GenMode::Mock),
+            _ => Err(format!(
+                "unknown gen mode `{}`, expected client | server | all | mock",
+                s
+            )),
+        }
+    }
+}
+
+/// settings of one codegen language
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Target {
+    pub out_dir: Option<PathBuf>,
+    pub tab_size: usize,
+    pub serialization: Serialization,
+    /// IDL type name to target type, replacing the generated or runtime one
+    pub types: HashMap<String, String>,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self {
+            out_dir: None,
+            tab_size: 2,
+            serialization: Serialization::default(),
+            types: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Serialization {
+    /// `Encode` / `Decode` to MessagePack, see `runtime::wire`
+    #[default]
+    Msgpack,
+    /// plain types without codecs
+    None,
+}
+
+impl Config {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        toml::from_str(src).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut config = Self::parse(&src).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(config)
+    }
+
+    /// the nearest `limit.toml` in `dir` or its parents
+    pub fn discover(dir: &Path) -> Result<Option<Self>, String> {
+        dir.ancestors()
+            .map(|d| d.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+            .map(|path| Self::load(&path))
+            .transpose()
+    }
+
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.project
+            .roots
+            .iter()
+            .map(|root| self.dir.join(root))
+            .collect()
+    }
+
+    /// the project's globs, plus `include` and `exclude`
+    pub fn sources(&self, include: &[String], exclude: &[String]) -> Result<Sources, String> {
+        Sources::new(
+            &[&self.project.include[..], include].concat(),
+            &[&self.project.exclude[..], exclude].concat(),
+        )
+    }
+
+    pub fn target(&self, lang: &str) -> Target {
+        let mut target = self.codegen.get(lang).cloned().unwrap_or_default();
+        target.out_dir = target.out_dir.map(|out| self.dir.join(out));
+        target
+    }
+}
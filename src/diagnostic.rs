@@ -0,0 +1,346 @@
+//! Diagnostics
+//!
+//! Errors and warnings of every command, with the span of source they
+//! point at. They print for humans, as one JSON object per line, or as a
+//! SARIF log for CI annotations.
+
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+/// byte range of the source, with 1-based line and column (in chars) of
+/// both ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+    pub line: u32,
+    pub column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+}
+
+impl Span {
+    /// scans `src` up to `end`, use `Lines` to locate many spans of a source
+    pub fn new(src: &str, start: usize, end: usize) -> Self {
+        Lines::new(src).span(start, end)
+    }
+
+    /// the bytes of the source
+    pub fn range(&self) -> Range<usize> {
+        self.start as usize..self.end as usize
+    }
+
+    /// the span of `part`, if it is a slice of `src`
+    ///
+    /// The AST borrows names from the source, so errors about them can be
+    /// located without tracking positions in the parser.
+    pub fn of(src: &str, part: &str) -> Option<Self> {
+        Lines::new(src).of(part)
+    }
+}
+
+/// where the lines of a source start, to locate spans without rescanning it
+#[derive(Debug, Clone)]
+pub struct Lines<'s> {
+    src: &'s str,
+    starts: Vec<usize>,
+}
+
+impl<'s> Lines<'s> {
+    pub fn new(src: &'s str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { src, starts }
+    }
+
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let (line, column) = self.position(start);
+        let (end_line, end_column) = self.position(end);
+        Span {
+            start: start as u32,
+            end: end as u32,
+            line,
+            column,
+            end_line,
+            end_column,
+        }
+    }
+
+    /// see `Span::of`
+    pub fn of(&self, part: &str) -> Option<Span> {
+        let start = (part.as_ptr() as usize).checked_sub(self.src.as_ptr() as usize)?;
+        (start + part.len() <= self.src.len()).then(|| self.span(start, start + part.len()))
+    }
+
+    fn position(&self, offset: usize) -> (u32, u32) {
+        let offset = offset.min(self.src.len());
+        let line = self.starts.partition_point(|&s| s <= offset);
+        let line_start = self.starts[line - 1];
+        (
+            line as u32,
+            self.src[line_start..offset].chars().count() as u32 + 1,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Related {
+    pub message: String,
+    /// which may be another file than the diagnostic's
+    pub file: Option<Box<Path>>,
+    pub span: Span,
+}
+
+/// replace `span` with `replacement`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fix {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// stable kebab-case name, the SARIF rule id
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<Box<Path>>,
+    pub span: Option<Span>,
+    pub related: Vec<Related>,
+    pub fix: Option<Box<Fix>>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            file: None,
+            span: None,
+            related: vec![],
+            fix: None,
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, message)
+        }
+    }
+
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn with_related(
+        mut self,
+        message: impl Into<String>,
+        file: Option<&Path>,
+        span: Option<Span>,
+    ) -> Self {
+        if let Some(span) = span {
+            self.related.push(Related {
+                message: message.into(),
+                file: file.map(Into::into),
+                span,
+            });
+        }
+        self
+    }
+
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fix = Some(Box::new(fix));
+        self
+    }
+
+    pub fn in_file(mut self, file: &Path) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).expect("diagnostics serialize to JSON")
+    }
+}
+
+impl From<std::io::Error> for Diagnostic {
+    fn from(e: std::io::Error) -> Self {
+        Self::error("io-error", e.to_string())
+    }
+}
+
+/// `error[code]: file:line:column: message`
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: ", self.severity.as_str(), self.code)?;
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(span) = &self.span {
+            write!(f, "{}:{}:", span.line, span.column)?;
+        }
+        if self.file.is_some() || self.span.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)?;
+        for related in self.related.iter() {
+            write!(f, "\n  note: ")?;
+            if let Some(file) = &related.file {
+                write!(f, "{}:", file.display())?;
+            }
+            write!(
+                f,
+                "{}:{}: {}",
+                related.span.line, related.span.column, related.message
+            )?;
+        }
+        if let Some(fix) = &self.fix {
+            write!(f, "\n  help: {}", fix.message)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    /// one object per line
+    Json,
+    /// one log of every diagnostic
+    Sarif,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            "sarif" => Ok(MessageFormat::Sarif),
+            _ => Err(format!(
+                "unknown message format `{}`, expected human | json | sarif",
+                s
+            )),
+        }
+    }
+}
+
+/// SARIF 2.1.0 log of `diagnostics`, spans are in bytes and columns in chars
+pub fn sarif(diagnostics: &[Diagnostic]) -> Value {
+    let mut rules = diagnostics.iter().map(|d| d.code).collect::<Vec<_>>();
+    rules.sort();
+    rules.dedup();
+    let results = diagnostics
+        .iter()
+        .map(|d| {
+            let mut result = json!({
+                "ruleId": d.code,
+                "level": d.severity.as_str(),
+                "message": { "text": d.message },
+            });
+            if d.file.is_some() || d.span.is_some() {
+                result["locations"] =
+                    json!([{ "physicalLocation": location(d.file.as_deref(), d.span) }]);
+            }
+            if !d.related.is_empty() {
+                result["relatedLocations"] = d
+                    .related
+                    .iter()
+                    .map(|r| {
+                        json!({
+                            "physicalLocation": location(r.file.as_deref(), Some(r.span)),
+                            "message": { "text": r.message },
+                        })
+                    })
+                    .collect();
+            }
+            if let Some(fix) = &d.fix {
+                result["fixes"] = json!([{
+                    "description": { "text": fix.message },
+                    "artifactChanges": [{
+                        "artifactLocation": artifact(d.file.as_deref()),
+                        "replacements": [{
+                            "deletedRegion": region(fix.span),
+                            "insertedContent": { "text": fix.replacement },
+                        }],
+                    }],
+                }]);
+            }
+            result
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+fn artifact(file: Option<&Path>) -> Value {
+    match file {
+        Some(file) => json!({ "uri": file.to_string_lossy().replace('\\', "/") }),
+        None => json!({ "uri": "<stdin>" }),
+    }
+}
+
+fn region(span: Span) -> Value {
+    json!({
+        "startLine": span.line,
+        "startColumn": span.column,
+        "endLine": span.end_line,
+        "endColumn": span.end_column,
+        "byteOffset": span.start,
+        "byteLength": span.end - span.start,
+    })
+}
+
+fn location(file: Option<&Path>, span: Option<Span>) -> Value {
+    let mut location = json!({ "artifactLocation": artifact(file) });
+    if let Some(span) = span {
+        location["region"] = region(span);
+    }
+    location
+}
@@ -0,0 +1,643 @@
+//! Intermediate representation
+//!
+//! The parse tree borrows every name from its source, so it can't outlive
+//! it, combine several files or be rewritten. `lower` turns it into an
+//! owned IR: definitions live in one arena and are referred to by `DefId`,
+//! names are interned `Symbol`s and every reference is bound to the
+//! definition or generic parameter it names. Checkers and backends work on
+//! the IR, the formatter and the language server keep the parse tree.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::ast::{
+    self, Append, ArrayLength, Constant, ContainerType, Macro, MacrodDef, SessionOrName,
+    SimpleType, TypeOrName,
+};
+use crate::diagnostic::{Lines, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DefId(u32);
+
+impl DefId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(u32);
+
+/// where a node was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loc {
+    pub file: FileId,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Interner {
+    names: Vec<Box<str>>,
+    ids: HashMap<Box<str>, Symbol>,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(sym) = self.ids.get(name) {
+            return *sym;
+        }
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name.into());
+        self.ids.insert(name.into(), sym);
+        sym
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ir {
+    names: Interner,
+    files: Vec<Option<Box<Path>>>,
+    defs: Vec<Def>,
+    /// the first definition of each name
+    scope: HashMap<Symbol, DefId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Def {
+    pub name: Symbol,
+    pub loc: Option<Loc>,
+    pub generics: Vec<Symbol>,
+    /// `///` comments, without the slashes
+    pub docs: Vec<String>,
+    pub kind: DefKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefKind {
+    Struct(Vec<Field>),
+    Enum(Vec<Variant>),
+    Session(SessionType),
+    Const(Ty, Constant),
+    Alias(Ty),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: Symbol,
+    pub loc: Option<Loc>,
+    pub ty: Ty,
+    pub tag: Option<u64>,
+    pub default: Option<Constant>,
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub name: Symbol,
+    pub loc: Option<Loc>,
+    pub value: AnnotationValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnotationValue {
+    Constant(Constant),
+    /// the value of a `const`
+    Const(Ref),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: Symbol,
+    pub loc: Option<Loc>,
+    pub ty: Ty,
+    pub tag: Option<u64>,
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ty {
+    Simple(SimpleType),
+    /// a payload which is always this constant
+    Literal(Constant),
+    Ref(Ref),
+    Array(Box<Ty>, Option<Length>),
+    Dict(SimpleType, Box<Ty>),
+    Option(Box<Ty>),
+    Session(Box<SessionType>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Length {
+    Literal(usize),
+    Const(Ref),
+}
+
+/// a name applied to its generic arguments
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ref {
+    pub name: Symbol,
+    pub target: Target,
+    pub args: Vec<Ty>,
+    pub loc: Option<Loc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Def(DefId),
+    /// a generic parameter of the enclosing definition
+    Param,
+    /// no definition has this name, it may still be mapped by a backend
+    Unresolved,
+}
+
+/// `s1 -> s2 -> ... -> sn`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionType(pub Vec<Session>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Session {
+    Recv(Ty),
+    Send(Ty),
+    Offer(Vec<Branch>),
+    Choose(Vec<Branch>),
+    Endpoint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Branch {
+    Ref(Ref),
+    Session(SessionType),
+}
+
+impl Ir {
+    pub fn name(&self, sym: Symbol) -> &str {
+        &self.names.names[sym.0 as usize]
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        self.names.intern(name)
+    }
+
+    pub fn file(&self, file: FileId) -> Option<&Path> {
+        self.files.get(file.0 as usize)?.as_deref()
+    }
+
+    pub fn def(&self, id: DefId) -> &Def {
+        &self.defs[id.index()]
+    }
+
+    pub fn def_mut(&mut self, id: DefId) -> &mut Def {
+        &mut self.defs[id.index()]
+    }
+
+    /// in the order they were defined
+    pub fn defs(&self) -> impl Iterator<Item = (DefId, &Def)> {
+        self.defs
+            .iter()
+            .enumerate()
+            .map(|(i, def)| (DefId(i as u32), def))
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<DefId> {
+        let sym = self.names.ids.get(name)?;
+        self.scope.get(sym).copied()
+    }
+
+    /// follows aliases from `ty` to the type they name, `None` when that is
+    /// a definition, a generic parameter or an unknown name, or when the
+    /// aliases are cyclic
+    pub fn resolve_alias<'t>(&'t self, mut ty: &'t Ty) -> Option<&'t Ty> {
+        // a longer chain means the aliases are cyclic
+        for _ in 0..=self.defs.len() {
+            match ty {
+                Ty::Ref(Ref {
+                    target: Target::Def(id),
+                    args,
+                    ..
+                }) if args.is_empty() => match &self.def(*id) {
+                    Def {
+                        kind: DefKind::Alias(t),
+                        generics,
+                        ..
+                    } if generics.is_empty() => ty = t,
+                    _ => return None,
+                },
+                Ty::Ref(_) => return None,
+                ty => return Some(ty),
+            }
+        }
+        None
+    }
+
+    /// adds `def`, references to its name are bound by the next `bind`
+    pub fn push(&mut self, def: Def) -> DefId {
+        let id = DefId(self.defs.len() as u32);
+        self.scope.entry(def.name).or_insert(id);
+        self.defs.push(def);
+        id
+    }
+
+    /// binds every reference which isn't a generic parameter to the
+    /// definition of its name
+    pub fn bind(&mut self) {
+        let scope = &self.scope;
+        for def in self.defs.iter_mut() {
+            def.kind.for_each_ref_mut(&mut |r| {
+                if r.target != Target::Param {
+                    r.target = scope
+                        .get(&r.name)
+                        .map_or(Target::Unresolved, |id| Target::Def(*id));
+                }
+            });
+        }
+    }
+
+    /// the constant `a` is set to, `None` if it names something else than a
+    /// `const`
+    pub fn annotation_value<'i>(&'i self, a: &'i Annotation) -> Option<&'i Constant> {
+        match &a.value {
+            AnnotationValue::Constant(c) => Some(c),
+            AnnotationValue::Const(Ref {
+                target: Target::Def(id),
+                ..
+            }) => match &self.def(*id).kind {
+                DefKind::Const(_, c) => Some(c),
+                _ => None,
+            },
+            AnnotationValue::Const(_) => None,
+        }
+    }
+
+    /// whether `annotations` include `#[name]`
+    pub fn annotated(&self, annotations: &[Annotation], name: &str) -> bool {
+        annotations.iter().any(|a| self.name(a.name) == name)
+    }
+
+    /// a copy with only the names and files, for passes which rebuild the
+    /// definitions
+    pub fn without_defs(&self) -> Self {
+        Self {
+            names: self.names.clone(),
+            files: self.files.clone(),
+            defs: vec![],
+            scope: HashMap::new(),
+        }
+    }
+}
+
+impl DefKind {
+    pub fn for_each_ref(&self, f: &mut impl FnMut(&Ref)) {
+        match self {
+            DefKind::Struct(fields) => fields.iter().for_each(|i| {
+                i.ty.for_each_ref(f);
+                i.annotations.iter().for_each(|a| a.for_each_ref(f));
+            }),
+            DefKind::Enum(variants) => variants.iter().for_each(|i| i.ty.for_each_ref(f)),
+            DefKind::Session(s) => s.for_each_ref(f),
+            DefKind::Const(ty, _) | DefKind::Alias(ty) => ty.for_each_ref(f),
+        }
+    }
+
+    pub fn for_each_ref_mut(&mut self, f: &mut impl FnMut(&mut Ref)) {
+        match self {
+            DefKind::Struct(fields) => fields.iter_mut().for_each(|i| {
+                i.ty.for_each_ref_mut(f);
+                i.annotations.iter_mut().for_each(|a| a.for_each_ref_mut(f));
+            }),
+            DefKind::Enum(variants) => variants.iter_mut().for_each(|i| i.ty.for_each_ref_mut(f)),
+            DefKind::Session(s) => s.for_each_ref_mut(f),
+            DefKind::Const(ty, _) | DefKind::Alias(ty) => ty.for_each_ref_mut(f),
+        }
+    }
+}
+
+/// `fields` in the order they are encoded: by tag, untagged ones last in
+/// definition order, so reordering fields doesn't change the encoding
+pub fn wire_order(fields: &[Field]) -> Vec<&Field> {
+    let mut fields = fields.iter().collect::<Vec<_>>();
+    fields.sort_by_key(|f| (f.tag.is_none(), f.tag));
+    fields
+}
+
+impl Annotation {
+    pub fn for_each_ref(&self, f: &mut impl FnMut(&Ref)) {
+        if let AnnotationValue::Const(r) = &self.value {
+            f(r);
+        }
+    }
+
+    pub fn for_each_ref_mut(&mut self, f: &mut impl FnMut(&mut Ref)) {
+        if let AnnotationValue::Const(r) = &mut self.value {
+            f(r);
+        }
+    }
+}
+
+impl Ty {
+    pub fn for_each_ref(&self, f: &mut impl FnMut(&Ref)) {
+        match self {
+            Ty::Simple(_) | Ty::Literal(_) => {}
+            Ty::Ref(r) => r.for_each_ref(f),
+            Ty::Array(t, len) => {
+                if let Some(Length::Const(r)) = len {
+                    f(r);
+                }
+                t.for_each_ref(f)
+            }
+            Ty::Dict(_, t) | Ty::Option(t) => t.for_each_ref(f),
+            Ty::Session(s) => s.for_each_ref(f),
+        }
+    }
+
+    pub fn for_each_ref_mut(&mut self, f: &mut impl FnMut(&mut Ref)) {
+        match self {
+            Ty::Simple(_) | Ty::Literal(_) => {}
+            Ty::Ref(r) => r.for_each_ref_mut(f),
+            Ty::Array(t, len) => {
+                if let Some(Length::Const(r)) = len {
+                    f(r);
+                }
+                t.for_each_ref_mut(f)
+            }
+            Ty::Dict(_, t) | Ty::Option(t) => t.for_each_ref_mut(f),
+            Ty::Session(s) => s.for_each_ref_mut(f),
+        }
+    }
+}
+
+impl Ref {
+    /// this reference, then the ones in its arguments
+    pub fn for_each_ref(&self, f: &mut impl FnMut(&Ref)) {
+        f(self);
+        self.args.iter().for_each(|a| a.for_each_ref(f));
+    }
+
+    pub fn for_each_ref_mut(&mut self, f: &mut impl FnMut(&mut Ref)) {
+        f(self);
+        self.args.iter_mut().for_each(|a| a.for_each_ref_mut(f));
+    }
+}
+
+impl SessionType {
+    pub fn for_each_ref(&self, f: &mut impl FnMut(&Ref)) {
+        for s in self.0.iter() {
+            match s {
+                Session::Recv(t) | Session::Send(t) => t.for_each_ref(f),
+                Session::Offer(branches) | Session::Choose(branches) => {
+                    for b in branches {
+                        match b {
+                            Branch::Ref(r) => r.for_each_ref(f),
+                            Branch::Session(s) => s.for_each_ref(f),
+                        }
+                    }
+                }
+                Session::Endpoint => {}
+            }
+        }
+    }
+
+    pub fn for_each_ref_mut(&mut self, f: &mut impl FnMut(&mut Ref)) {
+        for s in self.0.iter_mut() {
+            match s {
+                Session::Recv(t) | Session::Send(t) => t.for_each_ref_mut(f),
+                Session::Offer(branches) | Session::Choose(branches) => {
+                    for b in branches {
+                        match b {
+                            Branch::Ref(r) => r.for_each_ref_mut(f),
+                            Branch::Session(s) => s.for_each_ref_mut(f),
+                        }
+                    }
+                }
+                Session::Endpoint => {}
+            }
+        }
+    }
+}
+
+/// the definitions parsed from `src`
+#[derive(Debug, Clone, Copy)]
+pub struct Unit<'s, 'a> {
+    pub path: Option<&'s Path>,
+    pub src: &'s str,
+    pub defs: &'s [MacrodDef<'a>],
+}
+
+impl<'s, 'a> Unit<'s, 'a> {
+    pub fn new(src: &'s str, defs: &'s [MacrodDef<'a>]) -> Self {
+        Self {
+            path: None,
+            src,
+            defs,
+        }
+    }
+
+    pub fn in_file(self, path: &'s Path) -> Self {
+        Self {
+            path: Some(path),
+            ..self
+        }
+    }
+}
+
+/// one IR of the definitions of every unit, names are resolved across them
+pub fn lower(units: &[Unit]) -> Ir {
+    let mut ir = Ir::default();
+    for unit in units {
+        let file = FileId(ir.files.len() as u32);
+        ir.files.push(unit.path.map(Into::into));
+        let lines = Lines::new(unit.src);
+        for def in unit.defs {
+            let def = Lower {
+                ir: &mut ir,
+                file,
+                lines: &lines,
+                params: ast_generics(&def.0.body),
+            }
+            .def(def);
+            ir.push(def);
+        }
+    }
+    ir.bind();
+    ir
+}
+
+struct Lower<'i, 's, 'a> {
+    ir: &'i mut Ir,
+    file: FileId,
+    lines: &'s Lines<'s>,
+    /// generic parameters of the definition being lowered
+    params: &'s [&'a str],
+}
+
+impl<'a> Lower<'_, '_, 'a> {
+    fn loc(&self, part: &str) -> Option<Loc> {
+        Some(Loc {
+            file: self.file,
+            span: self.lines.of(part)?,
+        })
+    }
+
+    fn def(&mut self, def: &MacrodDef<'a>) -> Def {
+        let MacrodDef(def) = def;
+        let docs = def
+            .appends
+            .iter()
+            .filter_map(|a| match a {
+                Append::DocsComment(s) => {
+                    Some(s.trim().trim_start_matches("///").trim().to_string())
+                }
+                _ => None,
+            })
+            .collect();
+        let (name, kind) = match def.body.as_ref() {
+            ast::Def::StructDef(d) => (
+                d.name,
+                DefKind::Struct(
+                    d.items
+                        .iter()
+                        .map(|i| {
+                            let ast::StructItem(name, ty, tag, default) = i.body.as_ref();
+                            Field {
+                                name: self.ir.intern(name),
+                                loc: self.loc(name),
+                                ty: self.ty(ty),
+                                tag: *tag,
+                                default: default.clone(),
+                                annotations: self.annotations(i),
+                            }
+                        })
+                        .collect(),
+                ),
+            ),
+            ast::Def::EnumDef(d) => (
+                d.name,
+                DefKind::Enum(
+                    d.items
+                        .iter()
+                        .map(|i| {
+                            let ast::EnumItem(name, ty, tag) = i.body.as_ref();
+                            Variant {
+                                name: self.ir.intern(name),
+                                loc: self.loc(name),
+                                ty: self.ty(ty),
+                                tag: *tag,
+                                annotations: self.annotations(i),
+                            }
+                        })
+                        .collect(),
+                ),
+            ),
+            ast::Def::SessionDef(d) => (d.name, DefKind::Session(self.session(&d.session.body))),
+            ast::Def::ConstDef(d) => (d.name, DefKind::Const(self.ty(&d.ty), d.value.clone())),
+            ast::Def::TypeDef(d) => (d.name, DefKind::Alias(self.ty(&d.ty))),
+        };
+        Def {
+            name: self.ir.intern(name),
+            loc: self.loc(name),
+            generics: self.params.iter().map(|g| self.ir.intern(g)).collect(),
+            docs,
+            kind,
+        }
+    }
+
+    fn annotations<T>(&mut self, item: &Macro<'a, T>) -> Vec<Annotation> {
+        item.annotations()
+            .map(|a| Annotation {
+                name: self.ir.intern(a.0),
+                loc: self.loc(a.0),
+                value: match &a.1 {
+                    ast::AnnotationValue::Constant(c) => AnnotationValue::Constant(c.clone()),
+                    ast::AnnotationValue::Const(name) => {
+                        AnnotationValue::Const(self.reference(name, &[]))
+                    }
+                },
+            })
+            .collect()
+    }
+
+    fn reference(&mut self, name: &str, args: &[TypeOrName<'a>]) -> Ref {
+        Ref {
+            name: self.ir.intern(name),
+            target: if self.params.contains(&name) {
+                Target::Param
+            } else {
+                Target::Unresolved
+            },
+            args: args.iter().map(|a| self.ty(a)).collect(),
+            loc: self.loc(name),
+        }
+    }
+
+    fn ty(&mut self, ty: &TypeOrName<'a>) -> Ty {
+        match ty {
+            TypeOrName::Name(n) => Ty::Ref(self.reference(n, &[])),
+            TypeOrName::Generic(n, args) => Ty::Ref(self.reference(n, args)),
+            TypeOrName::Type(t) => match t.as_ref() {
+                ast::Type::SessionType(s) => Ty::Session(Box::new(self.session(s))),
+                ast::Type::Struct(s) => Ty::Ref(self.reference(s.name, &[])),
+                ast::Type::Enum(e) => Ty::Ref(self.reference(e.name, &[])),
+                ast::Type::TypeDef(t) => Ty::Ref(self.reference(t.name, &[])),
+                ast::Type::ContainerType(ContainerType::Array(t, len)) => Ty::Array(
+                    Box::new(self.ty(t)),
+                    len.map(|len| match len {
+                        ArrayLength::Literal(n) => Length::Literal(n),
+                        ArrayLength::Const(n) => Length::Const(self.reference(n, &[])),
+                    }),
+                ),
+                ast::Type::ContainerType(ContainerType::Dict(k, v)) => {
+                    Ty::Dict(*k, Box::new(self.ty(v)))
+                }
+                ast::Type::ContainerType(ContainerType::Option(t)) => {
+                    Ty::Option(Box::new(self.ty(t)))
+                }
+                ast::Type::SimpleType(t) => Ty::Simple(*t),
+                ast::Type::Constant(c) => Ty::Literal(c.clone()),
+            },
+        }
+    }
+
+    fn session(&mut self, s: &ast::SessionType<'a>) -> SessionType {
+        SessionType(
+            s.0.iter()
+                .map(|m| match m.body.as_ref() {
+                    ast::Session::Recv(t) => Session::Recv(self.ty(t)),
+                    ast::Session::Send(t) => Session::Send(self.ty(t)),
+                    ast::Session::Offer(u) => Session::Offer(self.branches(u)),
+                    ast::Session::Choose(u) => Session::Choose(self.branches(u)),
+                    ast::Session::Endpoint => Session::Endpoint,
+                })
+                .collect(),
+        )
+    }
+
+    fn branches(&mut self, u: &ast::SessionUnion<'a>) -> Vec<Branch> {
+        u.0.iter()
+            .map(|s| match s {
+                SessionOrName::Name(n) => Branch::Ref(self.reference(n, &[])),
+                SessionOrName::Generic(n, args) => Branch::Ref(self.reference(n, args)),
+                SessionOrName::Session(s) => Branch::Session(self.session(s)),
+            })
+            .collect()
+    }
+}
+
+fn ast_generics<'d, 'a>(def: &'d ast::Def<'a>) -> &'d [&'a str] {
+    match def {
+        ast::Def::SessionDef(d) => &d.generics,
+        ast::Def::StructDef(d) => &d.generics,
+        ast::Def::EnumDef(d) => &d.generics,
+        ast::Def::ConstDef(_) => &[],
+        ast::Def::TypeDef(d) => &d.generics,
+    }
+}
@@ -1,4 +1,13 @@
 pub mod ast;
+pub mod build;
 pub mod codegen;
+pub mod config;
+pub mod diagnostic;
+pub mod ir;
+pub mod lsp;
+pub mod monomorphize;
 pub mod parser;
+pub mod runtime;
+pub mod sources;
 pub mod type_checker;
+pub mod watch;
@@ -0,0 +1,617 @@
+//! Language server
+//!
+//! Serves `.lstr` documents over LSP on top of the parser, the type checker
+//! and `Codegen<Formatter>`. Documents are synced in full and parsed again
+//! for every request, the AST borrows from their text. Requests work on
+//! what parses of a broken text, see `parser::parse_recovering`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, Formatting, GotoDefinition, HoverRequest, References,
+    Request as LspRequest,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionResponse,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse,
+    GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability, Location, MarkupContent,
+    MarkupKind, NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit,
+    Uri,
+};
+
+use crate::ast::{
+    Append, ArrayLength, ContainerType, Def, GetName, Macro, MacrodDef, Session, SessionDef,
+    SessionOrName, SessionType, Type, TypeOrName,
+};
+use crate::codegen::formatter::{Formatter, Style};
+use crate::codegen::{format_idl, Codegen};
+use crate::config::Config;
+use crate::diagnostic::{self, Severity, Span};
+use crate::ir::{lower, Unit};
+use crate::parser::{parse_recovering, Node, KEYWORDS};
+use crate::type_checker::{check, warnings};
+
+pub fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    }
+}
+
+/// serves `connection` until the client shuts it down
+pub fn run(connection: Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    let mut server = Server::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    return Ok(());
+                }
+                connection
+                    .sender
+                    .send(Message::Response(server.request(req)))?;
+            }
+            Message::Notification(n) => {
+                for n in server.notification(n) {
+                    connection.sender.send(Message::Notification(n))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct Server {
+    /// keyed by URI
+    documents: HashMap<String, Document>,
+}
+
+#[derive(Debug)]
+struct Document {
+    uri: Uri,
+    text: String,
+    /// definitions of the text, and of earlier versions while a definition
+    /// is too broken to parse
+    names: Vec<(String, CompletionItemKind)>,
+}
+
+impl Server {
+    pub fn request(&self, req: Request) -> Response {
+        match req.method.as_str() {
+            GotoDefinition::METHOD => self.handle::<GotoDefinition>(req, |doc, params| {
+                let pos = params.text_document_position_params.position;
+                definition(&doc.text, pos).map(|range| {
+                    GotoDefinitionResponse::Scalar(Location::new(doc.uri.clone(), range))
+                })
+            }),
+            References::METHOD => self.handle::<References>(req, |doc, params| {
+                let pos = params.text_document_position.position;
+                let ranges = references(&doc.text, pos, params.context.include_declaration);
+                Some(
+                    ranges
+                        .into_iter()
+                        .map(|range| Location::new(doc.uri.clone(), range))
+                        .collect(),
+                )
+            }),
+            HoverRequest::METHOD => self.handle::<HoverRequest>(req, |doc, params| {
+                hover(&doc.text, params.text_document_position_params.position)
+            }),
+            DocumentSymbolRequest::METHOD => self.handle::<DocumentSymbolRequest>(req, |doc, _| {
+                symbols(&doc.text).map(DocumentSymbolResponse::Nested)
+            }),
+            Completion::METHOD => self.handle::<Completion>(req, |doc, _| {
+                Some(CompletionResponse::Array(completion(&doc.names)))
+            }),
+            Formatting::METHOD => self.handle::<Formatting>(req, |doc, params| {
+                let style = file_path(&doc.uri)
+                    .and_then(|path| Config::discover(path.parent()?).ok().flatten())
+                    .map(|config| config.format)
+                    .unwrap_or_else(|| Style {
+                        tab_size: params.options.tab_size as usize,
+                        ..Style::default()
+                    });
+                formatting(&doc.text, style)
+            }),
+            _ => Response::new_err(
+                req.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unknown method `{}`", req.method),
+            ),
+        }
+    }
+
+    /// runs `f` on the document the request is about
+    fn handle<R>(&self, req: Request, f: impl FnOnce(&Document, R::Params) -> R::Result) -> Response
+    where
+        R: LspRequest,
+        R::Params: HasDocument,
+    {
+        let params = match serde_json::from_value::<R::Params>(req.params) {
+            Ok(params) => params,
+            Err(e) => {
+                return Response::new_err(req.id, ErrorCode::InvalidParams as i32, e.to_string())
+            }
+        };
+        match self.documents.get(params.uri().as_str()) {
+            Some(doc) => Response::new_ok(req.id, f(doc, params)),
+            None => Response::new_err(
+                req.id,
+                ErrorCode::InvalidParams as i32,
+                format!("`{}` is not open", params.uri().as_str()),
+            ),
+        }
+    }
+
+    /// the diagnostics to publish
+    pub fn notification(&mut self, n: Notification) -> Vec<Notification> {
+        let uri = match n.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Ok(params) =
+                    n.extract::<lsp_types::DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD)
+                else {
+                    return vec![];
+                };
+                let doc = params.text_document;
+                self.update(doc.uri.clone(), doc.text);
+                doc.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let Ok(params) = n.extract::<lsp_types::DidChangeTextDocumentParams>(
+                    DidChangeTextDocument::METHOD,
+                ) else {
+                    return vec![];
+                };
+                // full sync, the last change is the whole text
+                let Some(change) = params.content_changes.into_iter().last() else {
+                    return vec![];
+                };
+                self.update(params.text_document.uri.clone(), change.text);
+                params.text_document.uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let Ok(params) = n
+                    .extract::<lsp_types::DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD)
+                else {
+                    return vec![];
+                };
+                let uri = params.text_document.uri;
+                self.documents.remove(uri.as_str());
+                return vec![publish(uri, vec![])];
+            }
+            _ => return vec![],
+        };
+        let doc = &self.documents[uri.as_str()];
+        vec![publish(uri.clone(), diagnostics(&doc.uri, &doc.text))]
+    }
+
+    fn update(&mut self, uri: Uri, text: String) {
+        let parsed = parse_recovering(&text);
+        let mut names = parsed
+            .defs()
+            .map(|MacrodDef(def)| (def.body.get_name().to_string(), completion_kind(&def.body)))
+            .collect::<Vec<_>>();
+        if !parsed.errors.is_empty() {
+            // the definitions being edited may be skipped
+            let old = self.documents.remove(uri.as_str());
+            for name in old.into_iter().flat_map(|doc| doc.names) {
+                if !names.iter().any(|(n, _)| *n == name.0) {
+                    names.push(name);
+                }
+            }
+        }
+        self.documents
+            .insert(uri.as_str().to_string(), Document { uri, text, names });
+    }
+}
+
+/// request parameters naming a document
+trait HasDocument {
+    fn uri(&self) -> &Uri;
+}
+
+macro_rules! has_document {
+    ($($params:ty => |$p:ident| $uri:expr),* $(,)?) => {
+        $(impl HasDocument for $params {
+            fn uri(&self) -> &Uri {
+                let $p = self;
+                &$uri
+            }
+        })*
+    };
+}
+
+has_document! {
+    lsp_types::GotoDefinitionParams => |p| p.text_document_position_params.text_document.uri,
+    lsp_types::ReferenceParams => |p| p.text_document_position.text_document.uri,
+    lsp_types::HoverParams => |p| p.text_document_position_params.text_document.uri,
+    lsp_types::DocumentSymbolParams => |p| p.text_document.uri,
+    lsp_types::CompletionParams => |p| p.text_document_position.text_document.uri,
+    lsp_types::DocumentFormattingParams => |p| p.text_document.uri,
+}
+
+fn publish(uri: Uri, diagnostics: Vec<lsp_types::Diagnostic>) -> Notification {
+    Notification::new(
+        PublishDiagnostics::METHOD.to_string(),
+        PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        },
+    )
+}
+
+fn file_path(uri: &Uri) -> Option<PathBuf> {
+    if uri.scheme()?.as_str() != "file" {
+        return None;
+    }
+    let path = uri.path().as_estr().decode().into_bytes();
+    Some(PathBuf::from(String::from_utf8_lossy(&path).into_owned()))
+}
+
+/// the LSP position, in UTF-16 code units, of byte `offset`
+pub fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+/// the byte offset of an LSP position, clamped to its line
+pub fn offset(text: &str, position: Position) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum();
+    let line = text[line_start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+fn range(text: &str, span: Span) -> Range {
+    Range::new(
+        position(text, span.start as usize),
+        position(text, span.end as usize),
+    )
+}
+
+/// every syntax error, or the type error and warnings of a text that parses
+pub fn diagnostics(uri: &Uri, text: &str) -> Vec<lsp_types::Diagnostic> {
+    let parsed = parse_recovering(text);
+    if !parsed.errors.is_empty() {
+        return parsed
+            .errors
+            .into_iter()
+            .map(|d| lsp_diagnostic(uri, text, d))
+            .collect();
+    }
+    let defs = parsed.defs().cloned().collect::<Vec<_>>();
+    let ir = lower(&[Unit::new(text, &defs)]);
+    check(&ir)
+        .err()
+        .map(|e| e.diagnostic(&ir))
+        .into_iter()
+        .chain(warnings(&ir))
+        .map(|d| lsp_diagnostic(uri, text, d))
+        .collect()
+}
+
+/// the definitions of `text`, as far as it parses
+fn defs(text: &str) -> Vec<MacrodDef<'_>> {
+    parse_recovering(text)
+        .defs
+        .into_iter()
+        .filter_map(Node::into_def)
+        .collect()
+}
+
+fn lsp_diagnostic(uri: &Uri, text: &str, d: diagnostic::Diagnostic) -> lsp_types::Diagnostic {
+    let mut message = d.message;
+    if let Some(fix) = &d.fix {
+        message = format!("{}\n{}", message, fix.message);
+    }
+    lsp_types::Diagnostic {
+        range: d.span.map(|span| range(text, span)).unwrap_or_default(),
+        severity: Some(match d.severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Note => DiagnosticSeverity::INFORMATION,
+        }),
+        code: Some(NumberOrString::String(d.code.to_string())),
+        source: Some(env!("CARGO_PKG_NAME").to_string()),
+        message,
+        related_information: Some(
+            d.related
+                .into_iter()
+                .map(|r| DiagnosticRelatedInformation {
+                    location: Location::new(uri.clone(), range(text, r.span)),
+                    message: r.message,
+                })
+                .collect(),
+        )
+        .filter(|related: &Vec<_>| !related.is_empty()),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+/// a name in the source, of a definition or referring to one
+#[derive(Debug, Clone, Copy)]
+struct Name<'a> {
+    name: &'a str,
+    def: bool,
+}
+
+/// every definition name and every reference to one in `defs`, generic
+/// parameters are left out
+fn names<'a>(defs: &[MacrodDef<'a>]) -> Vec<Name<'a>> {
+    let mut names = Names {
+        names: vec![],
+        params: &[],
+    };
+    for MacrodDef(def) in defs {
+        names.def(&def.body);
+    }
+    names.names
+}
+
+struct Names<'a, 'p> {
+    names: Vec<Name<'a>>,
+    params: &'p [&'a str],
+}
+
+impl<'a, 'p> Names<'a, 'p> {
+    fn def(&mut self, def: &'p Def<'a>) {
+        let (name, params) = match def {
+            Def::SessionDef(d) => (d.name, &d.generics[..]),
+            Def::StructDef(d) => (d.name, &d.generics[..]),
+            Def::EnumDef(d) => (d.name, &d.generics[..]),
+            Def::ConstDef(d) => (d.name, &[][..]),
+            Def::TypeDef(d) => (d.name, &d.generics[..]),
+        };
+        self.names.push(Name { name, def: true });
+        self.params = params;
+        match def {
+            Def::SessionDef(d) => self.session(&d.session.body),
+            Def::StructDef(d) => d.items.iter().for_each(|i| self.ty(&i.body.1)),
+            Def::EnumDef(d) => d.items.iter().for_each(|i| self.ty(&i.body.1)),
+            Def::ConstDef(d) => self.ty(&d.ty),
+            Def::TypeDef(d) => self.ty(&d.ty),
+        }
+    }
+
+    fn name(&mut self, name: &'a str) {
+        if !self.params.contains(&name) {
+            self.names.push(Name { name, def: false });
+        }
+    }
+
+    fn ty(&mut self, ty: &TypeOrName<'a>) {
+        match ty {
+            TypeOrName::Name(n) => self.name(n),
+            TypeOrName::Generic(n, args) => {
+                self.name(n);
+                args.iter().for_each(|a| self.ty(a));
+            }
+            TypeOrName::Type(t) => match t.as_ref() {
+                Type::SessionType(s) => self.session(s),
+                Type::ContainerType(ContainerType::Array(t, len)) => {
+                    if let Some(ArrayLength::Const(n)) = len {
+                        self.name(n);
+                    }
+                    self.ty(t);
+                }
+                Type::ContainerType(ContainerType::Dict(_, t) | ContainerType::Option(t)) => {
+                    self.ty(t)
+                }
+                Type::Struct(s) => s.items.iter().for_each(|i| self.ty(&i.body.1)),
+                Type::Enum(e) => e.items.iter().for_each(|i| self.ty(&i.body.1)),
+                Type::TypeDef(t) => self.ty(&t.ty),
+                Type::SimpleType(_) | Type::Constant(_) => {}
+            },
+        }
+    }
+
+    fn session(&mut self, s: &SessionType<'a>) {
+        for m in s.0.iter() {
+            match m.body.as_ref() {
+                Session::Recv(t) | Session::Send(t) => self.ty(t),
+                Session::Offer(u) | Session::Choose(u) => {
+                    for s in u.0.iter() {
+                        match s {
+                            SessionOrName::Name(n) => self.name(n),
+                            SessionOrName::Generic(n, args) => {
+                                self.name(n);
+                                args.iter().for_each(|a| self.ty(a));
+                            }
+                            SessionOrName::Session(s) => self.session(s),
+                        }
+                    }
+                }
+                Session::Endpoint => {}
+            }
+        }
+    }
+}
+
+/// the definition or reference under `pos`
+fn name_at<'a>(text: &'a str, defs: &[MacrodDef<'a>], pos: Position) -> Option<&'a str> {
+    let at = offset(text, pos);
+    names(defs)
+        .into_iter()
+        .find(|n| {
+            Span::of(text, n.name)
+                .is_some_and(|span| span.range().contains(&at) || span.end as usize == at)
+        })
+        .map(|n| n.name)
+}
+
+pub fn definition(text: &str, pos: Position) -> Option<Range> {
+    let defs = defs(text);
+    let name = name_at(text, &defs, pos)?;
+    let def = defs.iter().find(|MacrodDef(d)| d.body.get_name() == name)?;
+    Some(range(text, Span::of(text, def.0.body.get_name())?))
+}
+
+pub fn references(text: &str, pos: Position, include_declaration: bool) -> Vec<Range> {
+    let defs = defs(text);
+    let Some(name) = name_at(text, &defs, pos) else {
+        return vec![];
+    };
+    names(&defs)
+        .into_iter()
+        .filter(|n| n.name == name && (include_declaration || !n.def))
+        .filter_map(|n| Some(range(text, Span::of(text, n.name)?)))
+        .collect()
+}
+
+/// the definition, its docs and, for channels, the dual session
+pub fn hover(text: &str, pos: Position) -> Option<Hover> {
+    let defs = defs(text);
+    let name = name_at(text, &defs, pos)?;
+    let MacrodDef(def) = defs.iter().find(|MacrodDef(d)| d.body.get_name() == name)?;
+    let mut fmt = Formatter::new(Style::default());
+    let mut value = format!("```limit\n{}\n```", def.body.generate(&mut fmt).ok()?);
+    let docs = def
+        .appends
+        .iter()
+        .filter_map(|a| match a {
+            Append::DocsComment(s) => Some(s.trim().trim_start_matches("///").trim()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if !docs.is_empty() {
+        value.push_str("\n\n");
+        value.push_str(&docs.join("\n"));
+    }
+    if let Def::SessionDef(d) = def.body.as_ref() {
+        let dual = SessionDef {
+            name: d.name,
+            generics: d.generics.clone(),
+            session: Macro {
+                appends: vec![],
+                body: Box::new(d.session.body.dual()),
+            },
+        };
+        value.push_str(&format!(
+            "\n\ndual:\n```limit\n{}\n```",
+            dual.generate(&mut fmt).ok()?
+        ));
+    }
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: Span::of(text, name).map(|span| range(text, span)),
+    })
+}
+
+#[allow(deprecated)]
+pub fn symbols(text: &str) -> Option<Vec<DocumentSymbol>> {
+    let defs = defs(text);
+    let symbol = |name: &str, kind, children: Option<Vec<DocumentSymbol>>| {
+        let range = range(text, Span::of(text, name)?);
+        Some(DocumentSymbol {
+            name: name.to_string(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range,
+            selection_range: range,
+            children,
+        })
+    };
+    let symbols = defs
+        .iter()
+        .filter_map(|MacrodDef(def)| {
+            let (kind, children) = match def.body.as_ref() {
+                Def::SessionDef(_) => (SymbolKind::INTERFACE, None),
+                Def::StructDef(d) => (
+                    SymbolKind::STRUCT,
+                    Some(
+                        d.items
+                            .iter()
+                            .filter_map(|i| symbol(i.body.0, SymbolKind::FIELD, None))
+                            .collect(),
+                    ),
+                ),
+                Def::EnumDef(d) => (
+                    SymbolKind::ENUM,
+                    Some(
+                        d.items
+                            .iter()
+                            .filter_map(|i| symbol(i.body.0, SymbolKind::ENUM_MEMBER, None))
+                            .collect(),
+                    ),
+                ),
+                Def::ConstDef(_) => (SymbolKind::CONSTANT, None),
+                Def::TypeDef(_) => (SymbolKind::TYPE_PARAMETER, None),
+            };
+            symbol(def.body.get_name(), kind, children)
+        })
+        .collect();
+    Some(symbols)
+}
+
+fn completion_kind(def: &Def) -> CompletionItemKind {
+    match def {
+        Def::SessionDef(_) => CompletionItemKind::INTERFACE,
+        Def::StructDef(_) => CompletionItemKind::STRUCT,
+        Def::EnumDef(_) => CompletionItemKind::ENUM,
+        Def::ConstDef(_) => CompletionItemKind::CONSTANT,
+        Def::TypeDef(_) => CompletionItemKind::TYPE_PARAMETER,
+    }
+}
+
+/// keywords, builtin types and the definitions of the document
+fn completion(names: &[(String, CompletionItemKind)]) -> Vec<CompletionItem> {
+    KEYWORDS
+        .iter()
+        .map(|k| (k.to_string(), CompletionItemKind::KEYWORD))
+        .chain(names.iter().cloned())
+        .map(|(label, kind)| CompletionItem {
+            label,
+            kind: Some(kind),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+/// one edit replacing the whole text, none if it's formatted or broken
+pub fn formatting(text: &str, style: Style) -> Option<Vec<TextEdit>> {
+    let formatted = format_idl(text, &mut Formatter::new(style)).ok()?;
+    if formatted == text {
+        return Some(vec![]);
+    }
+    Some(vec![TextEdit::new(
+        Range::new(Position::new(0, 0), position(text, text.len())),
+        formatted,
+    )])
+}
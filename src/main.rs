@@ -1,14 +1,28 @@
 use std::{
-    cell::{Cell, RefCell},
-    ffi::OsString,
-    fs::{metadata, read_dir, File},
-    io::{Read, Write},
-    path::Path,
-    rc::Rc,
+    env::current_dir,
+    fs::File,
+    io::{stdin, stdout, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use clap::Parser;
-use limit_stream::codegen::{format_idl, formatter::Formatter, idl2rust, rust::Rust};
+use limit_stream::{
+    build::{Build, Outcome, Report},
+    codegen::{format_idl, formatter::Formatter, rust::Rust},
+    config::Config,
+    diagnostic::{sarif, Diagnostic, Fix, MessageFormat, Span},
+    ir::{lower, Ir, Unit},
+    parser::{parse_recovering, Node},
+    runtime::{
+        monitor::{Protocol, Side},
+        trace::{read_trace, replay as replay_trace, validate},
+    },
+    type_checker::{check, warnings},
+    watch::Watch,
+};
+use similar::TextDiff;
+use tokio::net::TcpStream;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "
@@ -24,14 +38,44 @@ also it is type driving by using session type for the classes defined in FILE.
 enum Limitsc {
     #[command(about = "generate code from IDL file")]
     CodeGen {
-        #[arg(short, long)]
-        lang: String,
-        #[arg(short, long, default_value_t = String::from("client"), help = "client | server | all")]
-        gen_mode: String,
-        #[arg(short, long, default_value_t = String::from("."), help = "IDL path directory")]
-        idl_path: String,
-        #[arg(short, long, default_value_t = String::from(""), help = "output path directory")]
-        out_path: String,
+        #[arg(
+            short,
+            long,
+            help = "target language, defaults to every `[codegen.<lang>]` of the config"
+        )]
+        lang: Option<String>,
+        #[arg(short, long, help = "client | server | all | mock")]
+        gen_mode: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "IDL path directory, defaults to the config's roots or `.`"
+        )]
+        idl_path: Option<String>,
+        #[arg(short, long, help = "output path directory")]
+        out_path: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "config file, defaults to the nearest `limit.toml`"
+        )]
+        config: Option<String>,
+        #[arg(long, help = "glob of IDL files to take, relative to the input path")]
+        include: Vec<String>,
+        #[arg(long, help = "glob of IDL files to skip, relative to the input path")]
+        exclude: Vec<String>,
+        #[arg(
+            short,
+            long,
+            help = "regenerate the outputs of changed IDL files until interrupted"
+        )]
+        watch: bool,
+        #[arg(
+            long,
+            default_value_t = String::from("human"),
+            help = "human | json | sarif"
+        )]
+        message_format: String,
         // #[arg(short, long, help = "entry file")]
         // file: String,
     },
@@ -41,70 +85,345 @@ enum Limitsc {
         path: String,
         #[arg(short, long)]
         file: String,
+        #[arg(
+            long,
+            default_value_t = String::from("human"),
+            help = "human | json | sarif"
+        )]
+        message_format: String,
     },
     #[command(about = "format IDL file")]
     Format {
-        #[arg(short, long, default_value_t = 4, help = "indent size")]
-        indent: usize,
-        #[arg(short, long, default_value_t = String::from("."), help = "IDL path directory")]
+        #[arg(short, long, help = "indent size, overrides the config")]
+        indent: Option<usize>,
+        #[arg(
+            short,
+            long,
+            help = "config file, defaults to the nearest `limit.toml`"
+        )]
+        config: Option<String>,
+        #[arg(short, long, default_value_t = String::from("."), help = "IDL path directory, `-` for stdin")]
         path: String,
+        #[arg(
+            long,
+            help = "list unformatted files and exit non-zero instead of writing"
+        )]
+        check: bool,
+        #[arg(long, help = "print a unified diff instead of writing")]
+        diff: bool,
+        #[arg(long, help = "glob of IDL files to take, relative to the input path")]
+        include: Vec<String>,
+        #[arg(long, help = "glob of IDL files to skip, relative to the input path")]
+        exclude: Vec<String>,
+        #[arg(
+            long,
+            default_value_t = String::from("human"),
+            help = "human | json | sarif"
+        )]
+        message_format: String,
+    },
+    #[command(about = "check a recorded session trace, or play one side of it")]
+    Replay {
+        #[arg(short, long, help = "IDL file")]
+        file: String,
+        #[arg(short, long, help = "channel the trace follows")]
+        channel: String,
+        #[arg(help = "trace file, one JSON event per line")]
+        trace: String,
+        #[arg(long, help = "client | server, the side to play against --connect")]
+        play: Option<String>,
+        #[arg(long, help = "address of the implementation under test")]
+        connect: Option<String>,
     },
 }
 
-pub fn rust_codegen_file(mut rs: Rust, idl_path: &Path, out_path: &Path) -> std::io::Result<()> {
+/// prints diagnostics in the `--message-format`
+struct Emitter {
+    format: MessageFormat,
+    /// SARIF is one log for every diagnostic
+    pending: Vec<Diagnostic>,
+    failed: bool,
+}
+
+impl Emitter {
+    fn new(format: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            format: format.parse().map_err(std::io::Error::other)?,
+            pending: vec![],
+            failed: false,
+        })
+    }
+
+    fn emit(&mut self, diagnostic: Diagnostic) {
+        self.failed |= diagnostic.is_error();
+        match self.format {
+            MessageFormat::Human => eprintln!("{}", diagnostic),
+            MessageFormat::Json => println!("{}", diagnostic.to_json()),
+            MessageFormat::Sarif => self.pending.push(diagnostic),
+        }
+    }
+
+    /// errors always, and the outputs as they change in watch mode
+    fn report(&mut self, report: Report, watch: bool) {
+        match report.outcome {
+            Outcome::Failed(diagnostic) => self.emit(diagnostic),
+            _ if watch && self.format == MessageFormat::Human => println!("{}", report),
+            _ => {}
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.format == MessageFormat::Sarif {
+            println!("{:#}", sarif(&self.pending));
+            self.pending.clear();
+        }
+    }
+}
+
+fn load_config(path: Option<String>) -> std::io::Result<Config> {
+    match path {
+        Some(path) => Config::load(Path::new(&path)),
+        None => Config::discover(&current_dir()?).map(Option::unwrap_or_default),
+    }
+    .map_err(std::io::Error::other)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FormatMode {
+    check: bool,
+    diff: bool,
+}
+
+impl FormatMode {
+    fn write(&self) -> bool {
+        !self.check && !self.diff
+    }
+}
+
+/// returns whether `path` was already formatted, or couldn't be parsed
+fn format_file(
+    mut fmt: Formatter,
+    path: &Path,
+    mode: FormatMode,
+    emitter: &mut Emitter,
+) -> std::io::Result<bool> {
     let mut src = String::new();
     {
-        let mut f = File::open(idl_path)?;
+        let mut f = File::open(path)?;
         f.read_to_string(&mut src)?;
     }
-    let code = idl2rust(&src, &mut rs);
-    let mut f = File::options()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(out_path)?;
+    let formated_src = match format_idl(&src, &mut fmt) {
+        Ok(formated_src) => formated_src,
+        Err(d) => {
+            emitter.emit(d.in_file(path));
+            return Ok(true);
+        }
+    };
+    if formated_src == src {
+        return Ok(true);
+    }
+    let name = path.display().to_string();
+    if emitter.format != MessageFormat::Human {
+        if !mode.write() {
+            emitter.emit(unformatted(&src, &formated_src).in_file(path));
+        }
+    } else {
+        if mode.diff {
+            print!("{}", diff(&src, &formated_src, &name));
+        }
+        if mode.check {
+            println!("{}", name);
+        }
+    }
+    if mode.write() {
+        let mut f = File::options()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        let _ = f.write(formated_src.as_bytes())?;
+    }
+    Ok(false)
+}
 
-    let _ = f.write(code.as_bytes())?;
-    Ok(())
+fn format_stdin(
+    mut fmt: Formatter,
+    mode: FormatMode,
+    emitter: &mut Emitter,
+) -> std::io::Result<bool> {
+    let mut src = String::new();
+    stdin().read_to_string(&mut src)?;
+    let formated_src = match format_idl(&src, &mut fmt) {
+        Ok(formated_src) => formated_src,
+        Err(d) => {
+            emitter.emit(d);
+            return Ok(true);
+        }
+    };
+    let formatted = formated_src == src;
+    if emitter.format != MessageFormat::Human {
+        if !mode.write() && !formatted {
+            emitter.emit(unformatted(&src, &formated_src));
+        }
+    } else {
+        if mode.diff {
+            print!("{}", diff(&src, &formated_src, "<stdin>"));
+        }
+        if mode.check && !formatted {
+            println!("<stdin>");
+        }
+    }
+    if mode.write() {
+        stdout().write_all(formated_src.as_bytes())?;
+    }
+    Ok(formatted)
 }
 
-fn format_file(mut fmt: Formatter, path: &Path) -> std::io::Result<()> {
+/// points at the first change, the fix replaces the whole file
+fn unformatted(src: &str, formated_src: &str) -> Diagnostic {
+    let start = src
+        .char_indices()
+        .zip(formated_src.chars())
+        .find(|((_, a), b)| a != b)
+        .map_or(src.len().min(formated_src.len()), |((i, _), _)| i);
+    Diagnostic::warning("unformatted", "file is not formatted")
+        .with_span(Some(Span::new(src, start, start)))
+        .with_fix(Fix {
+            message: "format the file".to_string(),
+            span: Span::new(src, 0, src.len()),
+            replacement: formated_src.to_string(),
+        })
+}
+
+fn diff(src: &str, formated_src: &str, name: &str) -> String {
+    TextDiff::from_lines(src, formated_src)
+        .unified_diff()
+        .header(name, name)
+        .to_string()
+}
+
+/// the checked IR of the IDL file at `path`, diagnostics are printed
+fn load_ir(path: &Path) -> std::io::Result<Option<Ir>> {
+    let mut emitter = Emitter::new("human")?;
     let mut src = String::new();
-    {
-        let mut f = File::open(path.clone())?;
-        f.read_to_string(&mut src)?;
+    File::open(path)?.read_to_string(&mut src)?;
+    let parsed = parse_recovering(&src);
+    for d in parsed.errors.iter() {
+        emitter.emit(d.clone().in_file(path));
     }
-    let formated_src = format_idl(&src, &mut fmt);
-    let mut f = File::options()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(path)?;
-    let _ = f.write(formated_src.as_bytes())?;
-    Ok(())
+    if emitter.failed {
+        return Ok(None);
+    }
+    let defs = parsed
+        .defs
+        .into_iter()
+        .filter_map(Node::into_def)
+        .collect::<Vec<_>>();
+    let ir = lower(&[Unit::new(&src, &defs).in_file(path)]);
+    if let Err(e) = check(&ir) {
+        emitter.emit(e.diagnostic(&ir));
+        return Ok(None);
+    }
+    Ok(Some(ir))
+}
+
+fn replay(
+    file: &str,
+    channel: &str,
+    trace: &str,
+    play: Option<String>,
+    connect: Option<String>,
+) -> std::io::Result<bool> {
+    let Some(ir) = load_ir(Path::new(file))? else {
+        return Ok(false);
+    };
+    let def = match Protocol::from_ir(&ir, channel) {
+        Ok(def) => Arc::new(def),
+        Err(e) => {
+            eprintln!("{}", e.diagnostic(&ir));
+            return Ok(false);
+        }
+    };
+    let events = read_trace(BufReader::new(File::open(trace)?))?;
+    let side = match play.as_deref() {
+        None => {
+            return Ok(match validate(def, &events) {
+                Ok(()) => {
+                    println!("{}: {} events follow `{}`", trace, events.len(), channel);
+                    true
+                }
+                Err((i, e)) => {
+                    eprintln!("{}: event {}: {}", trace, i, e);
+                    false
+                }
+            })
+        }
+        Some(side) => side.parse::<Side>().map_err(std::io::Error::other)?,
+    };
+    let Some(addr) = connect else {
+        return Err(std::io::Error::other("--play needs --connect"));
+    };
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    let res = rt.block_on(async {
+        let io = TcpStream::connect(&addr).await?;
+        replay_trace(def, &events, side, io).await
+    });
+    Ok(match res {
+        Ok(differs) => {
+            for i in differs.iter() {
+                println!("{}: event {}: answer differs from the trace", trace, i);
+            }
+            println!(
+                "{}: replayed {} events against {}",
+                trace,
+                events.len(),
+                addr
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("{}: {}", trace, e);
+            false
+        }
+    })
 }
 
 fn main() -> std::io::Result<()> {
     let args = Limitsc::parse();
     match args {
-        Limitsc::Format { indent, path } => {
-            let fmt = Formatter {
-                tab_size: indent,
-                indent: 0,
-            };
-            let pathinfo = metadata(path.clone())?;
-            if pathinfo.file_type().is_dir() {
-                let dir = read_dir(path)?;
-                for i in dir.flatten() {
-                    if i.file_type()?.is_file()
-                        && i.path().extension().expect("invalid extension name")
-                            == Into::<OsString>::into("lstr".to_string())
-                    {
-                        format_file(fmt.clone(), i.path().as_path())?;
-                    }
-                }
+        Limitsc::Format {
+            indent,
+            config,
+            path,
+            include,
+            exclude,
+            check,
+            diff,
+            message_format,
+        } => {
+            let mut emitter = Emitter::new(&message_format)?;
+            let config = load_config(config)?;
+            let mut fmt = Formatter::new(config.format.clone());
+            if let Some(indent) = indent {
+                fmt.style.tab_size = indent;
+            }
+            let mode = FormatMode { check, diff };
+            let mut formatted = true;
+            if path == "-" {
+                formatted = format_stdin(fmt, mode, &mut emitter)?;
             } else {
-                format_file(fmt, Path::new(&path))?;
+                let sources = config
+                    .sources(&include, &exclude)
+                    .map_err(std::io::Error::other)?;
+                for source in sources.files(Path::new(&path))? {
+                    formatted &= format_file(fmt.clone(), &source.path, mode, &mut emitter)?;
+                }
+            }
+            emitter.flush();
+            if emitter.failed || check && !formatted {
+                std::process::exit(1);
             }
         }
         Limitsc::CodeGen {
@@ -112,55 +431,127 @@ fn main() -> std::io::Result<()> {
             gen_mode,
             idl_path,
             out_path,
+            config,
+            include,
+            exclude,
+            watch,
+            message_format,
         } => {
-            match lang.as_str() {
-                "rust" => {
-                    let rust = Rust {
-                        tab_size: 2,
-                        indent: 0,
-                        enum_id: Rc::new(Cell::new(0)),
-                        codegen_regester: Rc::new(RefCell::new(vec![])),
+            let mut emitter = Emitter::new(&message_format)?;
+            let config = load_config(config)?;
+            let sources = config
+                .sources(&include, &exclude)
+                .map_err(std::io::Error::other)?;
+            let langs = match lang {
+                Some(lang) => vec![lang],
+                None => config.codegen.keys().cloned().collect(),
+            };
+            if langs.is_empty() {
+                return Err(std::io::Error::other(
+                    "no target language, pass --lang or add a [codegen.<lang>] table to limit.toml",
+                ));
+            }
+            let gen_mode = match gen_mode {
+                Some(mode) => mode.parse().map_err(std::io::Error::other)?,
+                None => config.project.gen_mode,
+            };
+            let inputs = match idl_path {
+                Some(path) => vec![PathBuf::from(path)],
+                None if !config.project.roots.is_empty() => config.roots(),
+                None => vec![PathBuf::from(".")],
+            };
+            let mut builds = vec![];
+            for lang in langs {
+                let target = config.target(&lang);
+                let out = out_path
+                    .clone()
+                    .map(PathBuf::from)
+                    .or(target.out_dir.clone());
+                match lang.as_str() {
+                    "rust" => builds.push(Build {
+                        rust: Rust::new(&target, gen_mode),
+                        inputs: inputs.clone(),
+                        out,
+                        sources: sources.clone(),
+                    }),
+                    _ => {
+                        return Err(std::io::Error::other(format!(
+                            "unsupported target language `{}`, expected rust",
+                            lang
+                        )))
+                    }
+                }
+            }
+            for report in builds.iter().flat_map(Build::all) {
+                emitter.report(report, watch);
+            }
+            emitter.flush();
+            if watch {
+                let watcher = Watch::new(&inputs).map_err(std::io::Error::other)?;
+                loop {
+                    let paths = match watcher.next(None) {
+                        Ok(paths) => paths,
+                        Err(e) => {
+                            emitter.emit(Diagnostic::error("watch-error", e.to_string()));
+                            emitter.flush();
+                            continue;
+                        }
                     };
-                    let pathinfo = metadata(idl_path.clone())?;
-                    if pathinfo.file_type().is_dir() {
-                        let dir = read_dir(idl_path.clone())?;
-                        for i in dir.flatten() {
-                            if i.file_type()?.is_file()
-                                && i.path().extension().expect("invalid extension name")
-                                    == Into::<OsString>::into("lstr".to_string())
-                            {
-                                let input_path = Path::new(&idl_path);
-                                let out_path = if out_path.is_empty() {
-                                    input_path
-                                } else {
-                                    Path::new(&out_path)
-                                };
-                                let out_path = out_path.with_file_name(i.file_name());
-                                // format("{}/{}")
-                                rust_codegen_file(
-                                    rust.clone(),
-                                    i.path().as_path(),
-                                    out_path.as_path(),
-                                )?;
-                            }
+                    for build in builds.iter() {
+                        for report in build.update(paths.iter().map(PathBuf::as_path)) {
+                            emitter.report(report, watch);
                         }
-                    } else {
-                        let idl_path = Path::new(&idl_path);
-                        let gen_out_path = idl_path.with_extension("rs");
-                        let out_path = if out_path.is_empty() {
-                            gen_out_path.as_path()
-                        } else {
-                            Path::new(&out_path)
-                        };
-                        dbg!(idl_path);
-                        dbg!(out_path);
-                        rust_codegen_file(rust.clone(), idl_path, out_path)?;
                     }
+                    emitter.flush();
                 }
-                _ => unimplemented!("unimplemented codegen target"),
+            }
+            if emitter.failed {
+                std::process::exit(1);
+            }
+        }
+        Limitsc::Replay {
+            file,
+            channel,
+            trace,
+            play,
+            connect,
+        } => {
+            if !replay(&file, &channel, &trace, play, connect)? {
+                std::process::exit(1);
+            }
+        }
+        Limitsc::TypeCheck {
+            path,
+            file,
+            message_format,
+        } => {
+            let mut emitter = Emitter::new(&message_format)?;
+            let path = Path::new(&path).join(file);
+            let mut src = String::new();
+            File::open(&path)?.read_to_string(&mut src)?;
+            let parsed = parse_recovering(&src);
+            if parsed.errors.is_empty() {
+                let defs = parsed
+                    .defs
+                    .into_iter()
+                    .filter_map(Node::into_def)
+                    .collect::<Vec<_>>();
+                let ir = lower(&[Unit::new(&src, &defs).in_file(&path)]);
+                if let Err(e) = check(&ir) {
+                    emitter.emit(e.diagnostic(&ir));
+                }
+                for d in warnings(&ir) {
+                    emitter.emit(d);
+                }
+            }
+            for d in parsed.errors {
+                emitter.emit(d.in_file(&path));
+            }
+            emitter.flush();
+            if emitter.failed {
+                std::process::exit(1);
             }
         }
-        Limitsc::TypeCheck { path, file } => todo!(),
     }
     Ok(())
 }
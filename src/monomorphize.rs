@@ -0,0 +1,201 @@
+//! Monomorphization
+//!
+//! Rewrites generic definitions into one concrete definition per
+//! instantiation, for backends whose target language has no generics.
+//! `Page<User>` becomes a reference to a new `struct Page_User`.
+
+use std::collections::HashMap;
+
+use crate::ast::GetName;
+use crate::ir::{
+    Branch, Def, DefKind, Field, Ir, Length, Ref, Session, SessionType, Symbol, Target, Ty, Variant,
+};
+use crate::type_checker::{Error, Name};
+
+type Env = HashMap<Symbol, Ty>;
+
+/// a copy of `ir` without generic definitions, every instance is defined
+/// before its first user
+pub fn monomorphize(ir: &Ir) -> Result<Ir, Error> {
+    let mut mono = Monomorphizer {
+        ir,
+        out: ir.without_defs(),
+        instances: HashMap::new(),
+    };
+    for (_, def) in ir.defs().filter(|(_, def)| def.generics.is_empty()) {
+        let def = mono.def(def, def.name, &Env::new())?;
+        mono.out.push(def);
+    }
+    mono.out.bind();
+    Ok(mono.out)
+}
+
+struct Monomorphizer<'i> {
+    ir: &'i Ir,
+    out: Ir,
+    instances: HashMap<String, Symbol>,
+}
+
+impl Monomorphizer<'_> {
+    /// emit `name<args>` once and return the name of the instance
+    fn instantiate(&mut self, r: &Ref, args: Vec<Ty>) -> Result<Symbol, Error> {
+        let Target::Def(id) = r.target else {
+            return Err(Error::NameIsNotFound(Name::of(self.ir, r)));
+        };
+        let def = self.ir.def(id);
+        if def.generics.len() != args.len() {
+            return Err(Error::ArityMismatch {
+                name: Name::of(self.ir, r),
+                expected: def.generics.len(),
+                found: args.len(),
+            });
+        }
+        let mangled = args
+            .iter()
+            .map(|a| {
+                self.mangle(a)
+                    .ok_or(Error::CannotMonomorphize(Name::of(self.ir, r)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mangled = format!("{}_{}", self.ir.name(r.name), mangled.join("_"));
+        if let Some(instance) = self.instances.get(&mangled) {
+            return Ok(*instance);
+        }
+        let instance = self.out.intern(&mangled);
+        self.instances.insert(mangled, instance);
+
+        let env = def.generics.iter().copied().zip(args).collect();
+        let def = self.def(def, instance, &env)?;
+        self.out.push(def);
+        Ok(instance)
+    }
+
+    fn def(&mut self, def: &Def, name: Symbol, env: &Env) -> Result<Def, Error> {
+        let kind = match &def.kind {
+            DefKind::Session(s) => DefKind::Session(self.session_type(s, env)?),
+            DefKind::Struct(fields) => DefKind::Struct(
+                fields
+                    .iter()
+                    .map(|i| {
+                        Ok(Field {
+                            ty: self.ty(&i.ty, env)?,
+                            ..i.clone()
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            DefKind::Enum(variants) => DefKind::Enum(
+                variants
+                    .iter()
+                    .map(|i| {
+                        Ok(Variant {
+                            ty: self.ty(&i.ty, env)?,
+                            ..i.clone()
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            DefKind::Const(ty, value) => DefKind::Const(self.ty(ty, env)?, value.clone()),
+            DefKind::Alias(ty) => DefKind::Alias(self.ty(ty, env)?),
+        };
+        Ok(Def {
+            name,
+            loc: def.loc,
+            generics: vec![],
+            docs: def.docs.clone(),
+            kind,
+        })
+    }
+
+    /// `r` with its arguments substituted, bound by the final `Ir::bind`
+    fn reference(&mut self, r: &Ref, env: &Env) -> Result<Ref, Error> {
+        let name = if r.args.is_empty() {
+            r.name
+        } else {
+            let args = r
+                .args
+                .iter()
+                .map(|a| self.ty(a, env))
+                .collect::<Result<_, _>>()?;
+            self.instantiate(r, args)?
+        };
+        Ok(Ref {
+            name,
+            target: Target::Unresolved,
+            args: vec![],
+            loc: r.loc,
+        })
+    }
+
+    fn ty(&mut self, ty: &Ty, env: &Env) -> Result<Ty, Error> {
+        Ok(match ty {
+            Ty::Ref(r) if r.target == Target::Param => {
+                env.get(&r.name).cloned().unwrap_or_else(|| ty.clone())
+            }
+            Ty::Ref(r) => Ty::Ref(self.reference(r, env)?),
+            Ty::Session(s) => Ty::Session(Box::new(self.session_type(s, env)?)),
+            Ty::Array(t, len) => Ty::Array(
+                Box::new(self.ty(t, env)?),
+                match len {
+                    Some(Length::Const(r)) => Some(Length::Const(self.reference(r, env)?)),
+                    len => len.clone(),
+                },
+            ),
+            Ty::Dict(k, v) => Ty::Dict(*k, Box::new(self.ty(v, env)?)),
+            Ty::Option(t) => Ty::Option(Box::new(self.ty(t, env)?)),
+            Ty::Simple(_) | Ty::Literal(_) => ty.clone(),
+        })
+    }
+
+    fn session_type(&mut self, s: &SessionType, env: &Env) -> Result<SessionType, Error> {
+        let sessions =
+            s.0.iter()
+                .map(|s| {
+                    Ok(match s {
+                        Session::Recv(t) => Session::Recv(self.ty(t, env)?),
+                        Session::Send(t) => Session::Send(self.ty(t, env)?),
+                        Session::Offer(u) => Session::Offer(self.branches(u, env)?),
+                        Session::Choose(u) => Session::Choose(self.branches(u, env)?),
+                        Session::Endpoint => Session::Endpoint,
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+        Ok(SessionType(sessions))
+    }
+
+    fn branches(&mut self, u: &[Branch], env: &Env) -> Result<Vec<Branch>, Error> {
+        u.iter()
+            .map(|b| {
+                Ok(match b {
+                    Branch::Ref(r) if r.target == Target::Param => match env.get(&r.name) {
+                        None => b.clone(),
+                        Some(Ty::Ref(r)) => Branch::Ref(r.clone()),
+                        Some(Ty::Session(s)) => Branch::Session(s.as_ref().clone()),
+                        Some(_) => return Err(Error::CannotMonomorphize(Name::of(self.ir, r))),
+                    },
+                    Branch::Ref(r) => Branch::Ref(self.reference(r, env)?),
+                    Branch::Session(s) => Branch::Session(self.session_type(s, env)?),
+                })
+            })
+            .collect()
+    }
+
+    /// the part of an instance name for the argument `ty`, containers are
+    /// spelled out like `Page<option<[User; 2]>>` as `Page_Option_Array_2_User`
+    fn mangle(&self, ty: &Ty) -> Option<String> {
+        Some(match ty {
+            Ty::Ref(r) if r.args.is_empty() => self.out.name(r.name).to_string(),
+            Ty::Simple(t) => t.get_name().to_string(),
+            Ty::Option(t) => format!("Option_{}", self.mangle(t)?),
+            Ty::Array(t, None) => format!("List_{}", self.mangle(t)?),
+            Ty::Array(t, Some(Length::Literal(len))) => {
+                format!("Array_{}_{}", len, self.mangle(t)?)
+            }
+            Ty::Array(t, Some(Length::Const(r))) => {
+                format!("Array_{}_{}", self.out.name(r.name), self.mangle(t)?)
+            }
+            Ty::Dict(k, v) => format!("Dict_{}_{}", k.get_name(), self.mangle(v)?),
+            _ => return None,
+        })
+    }
+}
@@ -1,17 +1,19 @@
 use nom::branch::alt;
-use nom::bytes::complete::{escaped_transform, is_a, tag};
-use nom::character::complete::{anychar, char, digit1, hex_digit1, oct_digit1};
-use nom::combinator::{cut, map, map_res, not, opt, recognize, value};
+use nom::bytes::complete::{escaped_transform, is_not, tag};
+use nom::character::complete::{anychar, char, digit1, hex_digit1, oct_digit1, satisfy};
+use nom::combinator::{cut, map, map_res, not, opt, recognize, value, verify};
 
-use nom::multi::{many0, many1, many_m_n, separated_list0, separated_list1};
+use nom::multi::{many0, many_m_n, separated_list0, separated_list1};
 
-use nom::sequence::{pair, preceded, terminated, tuple};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::{bytes::complete::take_while, IResult};
 
 use crate::ast::{
-    Annotation, Append, Constant, Def, EnumDef, EnumItem, Macro, MacrodDef, Session, SessionDef,
-    SessionOrName, SessionType, SessionUnion, SimpleType, StructDef, StructItem, Type, TypeOrName,
+    Annotation, AnnotationValue, Append, ArrayLength, ConstDef, Constant, Constraint,
+    ContainerType, Def, EnumDef, EnumItem, Macro, MacrodDef, Session, SessionDef, SessionOrName,
+    SessionType, SessionUnion, SimpleType, StructDef, StructItem, Type, TypeDef, TypeOrName,
 };
+use crate::diagnostic::{Diagnostic, Span};
 
 /*
 #[macro_export]
@@ -28,65 +30,360 @@ macro_rules! macro_gen {
 }
 // */
 
-pub fn parse(i: &str) -> Result<Vec<MacrodDef>, String> {
-    let (str, r) = many1(macrod_def)(i.trim()).map_err(|e| format!("{}", e))?;
-    if !str.is_empty() {
-        return Err("parse failed to end".to_string());
+/// reserved words, the builtin types included
+pub(crate) const KEYWORDS: &[&str] = &[
+    "end", "recv", "send", "offer", "choose", "channel", "struct", "enum", "bool", "int", "uint",
+    "float", "double", "string", "true", "false", "option", "default", "dict", "const", "type",
+];
+
+/// the definitions of `i`, or its first syntax error
+pub fn parse(i: &str) -> Result<Vec<MacrodDef<'_>>, Diagnostic> {
+    let parsed = parse_recovering(i);
+    match parsed.errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(parsed.defs.into_iter().filter_map(Node::into_def).collect()),
     }
-    Ok(r)
 }
 
-pub fn macrod_def(i: &str) -> IResult<&str, MacrodDef> {
+/// a partial AST and every syntax error in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parsed<'a> {
+    pub defs: Vec<Node<'a>>,
+    pub errors: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node<'a> {
+    Def(MacrodDef<'a>),
+    /// source skipped up to the next definition
+    Error(&'a str),
+}
+
+impl<'a> Node<'a> {
+    pub fn into_def(self) -> Option<MacrodDef<'a>> {
+        match self {
+            Node::Def(def) => Some(def),
+            Node::Error(_) => None,
+        }
+    }
+}
+
+impl<'a> Parsed<'a> {
+    pub fn defs(&self) -> impl Iterator<Item = &MacrodDef<'a>> {
+        self.defs.iter().filter_map(|node| match node {
+            Node::Def(def) => Some(def),
+            Node::Error(_) => None,
+        })
+    }
+}
+
+/// parses on after syntax errors
+///
+/// A definition that fails is skipped up to the next line starting with a
+/// definition keyword. Inside `struct` and `enum` bodies a broken item is
+/// skipped up to the next `,` or `}` and left out of its definition.
+pub fn parse_recovering(src: &str) -> Parsed<'_> {
+    let mut parsed = Parsed {
+        defs: vec![],
+        errors: vec![],
+    };
+    let mut rest = src.trim_start();
+    if rest.trim_end().is_empty() {
+        parsed
+            .errors
+            .push(syntax_error(src, &rest[rest.len()..], macrod_def));
+        return parsed;
+    }
+    while !rest.trim_end().is_empty() {
+        if let Ok((r, def)) = macrod_def(rest) {
+            parsed.defs.push(Node::Def(def));
+            rest = r.trim_start();
+            continue;
+        }
+        if let Some((r, def)) = recover_def(src, rest, &mut parsed.errors) {
+            parsed.defs.push(Node::Def(def));
+            rest = r.trim_start();
+            continue;
+        }
+        parsed.errors.push(syntax_error(src, rest, macrod_def));
+        let end = next_def(rest);
+        parsed.defs.push(Node::Error(rest[..end].trim_end()));
+        rest = &rest[end..];
+    }
+    parsed
+}
+
+/// an error at `rest` of `src`, or further where `parser` failed on it
+fn syntax_error<'a, T>(
+    src: &'a str,
+    rest: &'a str,
+    mut parser: impl FnMut(&'a str) -> IResult<&'a str, T>,
+) -> Diagnostic {
+    let rest = match parser(rest) {
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) if e.input.len() < rest.len() => e.input,
+        _ => rest,
+    };
+    let rest = rest.trim_start();
+    // `rest` is a slice of `src`
+    let start = rest.as_ptr() as usize - src.as_ptr() as usize;
+    let token = match rest.chars().next() {
+        Some(c) if is_name_char(c) => {
+            &rest[..rest.find(|c| !is_name_char(c)).unwrap_or(rest.len())]
+        }
+        Some(c) => &rest[..c.len_utf8()],
+        None => "",
+    };
+    let message = match token {
+        "" => "unexpected end of file".to_string(),
+        token => format!("unexpected `{}`", token),
+    };
+    Diagnostic::error("syntax-error", message).with_span(Some(Span::new(
+        src,
+        start,
+        start + token.len(),
+    )))
+}
+
+/// the offset of the next line of `i` starting a definition, with the
+/// comments and annotations before it, or the end of `i`
+fn next_def(i: &str) -> usize {
+    let mut offset = 0;
+    // start of the comments and annotations run before the current line
+    let mut appends = None;
+    for line in i.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if offset != 0 {
+            if trimmed.starts_with("//") || trimmed.starts_with("#[") {
+                appends.get_or_insert(offset);
+            } else if ["struct", "enum", "channel", "const", "type"]
+                .iter()
+                .any(|kw| keyword(kw)(trimmed).is_ok())
+            {
+                return appends.unwrap_or(offset);
+            } else if !trimmed.is_empty() {
+                appends = None;
+            }
+        }
+        offset += line.len();
+    }
+    i.len()
+}
+
+/// a `struct` or `enum` whose header parses, leaving out broken items
+fn recover_def<'a>(
+    src: &'a str,
+    i: &'a str,
+    errors: &mut Vec<Diagnostic>,
+) -> Option<(&'a str, MacrodDef<'a>)> {
+    let (i, appends) = many0(preceded(ws, append))(i).ok()?;
+    let header = |kw| {
+        tuple((
+            preceded(ws, keyword(kw)),
+            preceded(ws, name),
+            generics,
+            preceded(ws, tag("{")),
+        ))
+    };
+    let (i, def) = if let Ok((i, (_, name, generics, _))) = header("struct")(i) {
+        let (i, items) = recover_items(src, i, struct_item_macro, errors);
+        (
+            i,
+            Def::StructDef(StructDef {
+                name,
+                generics,
+                items,
+            }),
+        )
+    } else {
+        let (i, (_, name, generics, _)) = header("enum")(i).ok()?;
+        let (i, items) = recover_items(src, i, _macro(preceded(ws, enum_item)), errors);
+        (
+            i,
+            Def::EnumDef(EnumDef {
+                name,
+                generics,
+                items,
+            }),
+        )
+    };
+    Some((
+        i,
+        MacrodDef(Macro {
+            appends,
+            body: Box::new(def),
+        }),
+    ))
+}
+
+/// `item`s separated by `,` up to the closing `}`, skipping broken ones
+fn recover_items<'a, T>(
+    src: &'a str,
+    i: &'a str,
+    mut item: impl FnMut(&'a str) -> IResult<&'a str, T>,
+    errors: &mut Vec<Diagnostic>,
+) -> (&'a str, Vec<T>) {
+    let mut items = vec![];
+    // a body left open ends where the next definition starts
+    let body = &i[..next_def(i)];
+    let mut rest = body;
+    loop {
+        let r = rest.trim_start();
+        if let Some(r) = r.strip_prefix('}') {
+            return (&i[offset(i, r)..], items);
+        }
+        if r.is_empty() {
+            let at = offset(src, rest);
+            errors.push(
+                Diagnostic::error("syntax-error", "expected `}`")
+                    .with_span(Some(Span::new(src, at, at))),
+            );
+            return (&i[body.len()..], items);
+        }
+        let error = match item(r) {
+            Ok((after, t)) => {
+                items.push(t);
+                let after = after.trim_start();
+                if let Some(after) = after.strip_prefix(',') {
+                    rest = after;
+                    continue;
+                }
+                if after.starts_with('}') {
+                    rest = after;
+                    continue;
+                }
+                rest = after;
+                syntax_error(src, after, |r| tag(",")(r))
+            }
+            Err(_) => syntax_error(src, r, &mut item),
+        };
+        errors.push(error);
+        rest = skip_item(rest);
+    }
+}
+
+/// `i` after the next `,` outside brackets, or at the closing `}`
+///
+/// Only braces are matched to find the closing `}`, so a `(` or `<` left
+/// open in the item doesn't swallow the end of the list.
+fn skip_item(i: &str) -> &str {
+    let mut depth = 0usize;
+    let mut braces = 0usize;
+    let mut string = false;
+    let mut chars = i.char_indices();
+    while let Some((n, c)) = chars.next() {
+        match c {
+            '"' => string = !string,
+            '\\' if string => {
+                chars.next();
+            }
+            _ if string => {}
+            '<' | '[' | '(' => depth += 1,
+            '>' | ']' | ')' if depth > 0 => depth -= 1,
+            '{' => braces += 1,
+            '}' if braces > 0 => braces -= 1,
+            '}' => return &i[n..],
+            ',' if depth == 0 && braces == 0 => return &i[n + 1..],
+            _ => {}
+        }
+    }
+    &i[i.len()..]
+}
+
+/// byte offset of `part` in `src`
+fn offset(src: &str, part: &str) -> usize {
+    part.as_ptr() as usize - src.as_ptr() as usize
+}
+
+pub fn macrod_def(i: &str) -> IResult<&str, MacrodDef<'_>> {
     preceded(ws, map(_macro(preceded(ws, def)), MacrodDef))(i)
 }
 
-pub fn def(i: &str) -> IResult<&str, Def> {
+pub fn def(i: &str) -> IResult<&str, Def<'_>> {
     alt((
         map(session_def, Def::SessionDef),
         map(struct_def, Def::StructDef),
         map(enum_def, Def::EnumDef),
+        map(const_def, Def::ConstDef),
+        map(type_def, Def::TypeDef),
     ))(i)
 }
 
-pub fn session_def(i: &str) -> IResult<&str, SessionDef> {
+pub fn const_def(i: &str) -> IResult<&str, ConstDef<'_>> {
     map(
         tuple((
-            preceded(ws, tag("channel")),
+            preceded(ws, keyword("const")),
             preceded(ws, name),
+            preceded(ws, tag(":")),
+            preceded(ws, type_or_name),
+            preceded(ws, tag("=")),
+            preceded(ws, constant),
+            preceded(ws, tag(";")),
+        )),
+        |(_, name, _, ty, _, value, _)| ConstDef { name, ty, value },
+    )(i)
+}
+
+pub fn type_def(i: &str) -> IResult<&str, TypeDef<'_>> {
+    map(
+        tuple((
+            preceded(ws, keyword("type")),
+            preceded(ws, name),
+            generics,
+            preceded(ws, tag("=")),
+            preceded(ws, type_or_name),
+            preceded(ws, tag(";")),
+        )),
+        |(_, name, generics, _, ty, _)| TypeDef { name, generics, ty },
+    )(i)
+}
+
+pub fn session_def(i: &str) -> IResult<&str, SessionDef<'_>> {
+    map(
+        tuple((
+            preceded(ws, keyword("channel")),
+            preceded(ws, name),
+            generics,
             preceded(ws, tag("=")),
             preceded(ws, _macro(preceded(ws, session_type))),
         )),
-        |(_, name, _, session)| SessionDef { name, session },
+        |(_, name, generics, _, session)| SessionDef {
+            name,
+            generics,
+            session,
+        },
     )(i)
 }
 
-pub fn struct_def(i: &str) -> IResult<&str, StructDef> {
+pub fn struct_def(i: &str) -> IResult<&str, StructDef<'_>> {
     map(
         tuple((
-            preceded(ws, tag("struct")),
+            preceded(ws, keyword("struct")),
             preceded(ws, name),
+            generics,
             preceded(ws, tag("{")),
             preceded(
                 ws,
                 terminated(
-                    separated_list0(
-                        preceded(ws, char(',')),
-                        preceded(ws, _macro(preceded(ws, struct_item))),
-                    ),
+                    separated_list0(preceded(ws, char(',')), preceded(ws, struct_item_macro)),
                     opt(char(',')),
                 ),
             ),
             preceded(ws, tag("}")),
         )),
-        |(_, name, _, items, _)| StructDef { name, items },
+        |(_, name, generics, _, items, _)| StructDef {
+            name,
+            generics,
+            items,
+        },
     )(i)
 }
 
-pub fn enum_def(i: &str) -> IResult<&str, EnumDef> {
+pub fn enum_def(i: &str) -> IResult<&str, EnumDef<'_>> {
     map(
         tuple((
-            preceded(ws, tag("enum")),
+            preceded(ws, keyword("enum")),
             preceded(ws, name),
+            generics,
             preceded(ws, tag("{")),
             preceded(
                 ws,
@@ -100,23 +397,63 @@ pub fn enum_def(i: &str) -> IResult<&str, EnumDef> {
             ),
             preceded(ws, tag("}")),
         )),
-        |(_, name, _, items, _)| EnumDef { name, items },
+        |(_, name, generics, _, items, _)| EnumDef {
+            name,
+            generics,
+            items,
+        },
     )(i)
 }
 
-pub fn struct_item(i: &str) -> IResult<&str, StructItem> {
+pub fn struct_item(i: &str) -> IResult<&str, StructItem<'_>> {
     map(
         tuple((
             preceded(ws, name),
+            opt(preceded(ws, char('?'))),
             preceded(ws, tag(":")),
             preceded(ws, type_or_name),
-            opt(preceded(preceded(ws, tag("=")), preceded(ws, uint_lit))),
+            opt(preceded(
+                preceded(ws, tag("=")),
+                alt((
+                    pair(map(preceded(ws, uint_lit), Some), opt(default_value)),
+                    map(default_value, |default| (None, Some(default))),
+                )),
+            )),
         )),
-        |(name, _, ty, sync)| StructItem(name, ty, sync),
+        |(name, optional, _, ty, sync_default)| {
+            let ty = match optional {
+                Some(_) => TypeOrName::Type(Box::new(Type::ContainerType(ContainerType::Option(
+                    Box::new(ty),
+                )))),
+                None => ty,
+            };
+            let (sync, default) = sync_default.unwrap_or_default();
+            StructItem(name, ty, sync, default)
+        },
     )(i)
 }
 
-pub fn enum_item(i: &str) -> IResult<&str, EnumItem> {
+/// `_macro(struct_item)` which also takes annotations after the item,
+/// `age: uint = 1 #[range="0..150"]`
+pub fn struct_item_macro(i: &str) -> IResult<&str, Macro<'_, StructItem<'_>>> {
+    map(
+        pair(
+            _macro(preceded(ws, struct_item)),
+            many0(preceded(ws, annotation)),
+        ),
+        |(mut item, annotations)| {
+            item.appends
+                .extend(annotations.into_iter().map(Append::Annotation));
+            item
+        },
+    )(i)
+}
+
+pub fn default_value(i: &str) -> IResult<&str, Constant> {
+    preceded(preceded(ws, keyword("default")), preceded(ws, constant))(i)
+}
+
+pub fn enum_item(i: &str) -> IResult<&str, EnumItem<'_>> {
     map(
         tuple((
             preceded(ws, name),
@@ -129,29 +466,113 @@ pub fn enum_item(i: &str) -> IResult<&str, EnumItem> {
     )(i)
 }
 
-pub fn type_or_name(i: &str) -> IResult<&str, TypeOrName> {
+pub fn generics(i: &str) -> IResult<&str, Vec<&str>> {
+    map(
+        opt(delimited(
+            preceded(ws, char('<')),
+            terminated(
+                separated_list1(preceded(ws, char(',')), preceded(ws, name)),
+                opt(preceded(ws, char(','))),
+            ),
+            preceded(ws, char('>')),
+        )),
+        Option::unwrap_or_default,
+    )(i)
+}
+
+pub fn type_args(i: &str) -> IResult<&str, Vec<TypeOrName<'_>>> {
+    delimited(
+        preceded(ws, char('<')),
+        terminated(
+            separated_list1(preceded(ws, char(',')), preceded(ws, type_or_name)),
+            opt(preceded(ws, char(','))),
+        ),
+        preceded(ws, char('>')),
+    )(i)
+}
+
+pub fn type_or_name(i: &str) -> IResult<&str, TypeOrName<'_>> {
     alt((
         map(_type, |t| TypeOrName::Type(Box::new(t))),
+        map(pair(name, type_args), |(name, args)| {
+            TypeOrName::Generic(name, args)
+        }),
         map(name, TypeOrName::Name),
     ))(i)
 }
 
-pub fn _type(i: &str) -> IResult<&str, Type> {
+pub fn _type(i: &str) -> IResult<&str, Type<'_>> {
     alt((
         map(session_type, Type::SessionType),
+        map(container_type, Type::ContainerType),
         map(simple_type, Type::SimpleType),
         map(constant, Type::Constant),
     ))(i)
 }
 
-pub fn session_or_name(i: &str) -> IResult<&str, SessionOrName> {
+pub fn container_type(i: &str) -> IResult<&str, ContainerType<'_>> {
+    alt((
+        map(
+            delimited(
+                char('['),
+                pair(
+                    preceded(ws, type_or_name),
+                    opt(preceded(
+                        preceded(ws, char(';')),
+                        preceded(ws, array_length),
+                    )),
+                ),
+                preceded(ws, char(']')),
+            ),
+            |(ty, len)| ContainerType::Array(Box::new(ty), len),
+        ),
+        map(
+            preceded(
+                keyword("dict"),
+                delimited(
+                    preceded(ws, char('<')),
+                    tuple((
+                        preceded(ws, simple_type),
+                        preceded(ws, char(',')),
+                        preceded(ws, type_or_name),
+                    )),
+                    preceded(ws, char('>')),
+                ),
+            ),
+            |(k, _, v)| ContainerType::Dict(k, Box::new(v)),
+        ),
+        map(
+            preceded(
+                keyword("option"),
+                delimited(
+                    preceded(ws, char('<')),
+                    preceded(ws, type_or_name),
+                    preceded(ws, char('>')),
+                ),
+            ),
+            |ty| ContainerType::Option(Box::new(ty)),
+        ),
+    ))(i)
+}
+
+pub fn array_length(i: &str) -> IResult<&str, ArrayLength<'_>> {
+    alt((
+        map(map_res(number_dec, str::parse), ArrayLength::Literal),
+        map(name, ArrayLength::Const),
+    ))(i)
+}
+
+pub fn session_or_name(i: &str) -> IResult<&str, SessionOrName<'_>> {
     alt((
         map(session_type, |t| SessionOrName::Session(Box::new(t))),
+        map(pair(name, type_args), |(name, args)| {
+            SessionOrName::Generic(name, args)
+        }),
         map(name, SessionOrName::Name),
     ))(i)
 }
 
-pub fn session_type(i: &str) -> IResult<&str, SessionType> {
+pub fn session_type(i: &str) -> IResult<&str, SessionType<'_>> {
     map(
         separated_list1(
             preceded(ws, tag("->")),
@@ -161,29 +582,29 @@ pub fn session_type(i: &str) -> IResult<&str, SessionType> {
     )(i)
 }
 
-pub fn session(i: &str) -> IResult<&str, Session> {
+pub fn session(i: &str) -> IResult<&str, Session<'_>> {
     alt((
-        value(Session::Endpoint, tag("end")),
+        value(Session::Endpoint, keyword("end")),
         map(
-            preceded(ws, preceded(tag("offer"), preceded(ws, session_union))),
+            preceded(ws, preceded(keyword("offer"), preceded(ws, session_union))),
             Session::Offer,
         ),
         map(
-            preceded(ws, preceded(tag("choose"), preceded(ws, session_union))),
+            preceded(ws, preceded(keyword("choose"), preceded(ws, session_union))),
             Session::Choose,
         ),
         map(
-            preceded(ws, preceded(tag("recv"), preceded(ws, type_or_name))),
+            preceded(ws, preceded(keyword("recv"), preceded(ws, type_or_name))),
             Session::Recv,
         ),
         map(
-            preceded(ws, preceded(tag("send"), preceded(ws, type_or_name))),
+            preceded(ws, preceded(keyword("send"), preceded(ws, type_or_name))),
             Session::Send,
         ),
     ))(i)
 }
 
-pub fn session_union(i: &str) -> IResult<&str, SessionUnion> {
+pub fn session_union(i: &str) -> IResult<&str, SessionUnion<'_>> {
     map(
         many_m_n(
             2,
@@ -203,49 +624,31 @@ pub fn session_union(i: &str) -> IResult<&str, SessionUnion> {
 }
 
 pub fn name(i: &str) -> IResult<&str, &str> {
-    recognize(many1(pair(
-        not(alt((
-            value((), simple_type),
-            value((), is_a(" \t\r\n")), // white space
-            value((), tag("end")),
-            value((), tag("recv")),
-            value((), tag("send")),
-            value((), tag("offer")),
-            value((), tag("choose")),
-            value((), tag("channel")),
-            value((), tag("struct")),
-            value((), tag("enum")),
-            value(
-                (),
-                alt((
-                    tag("->"),
-                    tag("#"),
-                    tag("["),
-                    tag("]"),
-                    tag("{"),
-                    tag("}"),
-                    tag("("),
-                    tag(")"),
-                    tag(","),
-                    tag(":"),
-                    tag("="),
-                    tag("|"),
-                )),
-            ),
-            value((), constant),
-        ))),
-        anychar,
-    )))(i)
+    verify(
+        recognize(pair(
+            satisfy(|c| c.is_alphabetic() || c == '_'),
+            take_while(is_name_char),
+        )),
+        |name: &str| !KEYWORDS.contains(&name),
+    )(i)
+}
+
+pub fn keyword<'i>(kw: &'static str) -> impl FnMut(&'i str) -> IResult<&'i str, &'i str> {
+    terminated(tag(kw), not(satisfy(is_name_char)))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
 }
 
 pub fn simple_type(i: &str) -> IResult<&str, SimpleType> {
     alt((
-        value(SimpleType::Bool, tag("bool")),
-        value(SimpleType::Int, tag("int")),
-        value(SimpleType::Uint, tag("uint")),
-        value(SimpleType::Float, tag("float")),
-        value(SimpleType::Double, tag("double")),
-        value(SimpleType::String, tag("string")),
+        value(SimpleType::Bool, keyword("bool")),
+        value(SimpleType::Int, keyword("int")),
+        value(SimpleType::Uint, keyword("uint")),
+        value(SimpleType::Float, keyword("float")),
+        value(SimpleType::Double, keyword("double")),
+        value(SimpleType::String, keyword("string")),
     ))(i)
 }
 
@@ -261,7 +664,12 @@ pub fn constant(i: &str) -> IResult<&str, Constant> {
 
 pub fn double_lit(i: &str) -> IResult<&str, f64> {
     map_res(
-        recognize(tuple((signed_number, tag("."), number))),
+        recognize(tuple((
+            opt(alt((char('+'), char('-')))),
+            number,
+            tag("."),
+            number,
+        ))),
         str::parse,
     )(i)
 }
@@ -301,12 +709,18 @@ pub fn number_oct(i: &str) -> IResult<&str, &str> {
 }
 
 pub fn string_lit(i: &str) -> IResult<&str, String> {
-    preceded(char('"'), cut(terminated(parse_str, char('"'))))(i)
+    preceded(
+        char('"'),
+        cut(terminated(
+            map(opt(parse_str), Option::unwrap_or_default),
+            char('"'),
+        )),
+    )(i)
 }
 
 pub fn parse_str(i: &str) -> IResult<&str, String> {
     escaped_transform(
-        anychar,
+        is_not("\\\""),
         '\\',
         alt((
             value("\\", tag("\\")),
@@ -323,11 +737,11 @@ pub fn bool_lit(i: &str) -> IResult<&str, bool> {
 }
 
 pub fn true_lit(i: &str) -> IResult<&str, bool> {
-    value(true, tag("true"))(i)
+    value(true, keyword("true"))(i)
 }
 
 pub fn false_lit(i: &str) -> IResult<&str, bool> {
-    value(false, tag("false"))(i)
+    value(false, keyword("false"))(i)
 }
 
 // /*
@@ -358,7 +772,7 @@ pub fn _macro<
     }
 }
 //  */
-pub fn append(i: &str) -> IResult<&str, Append> {
+pub fn append(i: &str) -> IResult<&str, Append<'_>> {
     alt((
         map(docu_comment, Append::DocsComment),
         map(line_comment, Append::LineComment),
@@ -366,7 +780,7 @@ pub fn append(i: &str) -> IResult<&str, Append> {
     ))(i)
 }
 
-pub fn annotation(i: &str) -> IResult<&str, Annotation> {
+pub fn annotation(i: &str) -> IResult<&str, Annotation<'_>> {
     map(
         tuple((
             preceded(ws, tag("#")),
@@ -378,22 +792,62 @@ pub fn annotation(i: &str) -> IResult<&str, Annotation> {
     )(i)
 }
 
-pub fn annotation_body(i: &str) -> IResult<&str, Annotation> {
+pub fn annotation_body(i: &str) -> IResult<&str, Annotation<'_>> {
     alt((
         map(
             tuple((
                 preceded(ws, name),
                 preceded(ws, tag("=")),
-                preceded(ws, constant),
+                preceded(
+                    ws,
+                    alt((
+                        map(constant, AnnotationValue::Constant),
+                        map(name, AnnotationValue::Const),
+                    )),
+                ),
             )),
-            |(name, _, c)| Annotation(name, c),
+            |(name, _, value)| Annotation(name, value),
         ),
         map(preceded(ws, name), |name| {
-            Annotation(name, Constant::Bool(true))
+            Annotation(name, AnnotationValue::Constant(Constant::Bool(true)))
         }),
     ))(i)
 }
 
+/// the annotation `#[name=value]` as a constraint, `None` if `name` isn't
+/// one of `Constraint::NAMES`
+pub fn constraint(name: &str, value: &Constant) -> Option<Result<Constraint, String>> {
+    let invalid = || Err(format!("invalid `{}` constraint: {:?}", name, value));
+    Some(match (name, value) {
+        ("range", Constant::String(s)) => match range(s) {
+            Ok(("", c)) => Ok(c),
+            _ => invalid(),
+        },
+        ("min_len", Constant::Uint(n)) => Ok(Constraint::MinLen(*n)),
+        ("max_len", Constant::Uint(n)) => Ok(Constraint::MaxLen(*n)),
+        ("pattern", Constant::String(s)) => Ok(Constraint::Pattern(s.clone())),
+        (name, _) if Constraint::NAMES.contains(&name) => invalid(),
+        _ => return None,
+    })
+}
+
+pub fn range(i: &str) -> IResult<&str, Constraint> {
+    map(
+        tuple((
+            opt(preceded(ws, constant)),
+            preceded(ws, tag("..")),
+            opt(char('=')),
+            opt(preceded(ws, constant)),
+            ws,
+        )),
+        |(start, _, inclusive, end, _)| Constraint::Range {
+            start,
+            end,
+            inclusive: inclusive.is_some(),
+        },
+    )(i)
+}
+
 pub fn docu_comment(i: &str) -> IResult<&str, &str> {
     recognize(tuple((
         preceded(ws, tag("///")),
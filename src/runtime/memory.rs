@@ -0,0 +1,76 @@
+//! In-memory link
+//!
+//! `connect::<sum>()` gives both ends of a session in one process, passing
+//! messages as they are instead of encoding them, for testing protocol
+//! logic without sockets.
+
+use std::any::{self, Any};
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use super::session::{Chan, Client, Server};
+use super::transport::{Error, Link, Violation};
+
+type Message = Box<dyn Any + std::marker::Send>;
+
+pub struct Memory {
+    tx: UnboundedSender<Message>,
+    rx: UnboundedReceiver<Message>,
+}
+
+/// a client and the server it talks to, linked by a pair of queues
+pub fn connect<S>() -> (Chan<S, Client<Memory>>, Chan<S, Server<Memory>>) {
+    let (client_tx, server_rx) = unbounded_channel();
+    let (server_tx, client_rx) = unbounded_channel();
+    let client = Memory {
+        tx: client_tx,
+        rx: client_rx,
+    };
+    let server = Memory {
+        tx: server_tx,
+        rx: server_rx,
+    };
+    (Chan::client_on(client), Chan::server_on(server))
+}
+
+impl<M: Any + std::marker::Send> Link<M> for Memory {
+    async fn send(&mut self, msg: M) -> Result<(), Error> {
+        self.tx.send(Box::new(msg)).map_err(|_| Error::Disconnected)
+    }
+
+    async fn recv(&mut self) -> Result<M, Error> {
+        let msg = self.rx.recv().await.ok_or(Error::Disconnected)?;
+        msg.downcast().map(|msg| *msg).map_err(|_| {
+            Violation::UnexpectedMessage {
+                expected: any::type_name::<M>(),
+            }
+            .into()
+        })
+    }
+}
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+/// runs `f` on the current thread, to drive a session from sync code
+pub fn block_on<F: Future>(f: F) -> F::Output {
+    let waker = Arc::new(Unpark(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut f = pin!(f);
+    loop {
+        if let Poll::Ready(out) = f.as_mut().poll(&mut cx) {
+            return out;
+        }
+        thread::park();
+    }
+}
@@ -0,0 +1,524 @@
+//! Mock peers
+//!
+//! Plays one side of a session for a peer under development: what it sends
+//! comes from fixtures or is a random sample of the message type, the
+//! branches it picks come from a script or are random. The conversation is
+//! logged as a trace. Code generated with `-g mock` runs one for every
+//! channel of the IDL file.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, LineWriter};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Parser;
+use regex::Regex;
+use regex_syntax::hir::{Class, Hir, HirKind, Literal};
+use serde_json::{Map, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::monitor::{Monitor, Protocol, Shape, Side, State};
+use super::trace::Recorder;
+use super::transport::{Error, Transport};
+use super::wire::Encode;
+use crate::ast::{Constant, Constraint};
+use crate::ir::{lower, Unit};
+use crate::parser::parse;
+use crate::type_checker::check;
+
+/// past this depth samples take the smallest value, so recursive types end
+const MAX_DEPTH: usize = 4;
+/// strings generated for a `#[pattern]` before giving up on it
+const PATTERN_TRIES: usize = 100;
+
+pub trait Io: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Io for T {}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// the side the mock plays
+    pub side: Side,
+    /// values to send by type name, as in `Protocol::name`, used in turn
+    pub fixtures: HashMap<String, Vec<Value>>,
+    /// branches to pick in turn, random ones after it runs out
+    pub script: Vec<u64>,
+    pub seed: u64,
+    /// trace file the conversation is appended to, stderr if `None`
+    pub log: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            side: Side::Server,
+            fixtures: HashMap::new(),
+            script: vec![],
+            seed: 0,
+            log: None,
+        }
+    }
+}
+
+/// the channel `name` of the IDL source `idl`
+pub fn session(idl: &str, name: &str) -> Result<Arc<Protocol>, Error> {
+    let invalid = |e: String| Error::Io(io::Error::other(e));
+    let defs = parse(idl).map_err(|d| invalid(d.to_string()))?;
+    let ir = lower(&[Unit::new(idl, &defs)]);
+    check(&ir).map_err(|e| invalid(e.diagnostic(&ir).to_string()))?;
+    Protocol::from_ir(&ir, name)
+        .map(Arc::new)
+        .map_err(|e| invalid(e.diagnostic(&ir).to_string()))
+}
+
+/// play `options.side` of `def` on `io` until the session ends
+pub async fn run(def: Arc<Protocol>, io: impl Io, options: &Options) -> Result<(), Error> {
+    let recorder = match &options.log {
+        Some(path) => {
+            let log = File::options().create(true).append(true).open(path)?;
+            Recorder::new(Arc::clone(&def), LineWriter::new(log))
+        }
+        None => Recorder::new(Arc::clone(&def), io::stderr()),
+    };
+    let mut transport = Transport::new(io).record(options.side, recorder);
+    let mut monitor = Monitor::new(Arc::clone(&def));
+    let mut mock = Mock {
+        def: &def,
+        options,
+        rng: fastrand::Rng::with_seed(options.seed),
+        used: HashMap::new(),
+        script: options.script.iter(),
+    };
+    while let Some(turn) = monitor.turn() {
+        if turn == options.side {
+            let frame = mock.frame(monitor.state())?;
+            monitor.check(turn, &frame)?;
+            transport.send_frame(&frame).await?;
+        } else {
+            let frame = transport.recv_frame().await?;
+            monitor.check(turn, frame)?;
+        }
+    }
+    Ok(())
+}
+
+struct Mock<'m> {
+    def: &'m Protocol,
+    options: &'m Options,
+    rng: fastrand::Rng,
+    /// fixtures taken of every type
+    used: HashMap<String, usize>,
+    script: std::slice::Iter<'m, u64>,
+}
+
+impl Mock<'_> {
+    fn frame(&mut self, state: &State) -> Result<Vec<u8>, Error> {
+        let mut frame = vec![];
+        match state {
+            State::Send(shape, _) | State::Recv(shape, _) => {
+                let value = match self.fixture(shape) {
+                    Some(value) => value,
+                    None => self.sample(shape, 0)?,
+                };
+                self.def.encode(shape, &value, &mut frame)?;
+            }
+            State::Offer(arms) | State::Choose(arms) => {
+                let tag = match self.script.next() {
+                    Some(tag) => *tag,
+                    None => self.rng.u64(..arms.len() as u64),
+                };
+                tag.encode(&mut frame);
+            }
+            State::End => unreachable!("no turn after end"),
+        }
+        Ok(frame)
+    }
+
+    fn fixture(&mut self, shape: &Shape) -> Option<Value> {
+        let name = self.def.name(shape);
+        let fixtures = self.options.fixtures.get(&name).filter(|f| !f.is_empty())?;
+        let used = self.used.entry(name).or_default();
+        *used += 1;
+        Some(fixtures[(*used - 1) % fixtures.len()].clone())
+    }
+
+    fn sample(&mut self, shape: &Shape, depth: usize) -> Result<Value, Error> {
+        let deep = depth > MAX_DEPTH;
+        Ok(match shape {
+            Shape::Bool => self.rng.bool().into(),
+            Shape::Int => self.rng.i64(-100..100).into(),
+            Shape::Uint => self.rng.u64(..100).into(),
+            Shape::Float | Shape::Double => (self.rng.u32(..10000) as f64 / 100.0).into(),
+            Shape::String => format!("sample{}", self.rng.u32(..1000)).into(),
+            Shape::Literal(c) => match c {
+                Constant::String(s) => s.clone().into(),
+                Constant::Float(f) => (*f).into(),
+                Constant::Int(i) => (*i).into(),
+                Constant::Uint(u) => (*u).into(),
+                Constant::Bool(b) => (*b).into(),
+            },
+            Shape::Option(_) if deep || self.rng.bool() => Value::Null,
+            Shape::Option(shape) => self.sample(shape, depth + 1)?,
+            Shape::List(shape, len) => {
+                let len = len.unwrap_or(if deep { 0 } else { self.rng.usize(..3) });
+                (0..len)
+                    .map(|_| self.sample(shape, depth + 1))
+                    .collect::<Result<_, _>>()?
+            }
+            Shape::Dict(key, value) => {
+                let len = if deep { 0 } else { self.rng.usize(..3) };
+                self.dict(key, value, len, depth)?
+            }
+            Shape::Struct(fields) => fields
+                .iter()
+                .map(|(name, shape)| Ok((name.clone(), self.sample(shape, depth + 1)?)))
+                .collect::<Result<Map<_, _>, Error>>()?
+                .into(),
+            Shape::Enum(variants) => {
+                let (_, name, payload) = if deep {
+                    variants
+                        .iter()
+                        .min_by_key(|(_, _, payload)| {
+                            self.height(payload, &mut vec![]).unwrap_or(usize::MAX)
+                        })
+                        .expect("enums have variants")
+                } else {
+                    &variants[self.rng.usize(..variants.len())]
+                };
+                let payload = self.sample(payload, depth + 1)?;
+                Map::from_iter([(name.clone(), payload)]).into()
+            }
+            Shape::Def(i) => self.sample(&self.def.types[*i].1, depth + 1)?,
+            Shape::Constrained(shape, constraints) => {
+                self.constrained(shape, constraints, depth)?
+            }
+        })
+    }
+
+    /// a sample of `shape` that meets `constraints`, the value must be
+    /// accepted by the peer's `validate`
+    fn constrained(
+        &mut self,
+        shape: &Shape,
+        constraints: &[Constraint],
+        depth: usize,
+    ) -> Result<Value, Error> {
+        let deep = depth > MAX_DEPTH;
+        let (mut range, mut min_len, mut max_len, mut pattern) =
+            ((&None, &None, false), 0, None, None);
+        for c in constraints {
+            match c {
+                Constraint::Range {
+                    start,
+                    end,
+                    inclusive,
+                } => range = (start, end, *inclusive),
+                Constraint::MinLen(n) => min_len = *n as usize,
+                Constraint::MaxLen(n) => max_len = Some(*n as usize),
+                Constraint::Pattern(p) => pattern = Some(p.as_str()),
+            }
+        }
+        let max_len = max_len.unwrap_or(usize::MAX);
+        let unsatisfiable =
+            |what: String| Error::Io(io::Error::other(format!("can't sample {}", what)));
+        if min_len > max_len {
+            return Err(unsatisfiable(format!(
+                "a length of {}..={}",
+                min_len, max_len
+            )));
+        }
+        let (start, end, inclusive) = range;
+        let empty = || {
+            unsatisfiable(format!(
+                "a value in an empty range of {}",
+                self.def.name(shape)
+            ))
+        };
+        Ok(match shape {
+            Shape::Def(i) => self.constrained(&self.def.types[*i].1, constraints, depth + 1)?,
+            // constraints on `option<T>` apply to the value when present
+            Shape::Option(_) if deep || self.rng.bool() => Value::Null,
+            Shape::Option(shape) => self.constrained(shape, constraints, depth + 1)?,
+            Shape::Int | Shape::Uint => {
+                let bound = |c: &Option<Constant>| match c {
+                    Some(Constant::Int(i)) => Some(*i as i128),
+                    Some(Constant::Uint(u)) => Some(*u as i128),
+                    _ => None,
+                };
+                let (floor, ceil) = match shape {
+                    Shape::Int => (i64::MIN as i128, i64::MAX as i128),
+                    _ => (0, u64::MAX as i128),
+                };
+                let start = bound(start).map(|s| s.max(floor));
+                let end = bound(end).map(|e| if inclusive { e } else { e - 1 }.min(ceil));
+                let (lo, hi) = match (start, end) {
+                    (Some(lo), Some(hi)) => (lo, hi),
+                    (Some(lo), None) => (lo, (lo + 99).min(ceil)),
+                    (None, Some(hi)) => ((hi - 99).max(floor), hi),
+                    (None, None) => (floor.max(-100), 99),
+                };
+                if lo > hi {
+                    return Err(empty());
+                }
+                let n = self.rng.i128(lo..=hi);
+                match shape {
+                    Shape::Int => (n as i64).into(),
+                    _ => (n as u64).into(),
+                }
+            }
+            Shape::Float | Shape::Double => {
+                let bound = |c: &Option<Constant>| match c {
+                    Some(Constant::Float(f)) => Some(*f),
+                    Some(Constant::Int(i)) => Some(*i as f64),
+                    Some(Constant::Uint(u)) => Some(*u as f64),
+                    _ => None,
+                };
+                let (lo, hi) = match (bound(start), bound(end)) {
+                    (Some(lo), Some(hi)) => (lo, hi),
+                    (Some(lo), None) => (lo, lo + 100.0),
+                    (None, Some(hi)) => (hi - 100.0, hi),
+                    (None, None) => (0.0, 100.0),
+                };
+                if lo > hi || lo == hi && !inclusive {
+                    return Err(empty());
+                }
+                let x = lo + self.rng.f64() * (hi - lo);
+                // rounding to `float` may leave the range
+                let x = if *shape == Shape::Float {
+                    x as f32 as f64
+                } else {
+                    x
+                };
+                if lo <= x && (x < hi || inclusive && x <= hi) {
+                    x.into()
+                } else {
+                    lo.into()
+                }
+            }
+            Shape::String => match pattern {
+                Some(pattern) => self.matching(pattern, min_len..=max_len)?.into(),
+                None => {
+                    let len = self.rng.usize(min_len..=max_len.min(min_len + 8));
+                    (0..len)
+                        .map(|_| self.rng.lowercase())
+                        .collect::<String>()
+                        .into()
+                }
+            },
+            Shape::List(shape, None) => {
+                let spread = if deep { 0 } else { 2 };
+                let len = self.rng.usize(min_len..=max_len.min(min_len + spread));
+                (0..len)
+                    .map(|_| self.sample(shape, depth + 1))
+                    .collect::<Result<_, _>>()?
+            }
+            Shape::Dict(key, value) => {
+                let spread = if deep { 0 } else { 2 };
+                let len = self.rng.usize(min_len..=max_len.min(min_len + spread));
+                self.dict(key, value, len, depth)?
+            }
+            shape => self.sample(shape, depth)?,
+        })
+    }
+
+    /// a dict of `len` entries, or fewer if `key` has fewer values
+    fn dict(
+        &mut self,
+        key: &Shape,
+        value: &Shape,
+        len: usize,
+        depth: usize,
+    ) -> Result<Value, Error> {
+        let mut dict = Map::new();
+        for _ in 0..len * 10 {
+            if dict.len() == len {
+                break;
+            }
+            let key = match self.sample(key, depth + 1)? {
+                Value::String(key) => key,
+                key => key.to_string(),
+            };
+            let value = self.sample(value, depth + 1)?;
+            dict.insert(key, value);
+        }
+        Ok(dict.into())
+    }
+
+    /// a string of a length in `lens` that `pattern` matches whole
+    fn matching(&mut self, pattern: &str, lens: RangeInclusive<usize>) -> Result<String, Error> {
+        let unsatisfiable = || {
+            let e = format!("can't sample a string matching `{}`", pattern);
+            Error::Io(io::Error::other(e))
+        };
+        let hir = regex_syntax::parse(pattern).map_err(|_| unsatisfiable())?;
+        let regex = Regex::new(&format!("^(?:{})$", pattern)).map_err(|_| unsatisfiable())?;
+        // repetitions reach the shortest length allowed
+        let spread = u32::try_from(*lens.start()).unwrap_or(u32::MAX).max(3);
+        for _ in 0..PATTERN_TRIES {
+            let mut s = String::new();
+            if self.generate(&hir, spread, &mut s)
+                && regex.is_match(&s)
+                && lens.contains(&s.chars().count())
+            {
+                return Ok(s);
+            }
+        }
+        Err(unsatisfiable())
+    }
+
+    /// append a string `hir` matches to `out`, `false` if there is none.
+    /// Assertions are skipped, `matching` checks the result.
+    fn generate(&mut self, hir: &Hir, spread: u32, out: &mut String) -> bool {
+        match hir.kind() {
+            HirKind::Empty | HirKind::Look(_) => true,
+            HirKind::Literal(Literal(bytes)) => match std::str::from_utf8(bytes) {
+                Ok(s) => {
+                    out.push_str(s);
+                    true
+                }
+                Err(_) => false,
+            },
+            // printable ascii where the class has some
+            HirKind::Class(Class::Unicode(class)) => {
+                let Some(range) = self.pick(class.ranges()) else {
+                    return false;
+                };
+                let (lo, hi) = (range.start().max(' '), range.end().min('~'));
+                let (lo, hi) = if lo <= hi {
+                    (lo, hi)
+                } else {
+                    (range.start(), range.end())
+                };
+                out.push(self.rng.char(lo..=hi));
+                true
+            }
+            HirKind::Class(Class::Bytes(class)) => {
+                let Some(range) = self.pick(class.ranges()) else {
+                    return false;
+                };
+                let (lo, hi) = (range.start().max(b' '), range.end().min(b'~'));
+                let (lo, hi) = if lo <= hi {
+                    (lo, hi)
+                } else {
+                    (range.start(), range.end())
+                };
+                let b = self.rng.u8(lo..=hi);
+                out.push(b as char);
+                b.is_ascii()
+            }
+            HirKind::Repetition(rep) => {
+                let max = rep
+                    .max
+                    .unwrap_or(u32::MAX)
+                    .min(rep.min.saturating_add(spread));
+                (0..self.rng.u32(rep.min..=max)).all(|_| self.generate(&rep.sub, spread, out))
+            }
+            HirKind::Capture(cap) => self.generate(&cap.sub, spread, out),
+            HirKind::Concat(hirs) => hirs.iter().all(|h| self.generate(h, spread, out)),
+            HirKind::Alternation(hirs) => match self.pick(hirs) {
+                Some(h) => self.generate(h, spread, out),
+                None => false,
+            },
+        }
+    }
+
+    fn pick<'t, T>(&mut self, items: &'t [T]) -> Option<&'t T> {
+        (!items.is_empty()).then(|| &items[self.rng.usize(..items.len())])
+    }
+
+    /// how deep the smallest value of `shape` is, `None` if every value
+    /// contains one of the `visiting` definitions
+    fn height(&self, shape: &Shape, visiting: &mut Vec<usize>) -> Option<usize> {
+        match shape {
+            Shape::List(shape, Some(len)) if *len > 0 => Some(self.height(shape, visiting)? + 1),
+            Shape::Struct(fields) => fields.iter().try_fold(0, |h, (_, shape)| {
+                Some(h.max(self.height(shape, visiting)? + 1))
+            }),
+            Shape::Enum(variants) => variants
+                .iter()
+                .filter_map(|(_, _, shape)| self.height(shape, visiting))
+                .min()
+                .map(|h| h + 1),
+            // `min_len` makes lists take an element
+            Shape::Constrained(shape, constraints) => match shape.as_ref() {
+                Shape::List(item, None)
+                    if constraints
+                        .iter()
+                        .any(|c| matches!(c, Constraint::MinLen(n) if *n > 0)) =>
+                {
+                    Some(self.height(item, visiting)? + 1)
+                }
+                shape => self.height(shape, visiting),
+            },
+            Shape::Def(i) if visiting.contains(i) => None,
+            Shape::Def(i) => {
+                visiting.push(*i);
+                let height = self.height(&self.def.types[*i].1, visiting);
+                visiting.pop();
+                Some(height? + 1)
+            }
+            // empty or absent
+            _ => Some(0),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "mock peer of a limit_stream channel")]
+struct Args {
+    #[arg(help = "channel to mock")]
+    channel: String,
+    #[arg(long, default_value_t = String::from("127.0.0.1:4000"), help = "address to serve on")]
+    listen: String,
+    #[arg(long, help = "connect to a peer instead of listening")]
+    connect: Option<String>,
+    #[arg(long, default_value_t = String::from("server"), help = "client | server, the side to play")]
+    side: String,
+    #[arg(
+        long,
+        help = "JSON file of values to send, `{\"<type>\": [<value>, ..]}`"
+    )]
+    fixtures: Option<PathBuf>,
+    #[arg(long, value_delimiter = ',', help = "branches to pick, in turn")]
+    script: Vec<u64>,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    #[arg(long, help = "trace file to append to, defaults to stderr")]
+    log: Option<PathBuf>,
+}
+
+/// `main` of generated mocks, serves one connection at a time
+pub fn main(idl: &str) -> io::Result<()> {
+    let args = Args::parse();
+    let def = session(idl, &args.channel).map_err(io::Error::other)?;
+    let fixtures = match &args.fixtures {
+        Some(path) => serde_json::from_reader(BufReader::new(File::open(path)?))?,
+        None => HashMap::new(),
+    };
+    let options = Options {
+        side: args.side.parse().map_err(io::Error::other)?,
+        fixtures,
+        script: args.script,
+        seed: args.seed,
+        log: args.log,
+    };
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    rt.block_on(async {
+        if let Some(addr) = args.connect {
+            let io = TcpStream::connect(addr).await?;
+            return run(def, io, &options).await.map_err(io::Error::other);
+        }
+        let listener = TcpListener::bind(&args.listen).await?;
+        loop {
+            let (io, peer) = listener.accept().await?;
+            if let Err(e) = run(Arc::clone(&def), io, &options).await {
+                eprintln!("{}: {}", peer, e);
+            }
+        }
+    })
+}
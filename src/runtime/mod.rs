@@ -0,0 +1,31 @@
+//! Runtime
+//!
+//! Support library for the code generated by `codegen::rust`.
+//! Generated files start with `use limit_stream::runtime::*;`.
+
+pub mod memory;
+pub mod mock;
+pub mod monitor;
+pub mod session;
+pub mod trace;
+pub mod transport;
+pub mod validate;
+pub mod wire;
+
+pub use memory::{block_on, connect, Memory};
+pub use session::{
+    Branches, Chan, Choose, Client, Endpoint, Next, Offer, Recv, Role, Select, Send, Server,
+};
+pub use transport::{Error, Link, Transport, Violation, MAX_FRAME_LEN};
+pub use validate::{matches_pattern, Length, Validate, ValidationError};
+pub use wire::{
+    decode_array_len, decode_literal, decode_variant, encode_array_len, encode_literal,
+    encode_variant, Decode, DecodeError, Encode, Literal,
+};
+
+pub type Bool = bool;
+pub type Int = i64;
+pub type Uint = u64;
+pub type Float = f32;
+pub type Double = f64;
+pub type String = std::string::String;
@@ -0,0 +1,670 @@
+//! Protocol monitor
+//!
+//! Checks the frames of a connection against a session, for peers that
+//! aren't written with `Chan`. A `Protocol` is the session as data: the
+//! shape of every message and a table of protocol states, built from the
+//! IR at runtime.
+
+use std::collections::HashMap;
+use std::future::pending;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rmp::{decode, encode};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::io::{split, AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+
+use super::transport::{Error, Transport, Violation};
+use super::wire::{
+    decode_array_len, decode_variant, encode_array_len, Decode, DecodeError, Encode,
+};
+use crate::ast::{Constant, Constraint, SimpleType};
+use crate::ir::{
+    wire_order, Branch, DefId, DefKind, Ir, Length, Ref, Session, SessionType, Target, Ty,
+};
+use crate::monomorphize::monomorphize;
+use crate::parser;
+use crate::type_checker::{Error as TypeError, Kind, Name};
+
+/// what a message looks like on the wire
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Bool,
+    Int,
+    Uint,
+    Float,
+    Double,
+    String,
+    Literal(Constant),
+    Option(Box<Shape>),
+    /// with its length if fixed
+    List(Box<Shape>, Option<usize>),
+    Dict(Box<Shape>, Box<Shape>),
+    /// name and shape of every field
+    Struct(Vec<(String, Shape)>),
+    /// tag, name and payload of every variant
+    Enum(Vec<(u64, String, Shape)>),
+    /// `Protocol::types[i]`, for recursive types
+    Def(usize),
+    /// a struct field with the constraints its value must meet
+    Constrained(Box<Shape>, Vec<Constraint>),
+}
+
+/// a protocol state, `usize`s are indices into `Protocol::states`
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    /// the server sends a message
+    Send(Shape, usize),
+    /// the server receives a message
+    Recv(Shape, usize),
+    /// the client picks one of the branches
+    Offer(Vec<usize>),
+    /// the server picks one of the branches
+    Choose(Vec<usize>),
+    End,
+}
+
+/// a session as seen from the server, starting at `states[0]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Protocol {
+    /// name and shape of every definition used
+    pub types: Vec<(String, Shape)>,
+    pub states: Vec<State>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    /// runs the dual of the session
+    Client,
+    /// runs the session as written
+    Server,
+}
+
+impl FromStr for Side {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Side::Client),
+            "server" => Ok(Side::Server),
+            _ => Err(format!("unknown side `{}`, expected client | server", s)),
+        }
+    }
+}
+
+impl Side {
+    pub fn peer(self) -> Self {
+        match self {
+            Side::Client => Side::Server,
+            Side::Server => Side::Client,
+        }
+    }
+}
+
+impl Protocol {
+    /// the non-generic channel `name` of a checked `ir`
+    pub fn from_ir(ir: &Ir, name: &str) -> Result<Self, TypeError> {
+        let ir = monomorphize(ir)?;
+        let id = ir
+            .lookup(name)
+            .ok_or_else(|| TypeError::NameIsNotFound(name.into()))?;
+        let mut builder = Builder {
+            ir: &ir,
+            def: Protocol {
+                types: vec![],
+                states: vec![],
+            },
+            types: HashMap::new(),
+            sessions: HashMap::new(),
+        };
+        builder.session_def(id)?;
+        Ok(builder.def)
+    }
+
+    /// consume one value of `shape` from the front of `buf`
+    pub fn check(&self, shape: &Shape, buf: &mut &[u8]) -> Result<(), DecodeError> {
+        self.value(shape, buf).map(drop)
+    }
+
+    /// encode `value` as `shape`, the inverse of `value`
+    pub fn encode(
+        &self,
+        shape: &Shape,
+        value: &Value,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        let mismatch =
+            || DecodeError::Malformed(format!("`{}` doesn't fit {}", value, self.name(shape)));
+        match (shape, value) {
+            (Shape::Bool, Value::Bool(b)) => b.encode(buf),
+            (Shape::Int, Value::Number(n)) => n.as_i64().ok_or_else(mismatch)?.encode(buf),
+            (Shape::Uint, Value::Number(n)) => n.as_u64().ok_or_else(mismatch)?.encode(buf),
+            (Shape::Float, Value::Number(n)) => {
+                (n.as_f64().ok_or_else(mismatch)? as f32).encode(buf)
+            }
+            (Shape::Double, Value::Number(n)) => n.as_f64().ok_or_else(mismatch)?.encode(buf),
+            (Shape::String, Value::String(s)) => s.encode(buf),
+            (Shape::Literal(c), _) => match c {
+                Constant::String(s) => s.encode(buf),
+                Constant::Float(f) => f.encode(buf),
+                Constant::Int(i) => i.encode(buf),
+                Constant::Uint(u) => u.encode(buf),
+                Constant::Bool(b) => b.encode(buf),
+            },
+            (Shape::Option(_), Value::Null) => None::<u64>.encode(buf),
+            (Shape::Option(shape), value) => self.encode(shape, value, buf)?,
+            (Shape::List(shape, len), Value::Array(items)) => {
+                if len.is_some_and(|len| len != items.len()) {
+                    return Err(mismatch());
+                }
+                encode_array_len(buf, items.len());
+                for item in items {
+                    self.encode(shape, item, buf)?;
+                }
+            }
+            (Shape::Dict(key, value), Value::Object(entries)) => {
+                encode::write_map_len(buf, entries.len() as u32).expect("write to Vec<u8>");
+                for (k, v) in entries {
+                    // keys of other types are written as JSON
+                    let k = match key.as_ref() {
+                        Shape::String => Value::String(k.clone()),
+                        _ => serde_json::from_str(k).map_err(|_| mismatch())?,
+                    };
+                    self.encode(key, &k, buf)?;
+                    self.encode(value, v, buf)?;
+                }
+            }
+            (Shape::Struct(fields), Value::Object(entries)) => {
+                encode_array_len(buf, fields.len());
+                for (name, shape) in fields {
+                    self.encode(shape, entries.get(name).unwrap_or(&Value::Null), buf)?;
+                }
+            }
+            (Shape::Enum(variants), Value::Object(entries)) if entries.len() == 1 => {
+                let (name, payload) = entries.iter().next().ok_or_else(mismatch)?;
+                let (tag, _, shape) = variants
+                    .iter()
+                    .find(|(_, n, _)| n == name)
+                    .ok_or_else(mismatch)?;
+                encode_array_len(buf, 2);
+                tag.encode(buf);
+                self.encode(shape, payload, buf)?;
+            }
+            (Shape::Def(i), value) => self.encode(&self.types[*i].1, value, buf)?,
+            (Shape::Constrained(shape, _), value) => self.encode(shape, value, buf)?,
+            _ => return Err(mismatch()),
+        }
+        Ok(())
+    }
+
+    /// `shape` as written in the IDL
+    pub fn name(&self, shape: &Shape) -> String {
+        match shape {
+            Shape::Bool => "bool".to_string(),
+            Shape::Int => "int".to_string(),
+            Shape::Uint => "uint".to_string(),
+            Shape::Float => "float".to_string(),
+            Shape::Double => "double".to_string(),
+            Shape::String => "string".to_string(),
+            Shape::Literal(Constant::String(s)) => format!("{:?}", s),
+            Shape::Literal(Constant::Float(f)) => f.to_string(),
+            Shape::Literal(Constant::Int(i)) => i.to_string(),
+            Shape::Literal(Constant::Uint(u)) => u.to_string(),
+            Shape::Literal(Constant::Bool(b)) => b.to_string(),
+            Shape::Option(shape) => format!("option<{}>", self.name(shape)),
+            Shape::List(shape, None) => format!("[{}]", self.name(shape)),
+            Shape::List(shape, Some(len)) => format!("[{}; {}]", self.name(shape), len),
+            Shape::Dict(key, value) => format!("dict<{}, {}>", self.name(key), self.name(value)),
+            Shape::Struct(_) => "struct".to_string(),
+            Shape::Enum(_) => "enum".to_string(),
+            Shape::Def(i) => self.types[*i].0.clone(),
+            Shape::Constrained(shape, _) => self.name(shape),
+        }
+    }
+
+    /// decode one value of `shape` from the front of `buf`, structs as
+    /// objects and enums as `{variant: payload}`
+    pub fn value(&self, shape: &Shape, buf: &mut &[u8]) -> Result<Value, DecodeError> {
+        let malformed = |e: decode::ValueReadError| DecodeError::Malformed(e.to_string());
+        Ok(match shape {
+            Shape::Bool => bool::decode(buf)?.into(),
+            Shape::Int => i64::decode(buf)?.into(),
+            Shape::Uint => u64::decode(buf)?.into(),
+            Shape::Float => f32::decode(buf)?.into(),
+            Shape::Double => f64::decode(buf)?.into(),
+            Shape::String => String::decode(buf)?.into(),
+            Shape::Literal(c) => literal(c, buf)?,
+            // 0xc0 is the `nil` marker
+            Shape::Option(_) if buf.first() == Some(&0xc0) => {
+                *buf = &buf[1..];
+                Value::Null
+            }
+            Shape::Option(shape) => self.value(shape, buf)?,
+            Shape::List(shape, len) => {
+                let len = match len {
+                    Some(len) => decode_array_len(buf, *len).map(|_| *len)?,
+                    None => decode::read_array_len(buf).map_err(malformed)? as usize,
+                };
+                (0..len)
+                    .map(|_| self.value(shape, buf))
+                    .collect::<Result<_, _>>()?
+            }
+            Shape::Dict(key, value) => {
+                let len = decode::read_map_len(buf).map_err(malformed)?;
+                (0..len)
+                    .map(|_| {
+                        let key = match self.value(key, buf)? {
+                            Value::String(key) => key,
+                            key => key.to_string(),
+                        };
+                        Ok((key, self.value(value, buf)?))
+                    })
+                    .collect::<Result<Map<_, _>, DecodeError>>()?
+                    .into()
+            }
+            Shape::Struct(fields) => {
+                decode_array_len(buf, fields.len())?;
+                fields
+                    .iter()
+                    .map(|(name, f)| Ok((name.clone(), self.value(f, buf)?)))
+                    .collect::<Result<Map<_, _>, DecodeError>>()?
+                    .into()
+            }
+            Shape::Enum(variants) => {
+                let tag = decode_variant(buf)?;
+                let (_, name, payload) = variants
+                    .iter()
+                    .find(|(t, _, _)| *t == tag)
+                    .ok_or(DecodeError::UnknownVariant(tag))?;
+                Map::from_iter([(name.clone(), self.value(payload, buf)?)]).into()
+            }
+            Shape::Def(i) => self.value(&self.types[*i].1, buf)?,
+            Shape::Constrained(shape, _) => self.value(shape, buf)?,
+        })
+    }
+}
+
+fn literal(c: &Constant, buf: &mut &[u8]) -> Result<Value, DecodeError> {
+    fn expect<T>(expected: T, buf: &mut &[u8]) -> Result<Value, DecodeError>
+    where
+        T: Decode + PartialEq + std::fmt::Debug + Into<Value>,
+    {
+        let found = T::decode(buf)?;
+        if found != expected {
+            return Err(DecodeError::UnexpectedLiteral {
+                expected: format!("{:?}", expected),
+                found: format!("{:?}", found),
+            });
+        }
+        Ok(found.into())
+    }
+    match c {
+        Constant::String(s) => expect(s.clone(), buf),
+        Constant::Float(f) => expect(*f, buf),
+        Constant::Int(i) => expect(*i, buf),
+        Constant::Uint(u) => expect(*u, buf),
+        Constant::Bool(b) => expect(*b, buf),
+    }
+}
+
+struct Builder<'i> {
+    ir: &'i Ir,
+    def: Protocol,
+    types: HashMap<DefId, usize>,
+    sessions: HashMap<DefId, usize>,
+}
+
+impl Builder<'_> {
+    fn session_def(&mut self, id: DefId) -> Result<usize, TypeError> {
+        if let Some(&state) = self.sessions.get(&id) {
+            return Ok(state);
+        }
+        let def = self.ir.def(id);
+        match &def.kind {
+            DefKind::Session(s) => self.session(s, Some(id)),
+            DefKind::Alias(Ty::Ref(r)) => self.session_ref(r),
+            DefKind::Alias(Ty::Session(s)) => self.session(s, Some(id)),
+            _ => Err(TypeError::KindMismatch {
+                name: Name::new(self.ir.name(def.name), def.loc),
+                expected: Kind::Session,
+                found: Kind::of(def).unwrap_or(Kind::Type),
+            }),
+        }
+    }
+
+    fn session_ref(&mut self, r: &Ref) -> Result<usize, TypeError> {
+        match r.target {
+            Target::Def(id) => self.session_def(id),
+            _ => Err(TypeError::NameIsNotFound(Name::of(self.ir, r))),
+        }
+    }
+
+    /// the states of `s` are allocated in order, the first one is `s` itself
+    fn session(&mut self, s: &SessionType, def: Option<DefId>) -> Result<usize, TypeError> {
+        let start = self.def.states.len();
+        self.def
+            .states
+            .extend(std::iter::repeat_n(State::End, s.0.len()));
+        if let Some(def) = def {
+            self.sessions.insert(def, start);
+        }
+        for (i, session) in s.0.iter().enumerate() {
+            let next = |builder: &mut Self| {
+                if i + 1 < s.0.len() {
+                    start + i + 1
+                } else {
+                    builder.def.states.push(State::End);
+                    builder.def.states.len() - 1
+                }
+            };
+            let state = match session {
+                Session::Send(ty) => State::Send(self.shape(ty)?, next(self)),
+                Session::Recv(ty) => State::Recv(self.shape(ty)?, next(self)),
+                Session::Offer(arms) => State::Offer(self.branches(arms)?),
+                Session::Choose(arms) => State::Choose(self.branches(arms)?),
+                Session::Endpoint => State::End,
+            };
+            self.def.states[start + i] = state;
+        }
+        Ok(start)
+    }
+
+    fn branches(&mut self, arms: &[Branch]) -> Result<Vec<usize>, TypeError> {
+        arms.iter()
+            .map(|arm| match arm {
+                Branch::Ref(r) => self.session_ref(r),
+                Branch::Session(s) => self.session(s, None),
+            })
+            .collect()
+    }
+
+    fn shape(&mut self, ty: &Ty) -> Result<Shape, TypeError> {
+        Ok(match ty {
+            Ty::Simple(ty) => simple(ty),
+            Ty::Literal(c) => Shape::Literal(c.clone()),
+            Ty::Ref(r) => match r.target {
+                Target::Def(id) => Shape::Def(self.type_def(id, r)?),
+                _ => return Err(TypeError::NameIsNotFound(Name::of(self.ir, r))),
+            },
+            Ty::Array(ty, len) => {
+                let len = match len {
+                    None => None,
+                    Some(Length::Literal(len)) => Some(*len),
+                    Some(Length::Const(r)) => Some(self.length(r)?),
+                };
+                Shape::List(Box::new(self.shape(ty)?), len)
+            }
+            Ty::Dict(key, value) => {
+                Shape::Dict(Box::new(simple(key)), Box::new(self.shape(value)?))
+            }
+            Ty::Option(ty) => Shape::Option(Box::new(self.shape(ty)?)),
+            Ty::Session(_) => {
+                return Err(TypeError::KindMismatch {
+                    name: "session".into(),
+                    expected: Kind::Type,
+                    found: Kind::Session,
+                })
+            }
+        })
+    }
+
+    fn type_def(&mut self, id: DefId, r: &Ref) -> Result<usize, TypeError> {
+        if let Some(&i) = self.types.get(&id) {
+            return Ok(i);
+        }
+        let i = self.def.types.len();
+        let def = self.ir.def(id);
+        let name = self.ir.name(def.name).to_string();
+        self.def.types.push((name, Shape::Struct(vec![])));
+        self.types.insert(id, i);
+        let shape = match &def.kind {
+            DefKind::Struct(fields) => Shape::Struct(
+                wire_order(fields)
+                    .into_iter()
+                    .map(|f| {
+                        let shape = self.shape(&f.ty)?;
+                        let constraints = f
+                            .annotations
+                            .iter()
+                            .filter_map(|a| {
+                                let value = self.ir.annotation_value(a)?;
+                                parser::constraint(self.ir.name(a.name), value)?.ok()
+                            })
+                            .collect::<Vec<_>>();
+                        let shape = if constraints.is_empty() {
+                            shape
+                        } else {
+                            Shape::Constrained(Box::new(shape), constraints)
+                        };
+                        Ok((self.ir.name(f.name).to_string(), shape))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            // implicit tags follow the previous one, like rust discriminants
+            DefKind::Enum(variants) => {
+                let mut prev = None;
+                let mut arms = vec![];
+                for v in variants {
+                    let tag = v.tag.unwrap_or(prev.map_or(0, |p| p + 1));
+                    prev = Some(tag);
+                    arms.push((tag, self.ir.name(v.name).to_string(), self.shape(&v.ty)?));
+                }
+                Shape::Enum(arms)
+            }
+            DefKind::Alias(ty) => self.shape(ty)?,
+            _ => {
+                return Err(TypeError::KindMismatch {
+                    name: Name::of(self.ir, r),
+                    expected: Kind::Type,
+                    found: Kind::of(def).unwrap_or(Kind::Type),
+                })
+            }
+        };
+        self.def.types[i].1 = shape;
+        Ok(i)
+    }
+
+    fn length(&self, r: &Ref) -> Result<usize, TypeError> {
+        let value = match r.target {
+            Target::Def(id) => match &self.ir.def(id).kind {
+                DefKind::Const(_, Constant::Uint(u)) => usize::try_from(*u).ok(),
+                DefKind::Const(_, Constant::Int(i)) => usize::try_from(*i).ok(),
+                _ => None,
+            },
+            _ => None,
+        };
+        value.ok_or_else(|| TypeError::InvalidArrayLength(Name::of(self.ir, r)))
+    }
+}
+
+fn simple(ty: &SimpleType) -> Shape {
+    match ty {
+        SimpleType::Bool => Shape::Bool,
+        SimpleType::Int => Shape::Int,
+        SimpleType::Uint => Shape::Uint,
+        SimpleType::Float => Shape::Float,
+        SimpleType::Double => Shape::Double,
+        SimpleType::String => Shape::String,
+    }
+}
+
+/// follows one connection through a `Protocol`
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    def: Arc<Protocol>,
+    state: usize,
+}
+
+impl Monitor {
+    pub fn new(def: Arc<Protocol>) -> Self {
+        Self { def, state: 0 }
+    }
+
+    /// index of the current state in `Protocol::states`
+    pub fn index(&self) -> usize {
+        self.state
+    }
+
+    pub fn state(&self) -> &State {
+        &self.def.states[self.state]
+    }
+
+    /// the side expected to send the next frame, `None` after `end`
+    pub fn turn(&self) -> Option<Side> {
+        match self.state() {
+            State::Send(..) | State::Choose(_) => Some(Side::Server),
+            State::Recv(..) | State::Offer(_) => Some(Side::Client),
+            State::End => None,
+        }
+    }
+
+    /// check a frame sent by `from` and advance past it, the state is
+    /// unchanged if it violates the session
+    pub fn check(&mut self, from: Side, frame: &[u8]) -> Result<(), Error> {
+        self.observe(from, frame).map(drop)
+    }
+
+    /// `check` returning what the frame holds
+    pub fn observe(&mut self, from: Side, frame: &[u8]) -> Result<Observed, Error> {
+        if self.turn() != Some(from) {
+            return Err(Violation::OutOfTurn.into());
+        }
+        let mut rest = frame;
+        let (observed, next) = match self.state() {
+            State::Send(shape, next) | State::Recv(shape, next) => {
+                (Observed::Message(self.def.value(shape, &mut rest)?), *next)
+            }
+            State::Offer(arms) | State::Choose(arms) => {
+                let tag = u64::decode(&mut rest)?;
+                let next = usize::try_from(tag)
+                    .ok()
+                    .and_then(|i| arms.get(i))
+                    .ok_or(Violation::UnknownBranch(tag))?;
+                (Observed::Branch(tag), *next)
+            }
+            State::End => unreachable!("no turn after end"),
+        };
+        if !rest.is_empty() {
+            return Err(Violation::TrailingBytes(rest.len()).into());
+        }
+        self.state = next;
+        Ok(observed)
+    }
+}
+
+/// what a frame held
+#[derive(Debug, Clone, PartialEq)]
+pub enum Observed {
+    Message(Value),
+    /// the index of the branch picked
+    Branch(u64),
+}
+
+/// what `proxy` does after a violation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// close both connections
+    Terminate,
+    /// forward the frame and everything after it without checking
+    Forward,
+}
+
+/// forwards frames between `client` and `server` until the session ends,
+/// checking each with `monitor`. `on_violation` reports every violation
+/// and decides what happens next.
+///
+/// Both sides are read at once, so a frame sent out of turn is reported as
+/// soon as it arrives. After `Action::Forward` frames are passed on
+/// unchecked until both sides close.
+pub async fn proxy<C, S>(
+    mut monitor: Monitor,
+    client: C,
+    server: S,
+    mut on_violation: impl FnMut(&Error) -> Action,
+) -> Result<(), Error>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (client_read, client) = split(client);
+    let (server_read, server) = split(server);
+    let (tx, mut frames) = mpsc::channel(1);
+    let read = async {
+        tokio::join!(
+            read(Side::Client, client_read, tx.clone()),
+            read(Side::Server, server_read, tx)
+        );
+        // the relay stops at the errors that ended both
+        pending().await
+    };
+    let relay = async {
+        let mut client = Transport::new(client);
+        let mut server = Transport::new(server);
+        let mut checked = true;
+        while checked && monitor.turn().is_some() {
+            let (side, frame) = frames.recv().await.expect("read until an error");
+            let frame = frame?;
+            if let Err(e) = monitor.check(side, &frame) {
+                if on_violation(&e) == Action::Terminate {
+                    return Err(e);
+                }
+                checked = false;
+            }
+            pass(side, Some(&frame), &mut client, &mut server).await?;
+        }
+        let mut open = if checked { 0 } else { 2 };
+        while open > 0 {
+            let (side, frame) = frames.recv().await.expect("read until an error");
+            match frame {
+                Ok(frame) => pass(side, Some(&frame), &mut client, &mut server).await?,
+                Err(Error::Disconnected) => {
+                    pass(side, None, &mut client, &mut server).await?;
+                    open -= 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    };
+    tokio::select! {
+        res = relay => res,
+        res = read => res,
+    }
+}
+
+/// send the frames of `side` to the relay until one fails
+async fn read<T: AsyncRead + Unpin>(
+    side: Side,
+    io: T,
+    frames: mpsc::Sender<(Side, Result<Vec<u8>, Error>)>,
+) {
+    let mut from = Transport::new(io);
+    loop {
+        let frame = from.recv_frame().await.map(<[u8]>::to_vec);
+        let failed = frame.is_err();
+        if frames.send((side, frame)).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
+/// pass a frame of `from` to its peer, `None` closes the peer's side
+async fn pass<C, S>(
+    from: Side,
+    frame: Option<&[u8]>,
+    client: &mut Transport<C>,
+    server: &mut Transport<S>,
+) -> Result<(), Error>
+where
+    C: AsyncWrite + Unpin,
+    S: AsyncWrite + Unpin,
+{
+    match (from, frame) {
+        (Side::Client, Some(frame)) => server.send_frame(frame).await,
+        (Side::Server, Some(frame)) => client.send_frame(frame).await,
+        (Side::Client, None) => server.shutdown().await,
+        (Side::Server, None) => client.shutdown().await,
+    }
+}
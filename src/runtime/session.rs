@@ -0,0 +1,222 @@
+//! Session type markers
+//!
+//! `channel sum = recv int -> end` is generated as
+//! `pub type sum = Next<Recv<Int>, Endpoint>;`
+//! and run over a link `L` as a `Chan<sum, Server<L>>`, or as a
+//! `Chan<sum, Client<L>>` on the other end.
+
+use std::marker::PhantomData;
+
+use super::transport::{Error, Link, Transport, Violation};
+
+pub struct Send<T>(PhantomData<T>);
+
+pub struct Recv<T>(PhantomData<T>);
+
+pub struct Next<A, B>(PhantomData<(A, B)>);
+
+/// `E` is the generated enum of branches the peer chooses from
+pub struct Offer<E>(PhantomData<E>);
+
+/// `E` is the generated enum of branches we choose from
+pub struct Choose<E>(PhantomData<E>);
+
+pub struct Endpoint;
+
+/// implemented by the generated enum of an `offer`/`choose`, whose arms
+/// hold the channel continuing in that branch
+pub trait Branches<R>: Sized {
+    fn tag(&self) -> u64;
+    fn branch(tag: u64, role: R) -> Option<Self>;
+    fn into_role(self) -> R;
+}
+
+/// `Offer<E>` names the enum as `E<()>`, this gets it on another role
+pub trait Select {
+    type On<R>: Branches<R>;
+}
+
+/// the side of a connection, which decides whether `Send` sends
+pub trait Role {
+    type Link;
+    fn link(&mut self) -> &mut Self::Link;
+    fn into_link(self) -> Self::Link;
+}
+
+/// runs the session as written in the IDL
+pub struct Server<L>(L);
+
+/// runs the dual of the session: sends on `recv`, chooses on `offer`
+pub struct Client<L>(L);
+
+impl<L> Role for Server<L> {
+    type Link = L;
+    fn link(&mut self) -> &mut L {
+        &mut self.0
+    }
+    fn into_link(self) -> L {
+        self.0
+    }
+}
+
+impl<L> Role for Client<L> {
+    type Link = L;
+    fn link(&mut self) -> &mut L {
+        &mut self.0
+    }
+    fn into_link(self) -> L {
+        self.0
+    }
+}
+
+/// an endpoint in session `S`, every step consumes it and returns the
+/// channel for the rest of the session
+#[must_use]
+pub struct Chan<S, R> {
+    role: R,
+    session: PhantomData<S>,
+}
+
+impl<S, R> Chan<S, R> {
+    pub fn new(role: R) -> Self {
+        Self {
+            role,
+            session: PhantomData,
+        }
+    }
+
+    pub fn into_role(self) -> R {
+        self.role
+    }
+}
+
+impl<S, T> Chan<S, Server<Transport<T>>> {
+    pub fn server(io: T) -> Self {
+        Self::new(Server(Transport::new(io)))
+    }
+}
+
+impl<S, T> Chan<S, Client<Transport<T>>> {
+    pub fn client(io: T) -> Self {
+        Self::new(Client(Transport::new(io)))
+    }
+}
+
+impl<S, L> Chan<S, Server<L>> {
+    /// a server on a link already set up, like a recording `Transport`
+    pub fn server_on(link: L) -> Self {
+        Self::new(Server(link))
+    }
+}
+
+impl<S, L> Chan<S, Client<L>> {
+    /// a client on a link already set up, like a recording `Transport`
+    pub fn client_on(link: L) -> Self {
+        Self::new(Client(link))
+    }
+}
+
+impl<S, R: Role> Chan<S, R> {
+    async fn put<A, N>(mut self, msg: A) -> Result<Chan<N, R>, Error>
+    where
+        R::Link: Link<A>,
+    {
+        self.role.link().send(msg).await?;
+        Ok(Chan::new(self.role))
+    }
+
+    async fn take<A, N>(mut self) -> Result<(A, Chan<N, R>), Error>
+    where
+        R::Link: Link<A>,
+    {
+        let msg = self.role.link().recv().await?;
+        Ok((msg, Chan::new(self.role)))
+    }
+
+    async fn offered<E: Select>(mut self) -> Result<E::On<R>, Error>
+    where
+        R::Link: Link<u64>,
+    {
+        let tag = self.role.link().recv().await?;
+        E::On::branch(tag, self.role).ok_or(Violation::UnknownBranch(tag).into())
+    }
+
+    async fn chosen<E: Select, N>(
+        self,
+        arm: impl FnOnce(Chan<N, R>) -> E::On<R>,
+    ) -> Result<Chan<N, R>, Error>
+    where
+        R::Link: Link<u64>,
+    {
+        let branch = arm(Chan::new(self.role));
+        let tag = branch.tag();
+        let mut role = branch.into_role();
+        role.link().send(tag).await?;
+        Ok(Chan::new(role))
+    }
+}
+
+impl<A, S, L: Link<A>> Chan<Next<Send<A>, S>, Server<L>> {
+    pub async fn send(self, msg: A) -> Result<Chan<S, Server<L>>, Error> {
+        self.put(msg).await
+    }
+}
+
+impl<A, S, L: Link<A>> Chan<Next<Recv<A>, S>, Server<L>> {
+    pub async fn recv(self) -> Result<(A, Chan<S, Server<L>>), Error> {
+        self.take().await
+    }
+}
+
+impl<A, S, L: Link<A>> Chan<Next<Send<A>, S>, Client<L>> {
+    pub async fn recv(self) -> Result<(A, Chan<S, Client<L>>), Error> {
+        self.take().await
+    }
+}
+
+impl<A, S, L: Link<A>> Chan<Next<Recv<A>, S>, Client<L>> {
+    pub async fn send(self, msg: A) -> Result<Chan<S, Client<L>>, Error> {
+        self.put(msg).await
+    }
+}
+
+impl<E: Select, L: Link<u64>> Chan<Offer<E>, Server<L>> {
+    /// wait for the peer to pick a branch
+    pub async fn offer(self) -> Result<E::On<Server<L>>, Error> {
+        self.offered::<E>().await
+    }
+}
+
+impl<E: Select, L: Link<u64>> Chan<Choose<E>, Server<L>> {
+    /// pick the branch built by `arm`, a variant of the generated enum
+    pub async fn choose<N>(
+        self,
+        arm: impl FnOnce(Chan<N, Server<L>>) -> E::On<Server<L>>,
+    ) -> Result<Chan<N, Server<L>>, Error> {
+        self.chosen::<E, N>(arm).await
+    }
+}
+
+impl<E: Select, L: Link<u64>> Chan<Choose<E>, Client<L>> {
+    /// wait for the peer to pick a branch
+    pub async fn offer(self) -> Result<E::On<Client<L>>, Error> {
+        self.offered::<E>().await
+    }
+}
+
+impl<E: Select, L: Link<u64>> Chan<Offer<E>, Client<L>> {
+    /// pick the branch built by `arm`, a variant of the generated enum
+    pub async fn choose<N>(
+        self,
+        arm: impl FnOnce(Chan<N, Client<L>>) -> E::On<Client<L>>,
+    ) -> Result<Chan<N, Client<L>>, Error> {
+        self.chosen::<E, N>(arm).await
+    }
+}
+
+impl<R: Role> Chan<Endpoint, R> {
+    /// the session is over, hand back the link
+    pub fn close(self) -> R::Link {
+        self.role.into_link()
+    }
+}
@@ -0,0 +1,157 @@
+//! Session traces
+//!
+//! A trace is a JSON line per frame, recorded by a `Transport` given a
+//! `Recorder`. `validate` checks a trace against the session, `replay`
+//! plays one side of it against a live peer.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, LineWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::monitor::{Monitor, Observed, Protocol, Side};
+use super::transport::{Error, Transport, Violation};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// microseconds since the unix epoch
+    pub time: u64,
+    /// index into `Protocol::states` before the frame
+    pub state: usize,
+    pub from: Side,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+    /// why the frame doesn't fit the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub violation: Option<String>,
+    /// the frame as sent, in hex
+    pub frame: String,
+}
+
+/// writes the events of one session
+pub struct Recorder {
+    monitor: Monitor,
+    out: Box<dyn Write + std::marker::Send>,
+}
+
+impl Recorder {
+    pub fn new(def: Arc<Protocol>, out: impl Write + std::marker::Send + 'static) -> Self {
+        Self {
+            monitor: Monitor::new(def),
+            out: Box::new(out),
+        }
+    }
+
+    /// one event per line, written as it happens
+    pub fn create(def: Arc<Protocol>, path: &Path) -> io::Result<Self> {
+        Ok(Self::new(def, LineWriter::new(File::create(path)?)))
+    }
+
+    /// a frame that violates the session is recorded with the violation,
+    /// and the session is followed from where it was
+    pub fn record(&mut self, from: Side, frame: &[u8]) -> io::Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_micros() as u64);
+        let mut event = Event {
+            time,
+            state: self.monitor.index(),
+            from,
+            branch: None,
+            payload: None,
+            violation: None,
+            frame: hex(frame),
+        };
+        match self.monitor.observe(from, frame) {
+            Ok(Observed::Message(payload)) => event.payload = Some(payload),
+            Ok(Observed::Branch(tag)) => event.branch = Some(tag),
+            Err(e) => event.violation = Some(e.to_string()),
+        }
+        serde_json::to_writer(&mut self.out, &event)?;
+        self.out.write_all(b"\n")
+    }
+}
+
+pub fn read_trace(r: impl BufRead) -> io::Result<Vec<Event>> {
+    r.lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// the index of the first event the session doesn't allow, and why
+pub fn validate(def: Arc<Protocol>, events: &[Event]) -> Result<(), (usize, Error)> {
+    let mut monitor = Monitor::new(def);
+    for (i, e) in events.iter().enumerate() {
+        if e.state != monitor.index() {
+            return Err((i, Violation::Diverged(i).into()));
+        }
+        monitor
+            .check(e.from, &frame(e).map_err(|err| (i, err))?)
+            .map_err(|err| (i, err))?;
+    }
+    Ok(())
+}
+
+/// sends the frames of `side` to `io` and checks the frames it answers
+/// with, returns the indices of the answers whose payload differs from the
+/// recorded one. The peer has to take the same branches as in the trace.
+pub async fn replay<T>(
+    def: Arc<Protocol>,
+    events: &[Event],
+    side: Side,
+    io: T,
+) -> Result<Vec<usize>, Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut monitor = Monitor::new(def);
+    let mut transport = Transport::new(io);
+    let mut differs = vec![];
+    for (i, e) in events.iter().enumerate() {
+        if e.state != monitor.index() {
+            return Err(Violation::Diverged(i).into());
+        }
+        let recorded = frame(e)?;
+        if e.from == side {
+            monitor.check(side, &recorded)?;
+            transport.send_frame(&recorded).await?;
+        } else {
+            let answer = transport.recv_frame().await?;
+            monitor.check(e.from, answer)?;
+            if answer != recorded {
+                differs.push(i);
+            }
+        }
+    }
+    Ok(differs)
+}
+
+fn frame(e: &Event) -> Result<Vec<u8>, Error> {
+    unhex(&e.frame).ok_or_else(|| Error::Io(io::Error::other("frame is not hex")))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,199 @@
+//! Framed transport
+//!
+//! Every message is one frame: a big-endian `u32` length followed by the
+//! message in the wire format. The branch picked by `offer`/`choose` is
+//! sent as a frame holding its index.
+
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::monitor::Side;
+use super::trace::Recorder;
+use super::wire::{Decode, DecodeError, Encode};
+
+/// longer frames are rejected before being read or written
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    /// the peer closed the connection
+    Disconnected,
+    Io(io::Error),
+    Decode(DecodeError),
+    Protocol(Violation),
+}
+
+/// the peer sent something the session doesn't allow
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    UnknownBranch(u64),
+    /// bytes left in a frame after its message
+    TrailingBytes(usize),
+    FrameTooLarge(usize),
+    /// a message from the side whose turn it isn't, or after `end`
+    OutOfTurn,
+    /// a replayed session left the trace at this event
+    Diverged(usize),
+    /// a message of another type than the session's, on an in-memory link
+    UnexpectedMessage {
+        expected: &'static str,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "peer disconnected"),
+            Error::Io(e) => Display::fmt(e, f),
+            Error::Decode(e) => Display::fmt(e, f),
+            Error::Protocol(e) => write!(f, "protocol violation: {}", e),
+        }
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::UnknownBranch(tag) => write!(f, "unknown branch {}", tag),
+            Violation::TrailingBytes(n) => write!(f, "{} bytes after message", n),
+            Violation::FrameTooLarge(len) => {
+                write!(f, "frame of {} bytes exceeds {}", len, MAX_FRAME_LEN)
+            }
+            Violation::OutOfTurn => write!(f, "message out of turn"),
+            Violation::Diverged(event) => write!(f, "diverged from the trace at event {}", event),
+            Violation::UnexpectedMessage { expected } => {
+                write!(f, "expected a message of type {}", expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted => Error::Disconnected,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
+
+impl From<Violation> for Error {
+    fn from(e: Violation) -> Self {
+        Error::Protocol(e)
+    }
+}
+
+/// carries the messages of type `M` between the two ends of a session
+pub trait Link<M> {
+    fn send(&mut self, msg: M) -> impl Future<Output = Result<(), Error>>;
+    fn recv(&mut self) -> impl Future<Output = Result<M, Error>>;
+}
+
+pub struct Transport<T> {
+    io: T,
+    buf: Vec<u8>,
+    /// the side this end runs, and where its frames are recorded
+    trace: Option<(Side, Recorder)>,
+}
+
+impl<T> Transport<T> {
+    pub fn new(io: T) -> Self {
+        Self {
+            io,
+            buf: vec![],
+            trace: None,
+        }
+    }
+
+    /// record every frame sent or received, for the `side` this end runs
+    pub fn record(mut self, side: Side, recorder: Recorder) -> Self {
+        self.trace = Some((side, recorder));
+        self
+    }
+
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<T: AsyncWrite + Unpin> Transport<T> {
+    pub async fn send<M: Encode>(&mut self, msg: &M) -> Result<(), Error> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        msg.encode(&mut buf);
+        let res = self.send_frame(&buf).await;
+        self.buf = buf;
+        res
+    }
+
+    /// send an already encoded message
+    pub async fn send_frame(&mut self, frame: &[u8]) -> Result<(), Error> {
+        if frame.len() > MAX_FRAME_LEN {
+            return Err(Violation::FrameTooLarge(frame.len()).into());
+        }
+        if let Some((side, recorder)) = &mut self.trace {
+            recorder.record(*side, frame)?;
+        }
+        self.io
+            .write_all(&(frame.len() as u32).to_be_bytes())
+            .await?;
+        self.io.write_all(frame).await?;
+        Ok(self.io.flush().await?)
+    }
+
+    /// close the sending direction, the peer reads the end of the stream
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        Ok(self.io.shutdown().await?)
+    }
+}
+
+impl<T: AsyncRead + Unpin> Transport<T> {
+    pub async fn recv<M: Decode>(&mut self) -> Result<M, Error> {
+        let mut rest = self.recv_frame().await?;
+        let msg = M::decode(&mut rest)?;
+        if !rest.is_empty() {
+            return Err(Violation::TrailingBytes(rest.len()).into());
+        }
+        Ok(msg)
+    }
+
+    /// the next message, still encoded
+    pub async fn recv_frame(&mut self) -> Result<&[u8], Error> {
+        let mut len = [0; 4];
+        self.io.read_exact(&mut len).await?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(Violation::FrameTooLarge(len).into());
+        }
+        self.buf.resize(len, 0);
+        self.io.read_exact(&mut self.buf).await?;
+        if let Some((side, recorder)) = &mut self.trace {
+            recorder.record(side.peer(), &self.buf)?;
+        }
+        Ok(&self.buf)
+    }
+}
+
+impl<M: Encode + Decode, T: AsyncRead + AsyncWrite + Unpin> Link<M> for Transport<T> {
+    async fn send(&mut self, msg: M) -> Result<(), Error> {
+        Transport::send(self, &msg).await
+    }
+
+    async fn recv(&mut self) -> Result<M, Error> {
+        Transport::recv(self).await
+    }
+}
@@ -0,0 +1,74 @@
+//! Refinement checks for struct fields annotated with
+//! `#[range=..]`, `#[min_len=..]`, `#[max_len=..]` or `#[pattern=..]`.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::sync::{Mutex, OnceLock};
+
+use regex::Regex;
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub constraint: &'static str,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "field {} violates {}", self.field, self.constraint)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// length as seen by `min_len` and `max_len`, strings count chars
+pub trait Length {
+    fn length(&self) -> usize;
+}
+
+impl Length for String {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl<T: Length> Length for Box<T> {
+    fn length(&self) -> usize {
+        self.as_ref().length()
+    }
+}
+
+impl<T> Length for Vec<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T, const N: usize> Length for [T; N] {
+    fn length(&self) -> usize {
+        N
+    }
+}
+
+impl<K, V> Length for HashMap<K, V> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+/// whole-string match, patterns are compiled once and cached
+pub fn matches_pattern(value: &str, pattern: &'static str) -> bool {
+    static CACHE: OnceLock<Mutex<HashMap<&'static str, Regex>>> = OnceLock::new();
+    let mut cache = CACHE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let regex = cache.entry(pattern).or_insert_with(|| {
+        Regex::new(&format!("^(?:{})$", pattern)).expect("pattern is checked by type checker")
+    });
+    regex.is_match(value)
+}
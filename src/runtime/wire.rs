@@ -0,0 +1,301 @@
+//! Wire format
+//!
+//! Messages are MessagePack values:
+//! - `struct`s are arrays of their fields by tag, untagged ones last in
+//!   definition order
+//! - `enum`s are `[tag, payload]`, `tag` being the item's `= n` or its index
+//! - `option<T>` is `nil` or `T`
+//! - literal message types are their constant
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display};
+use std::hash::Hash;
+
+use rmp::decode;
+use rmp::encode;
+
+use super::validate::ValidationError;
+
+pub trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    /// decode one value from the front of `buf` and advance past it
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    Malformed(String),
+    LengthMismatch { expected: usize, found: usize },
+    UnknownVariant(u64),
+    UnexpectedLiteral { expected: String, found: String },
+    Invalid(ValidationError),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Malformed(e) => write!(f, "malformed message: {}", e),
+            DecodeError::LengthMismatch { expected, found } => {
+                write!(f, "expected {} elements, found {}", expected, found)
+            }
+            DecodeError::UnknownVariant(tag) => write!(f, "unknown variant tag {}", tag),
+            DecodeError::UnexpectedLiteral { expected, found } => {
+                write!(f, "expected literal {}, found {}", expected, found)
+            }
+            DecodeError::Invalid(e) => Display::fmt(e, f),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<ValidationError> for DecodeError {
+    fn from(e: ValidationError) -> Self {
+        DecodeError::Invalid(e)
+    }
+}
+
+fn malformed(e: impl Display) -> DecodeError {
+    DecodeError::Malformed(e.to_string())
+}
+
+// writing to a `Vec<u8>` can't fail
+macro_rules! write {
+    ($e: expr) => {
+        $e.expect("write to Vec<u8>")
+    };
+}
+
+pub fn encode_array_len(buf: &mut Vec<u8>, len: usize) {
+    write!(encode::write_array_len(buf, len as u32));
+}
+
+pub fn decode_array_len(buf: &mut &[u8], expected: usize) -> Result<(), DecodeError> {
+    let found = decode::read_array_len(buf).map_err(malformed)? as usize;
+    if found != expected {
+        return Err(DecodeError::LengthMismatch { expected, found });
+    }
+    Ok(())
+}
+
+pub fn encode_variant<T: Encode>(buf: &mut Vec<u8>, tag: u64, payload: &T) {
+    encode_array_len(buf, 2);
+    tag.encode(buf);
+    payload.encode(buf);
+}
+
+/// returns the tag, the payload is left in `buf`
+pub fn decode_variant(buf: &mut &[u8]) -> Result<u64, DecodeError> {
+    decode_array_len(buf, 2)?;
+    u64::decode(buf)
+}
+
+/// value-level singleton type, generated for literal payloads like `recv 1`
+pub trait Literal: Default {
+    type Value: Encode + Decode + PartialEq + Debug;
+    fn value() -> Self::Value;
+}
+
+pub fn encode_literal<L: Literal>(buf: &mut Vec<u8>) {
+    L::value().encode(buf);
+}
+
+pub fn decode_literal<L: Literal>(buf: &mut &[u8]) -> Result<L, DecodeError> {
+    let found = L::Value::decode(buf)?;
+    let expected = L::value();
+    if found != expected {
+        return Err(DecodeError::UnexpectedLiteral {
+            expected: format!("{:?}", expected),
+            found: format!("{:?}", found),
+        });
+    }
+    Ok(L::default())
+}
+
+impl Encode for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write!(encode::write_bool(buf, *self));
+    }
+}
+
+impl Decode for bool {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode::read_bool(buf).map_err(malformed)
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write!(encode::write_sint(buf, *self));
+    }
+}
+
+impl Decode for i64 {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode::read_int(buf).map_err(malformed)
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write!(encode::write_uint(buf, *self));
+    }
+}
+
+impl Decode for u64 {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode::read_int(buf).map_err(malformed)
+    }
+}
+
+// narrower ints, for `[codegen.rust.types]` mappings like `uint = "u32"`
+macro_rules! int_codec {
+    ($write: ident, $wide: ty, $($t: ty),*) => {$(
+        impl Encode for $t {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                write!(encode::$write(buf, *self as $wide));
+            }
+        }
+
+        impl Decode for $t {
+            fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+                decode::read_int(buf).map_err(malformed)
+            }
+        }
+    )*};
+}
+
+int_codec!(write_sint, i64, i8, i16, i32);
+int_codec!(write_uint, u64, u8, u16, u32);
+
+impl Encode for f32 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write!(encode::write_f32(buf, *self));
+    }
+}
+
+impl Decode for f32 {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode::read_f32(buf).map_err(malformed)
+    }
+}
+
+impl Encode for f64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write!(encode::write_f64(buf, *self));
+    }
+}
+
+impl Decode for f64 {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode::read_f64(buf).map_err(malformed)
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write!(encode::write_str(buf, self));
+    }
+}
+
+impl Decode for String {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode::read_str_len(buf).map_err(malformed)? as usize;
+        if buf.len() < len {
+            return Err(malformed("string is longer than the message"));
+        }
+        let (s, rest) = buf.split_at(len);
+        *buf = rest;
+        std::str::from_utf8(s)
+            .map(str::to_string)
+            .map_err(malformed)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Some(v) => v.encode(buf),
+            None => write!(encode::write_nil(buf)),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        // 0xc0 is the `nil` marker
+        if buf.first() == Some(&0xc0) {
+            *buf = &buf[1..];
+            Ok(None)
+        } else {
+            T::decode(buf).map(Some)
+        }
+    }
+}
+
+impl<T: Encode> Encode for Box<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_ref().encode(buf)
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        T::decode(buf).map(Box::new)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_array_len(buf, self.len());
+        self.iter().for_each(|v| v.encode(buf));
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode::read_array_len(buf).map_err(malformed)?;
+        (0..len).map(|_| T::decode(buf)).collect()
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_array_len(buf, N);
+        self.iter().for_each(|v| v.encode(buf));
+    }
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        decode_array_len(buf, N)?;
+        let items = (0..N)
+            .map(|_| T::decode(buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(items
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("decoded exactly N items")))
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for HashMap<K, V> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write!(encode::write_map_len(buf, self.len() as u32));
+        for (k, v) in self {
+            k.encode(buf);
+            v.encode(buf);
+        }
+    }
+}
+
+impl<K: Decode + Eq + Hash, V: Decode> Decode for HashMap<K, V> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = decode::read_map_len(buf).map_err(malformed)?;
+        (0..len)
+            .map(|_| Ok((K::decode(buf)?, V::decode(buf)?)))
+            .collect()
+    }
+}
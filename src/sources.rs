@@ -0,0 +1,97 @@
+//! IDL file discovery
+//!
+//! Walks input roots recursively for `.lstr` files. Include and exclude
+//! globs are matched against paths relative to the root, so outputs can
+//! mirror the input tree.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use walkdir::WalkDir;
+
+pub const IDL_EXTENSION: &str = "lstr";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub path: PathBuf,
+    /// `path` relative to its root
+    pub relative: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sources {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: GlobSet::empty(),
+        }
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, String> {
+    let mut set = GlobSetBuilder::new();
+    for glob in globs {
+        set.add(Glob::new(glob).map_err(|e| e.to_string())?);
+    }
+    set.build().map_err(|e| e.to_string())
+}
+
+impl Sources {
+    /// an empty `include` takes every IDL file
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            include: match include {
+                [] => None,
+                include => Some(glob_set(include)?),
+            },
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    /// whether the file at `relative` to its root is an IDL file to take
+    pub fn matches(&self, relative: &Path) -> bool {
+        relative.extension().is_some_and(|e| e == IDL_EXTENSION)
+            && self.include.as_ref().is_none_or(|i| i.is_match(relative))
+            && !self.exclude.is_match(relative)
+    }
+
+    /// IDL files under `root` in path order, or `root` itself if it is a file
+    pub fn files(&self, root: &Path) -> io::Result<Vec<Source>> {
+        if root.is_file() {
+            return Ok(vec![Source {
+                path: root.to_path_buf(),
+                relative: root.file_name().map(PathBuf::from).unwrap_or_default(),
+            }]);
+        }
+        let mut files = vec![];
+        let walk = WalkDir::new(root)
+            .sort_by_file_name()
+            .into_iter()
+            // hidden directories like `.git`
+            .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+        for entry in walk {
+            let entry = entry.map_err(io::Error::other)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(root)
+                .expect("walkdir yields paths under its root")
+                .to_path_buf();
+            if self.matches(&relative) {
+                files.push(Source {
+                    path: entry.into_path(),
+                    relative,
+                });
+            }
+        }
+        Ok(files)
+    }
+}
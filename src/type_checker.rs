@@ -1,63 +1,996 @@
 //! Type checker
 //!
-//! steps:
-//! 1. check `end` exists?
-//! 2. check `ring` exists?
-//! 3. check if could generate dual type
-//!
-//!
+//! `check` runs every pass over the IR:
+//! 1. names are defined once, exist and are used as their kind
+//! 2. defaults, constants and constraints fit their types
+//! 3. no struct contains itself
+//! 4. sessions reach `end` and only recurse after communicating
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use petgraph::{
+    algo::{is_cyclic_directed, tarjan_scc},
+    graph::NodeIndex,
+    visit::{Dfs, DfsPostOrder},
+    Graph,
+};
+
+use regex::Regex;
+
+use crate::diagnostic::{Diagnostic, Fix};
+use crate::ir::{
+    AnnotationValue, Branch, Def, DefId, DefKind, Ir, Length, Loc, Ref, Session, SessionType,
+    Symbol, Target, Ty,
+};
+use crate::parser;
+
+use crate::ast::{Constant, Constraint, SimpleType};
+
+/// a name as written, compared by its text
+#[derive(Debug, Clone)]
+pub struct Name {
+    pub text: String,
+    pub loc: Option<Loc>,
+}
+
+impl Name {
+    pub fn new(text: &str, loc: Option<Loc>) -> Self {
+        Self {
+            text: text.to_string(),
+            loc,
+        }
+    }
+
+    pub fn of(ir: &Ir, r: &Ref) -> Self {
+        Self::new(ir.name(r.name), r.loc)
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl From<&str> for Name {
+    fn from(text: &str) -> Self {
+        Self::new(text, None)
+    }
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// a definition, field or variant named like an earlier one
+    Duplicate {
+        namespace: Namespace,
+        name: Name,
+        first: Name,
+    },
+    NameIsNotFound(Name),
+    ArityMismatch {
+        name: Name,
+        expected: usize,
+        found: usize,
+    },
+    KindMismatch {
+        name: Name,
+        expected: Kind,
+        found: Kind,
+    },
+    CannotMonomorphize(Name),
+    InvalidDefault {
+        field: Name,
+        value: Constant,
+    },
+    InvalidConstant {
+        name: Name,
+        value: Constant,
+    },
+    InvalidArrayLength(Name),
+    InvalidConstraint {
+        field: Name,
+        constraint: Name,
+    },
+    ConstraintNotApplicable {
+        field: Name,
+        constraint: Name,
+    },
+    RecursiveAlias(Name),
+    InfiniteSize(Name),
+    UnterminatedSession(Name),
+    UnreachableAfterEnd(Name),
+    EmptyBranch(Name),
+    UnguardedRecursion(Name),
+}
 
-use std::collections::HashMap;
+/// names which have to be unique among each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Definition,
+    /// the fields of one struct
+    Field,
+    /// the variants of one enum
+    Variant,
+}
 
-use petgraph::{algo::is_cyclic_directed, Graph};
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Duplicate { namespace, .. } => match namespace {
+                Namespace::Definition => "duplicate-definition",
+                Namespace::Field => "duplicate-field",
+                Namespace::Variant => "duplicate-variant",
+            },
+            Error::NameIsNotFound(_) => "name-not-found",
+            Error::ArityMismatch { .. } => "arity-mismatch",
+            Error::KindMismatch { .. } => "kind-mismatch",
+            Error::CannotMonomorphize(_) => "cannot-monomorphize",
+            Error::InvalidDefault { .. } => "invalid-default",
+            Error::InvalidConstant { .. } => "invalid-constant",
+            Error::InvalidArrayLength(_) => "invalid-array-length",
+            Error::InvalidConstraint { .. } => "invalid-constraint",
+            Error::ConstraintNotApplicable { .. } => "constraint-not-applicable",
+            Error::RecursiveAlias(_) => "recursive-alias",
+            Error::InfiniteSize(_) => "infinite-size",
+            Error::UnterminatedSession(_) => "unterminated-session",
+            Error::UnreachableAfterEnd(_) => "unreachable-after-end",
+            Error::EmptyBranch(_) => "empty-branch",
+            Error::UnguardedRecursion(_) => "unguarded-recursion",
+        }
+    }
 
-use crate::ast::{Def, GetFields, GetName, Type, TypeOrName};
+    /// located with the spans `ir` recorded when it was lowered
+    pub fn diagnostic(&self, ir: &Ir) -> Diagnostic {
+        let defined = |name: &Name| ir.lookup(&name.text).and_then(|id| ir.def(id).loc);
+        let (name, message) = match self {
+            Error::Duplicate {
+                namespace, name, ..
+            } => {
+                let what = match namespace {
+                    Namespace::Definition => "",
+                    Namespace::Field => "field ",
+                    Namespace::Variant => "variant ",
+                };
+                (
+                    name,
+                    format!("{}`{}` is defined more than once", what, name),
+                )
+            }
+            Error::NameIsNotFound(name) => (name, format!("cannot find `{}`", name)),
+            Error::ArityMismatch {
+                name,
+                expected,
+                found,
+            } => (
+                name,
+                format!(
+                    "`{}` takes {} generic arguments, found {}",
+                    name, expected, found
+                ),
+            ),
+            Error::KindMismatch {
+                name,
+                expected,
+                found,
+            } => (
+                name,
+                format!("expected a {}, `{}` is a {}", expected, name, found),
+            ),
+            Error::CannotMonomorphize(name) => (name, format!("cannot monomorphize `{}`", name)),
+            Error::InvalidDefault { field, value } => (
+                field,
+                format!("default {:?} doesn't fit the type of `{}`", value, field),
+            ),
+            Error::InvalidConstant { name, value } => (
+                name,
+                format!("value {:?} doesn't fit the type of `{}`", value, name),
+            ),
+            Error::InvalidArrayLength(name) => (
+                name,
+                format!("`{}` is not a non-negative integer constant", name),
+            ),
+            Error::InvalidConstraint { field, constraint } => (
+                constraint,
+                format!("invalid `{}` constraint on `{}`", constraint, field),
+            ),
+            Error::ConstraintNotApplicable { field, constraint } => (
+                constraint,
+                format!(
+                    "`{}` constraint doesn't apply to the type of `{}`",
+                    constraint, field
+                ),
+            ),
+            Error::RecursiveAlias(name) => (name, format!("`{}` is an alias of itself", name)),
+            Error::InfiniteSize(name) => (
+                name,
+                format!(
+                    "`{}` contains itself, refer to it through an array, a dict or an option",
+                    name
+                ),
+            ),
+            Error::UnterminatedSession(name) => (
+                name,
+                format!(
+                    "a path of `{}` ends without `end` or continuing as a channel",
+                    name
+                ),
+            ),
+            Error::UnreachableAfterEnd(name) => (
+                name,
+                format!(
+                    "nothing can follow `end`, `offer` or `choose` in `{}`",
+                    name
+                ),
+            ),
+            Error::EmptyBranch(name) => (name, format!("`{}` has an empty branch", name)),
+            Error::UnguardedRecursion(name) => (
+                name,
+                format!(
+                    "`{}` continues as itself without sending or receiving anything",
+                    name
+                ),
+            ),
+        };
+        let span = name.loc.map(|loc| loc.span);
+        let mut diagnostic = Diagnostic::error(self.code(), message).with_span(span);
+        if let Some(file) = name.loc.and_then(|loc| ir.file(loc.file)) {
+            diagnostic = diagnostic.in_file(file);
+        }
+        match self {
+            Error::Duplicate { first, .. } => {
+                diagnostic = diagnostic.with_related(
+                    format!("`{}` is first defined here", first),
+                    first.loc.and_then(|loc| ir.file(loc.file)),
+                    first.loc.map(|loc| loc.span),
+                );
+            }
+            Error::ArityMismatch { name, .. } | Error::KindMismatch { name, .. } => {
+                let loc = defined(name);
+                diagnostic = diagnostic.with_related(
+                    format!("`{}` is defined here", name),
+                    loc.and_then(|loc| ir.file(loc.file)),
+                    loc.map(|loc| loc.span),
+                );
+            }
+            Error::NameIsNotFound(name) => {
+                let text = name.text.as_str();
+                let similar = ir
+                    .defs()
+                    .map(|(_, def)| ir.name(def.name))
+                    .filter(|def| distance(text, def) <= text.chars().count().div_ceil(3))
+                    .min_by_key(|def| distance(text, def));
+                if let (Some(similar), Some(span)) = (similar, span) {
+                    diagnostic = diagnostic.with_fix(Fix {
+                        message: format!("did you mean `{}`?", similar),
+                        span,
+                        replacement: similar.to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+        diagnostic
+    }
+}
 
-pub enum Error<'a> {
-    NameIsNotFound(&'a str),
+/// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replace = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replace.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
 }
 
-pub fn ring_checker<'a>(irs: &'a [Def]) -> Result<bool, Error<'a>> {
-    let g = ir2graph(irs)?;
-    Ok(is_cyclic_directed(&g))
+/// `struct`, `enum` and generic parameters are types, `channel`s are sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Type,
+    Session,
+    Constant,
 }
 
-pub fn ir2graph<'a>(irs: &'a [Def]) -> Result<Graph<Type<'a>, ()>, Error<'a>> {
-    let table: HashMap<_, _> = irs
-        .iter()
-        .map(|def| (def.get_name(), def.clone().into()))
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Kind::Type => "type",
+            Kind::Session => "session",
+            Kind::Constant => "constant",
+        })
+    }
+}
+
+impl Kind {
+    /// `None` for `type` aliases, which take the kind of what they name
+    pub fn of(def: &Def) -> Option<Self> {
+        match def.kind {
+            DefKind::Session(_) => Some(Kind::Session),
+            DefKind::Struct(_) | DefKind::Enum(_) => Some(Kind::Type),
+            DefKind::Const(_, _) => Some(Kind::Constant),
+            DefKind::Alias(_) => None,
+        }
+    }
+}
+
+/// kind of every definition, with aliases resolved
+fn kind_table(ir: &Ir) -> Result<Vec<Kind>, Error> {
+    let mut table = ir.defs().map(|(_, def)| Kind::of(def)).collect::<Vec<_>>();
+    let mut aliases = ir
+        .defs()
+        .filter_map(|(id, def)| match &def.kind {
+            DefKind::Alias(ty) => Some((id, def, ty)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    while let Some((_, first, _)) = aliases.first() {
+        let first = Name::new(ir.name(first.name), first.loc);
+        let pending = aliases.len();
+        aliases.retain(|(id, _, ty)| {
+            let kind = match ty {
+                Ty::Ref(r) => match r.target {
+                    Target::Param => Some(Kind::Type),
+                    // `None` until the alias it names is resolved
+                    Target::Def(target) => table[target.index()],
+                    // reported as `NameIsNotFound` by the scope check
+                    Target::Unresolved => Some(Kind::Type),
+                },
+                Ty::Session(_) => Some(Kind::Session),
+                _ => Some(Kind::Type),
+            };
+            match kind {
+                Some(kind) => {
+                    table[id.index()] = Some(kind);
+                    false
+                }
+                None => true,
+            }
+        });
+        if aliases.len() == pending {
+            return Err(Error::RecursiveAlias(first));
+        }
+    }
+    Ok(table
+        .into_iter()
+        .map(|kind| kind.unwrap_or(Kind::Type))
+        .collect())
+}
+
+/// check no two definitions, and no two fields or variants of one
+/// definition, have the same name
+pub fn duplicate_checker(ir: &Ir) -> Result<(), Error> {
+    fn unique(
+        ir: &Ir,
+        namespace: Namespace,
+        names: impl Iterator<Item = (Symbol, Option<Loc>)>,
+    ) -> Result<(), Error> {
+        let mut seen = HashMap::new();
+        for (name, loc) in names {
+            if let Some(first) = seen.insert(name, loc) {
+                let text = ir.name(name);
+                return Err(Error::Duplicate {
+                    namespace,
+                    name: Name::new(text, loc),
+                    first: Name::new(text, first),
+                });
+            }
+        }
+        Ok(())
+    }
+    let defs = ir.defs().map(|(_, def)| (def.name, def.loc));
+    unique(ir, Namespace::Definition, defs)?;
+    ir.defs().try_for_each(|(_, def)| match &def.kind {
+        DefKind::Struct(fields) => {
+            unique(ir, Namespace::Field, fields.iter().map(|f| (f.name, f.loc)))
+        }
+        DefKind::Enum(variants) => unique(
+            ir,
+            Namespace::Variant,
+            variants.iter().map(|v| (v.name, v.loc)),
+        ),
+        _ => Ok(()),
+    })
+}
+
+/// check every referenced name exists, is applied to the right number of
+/// generic arguments and is used where its kind is expected.
+pub fn kind_checker(ir: &Ir) -> Result<(), Error> {
+    extern_kind_checker(ir, &[])
+}
+
+/// `kind_checker` where the names in `externs` are types defined outside the
+/// IDL, see `config::Target::types`
+pub fn extern_kind_checker(ir: &Ir, externs: &[&str]) -> Result<(), Error> {
+    let table = kind_table(ir)?;
+    let lengths = ir
+        .defs()
+        .filter_map(|(id, def)| match def.kind {
+            DefKind::Const(_, Constant::Uint(_) | Constant::Int(0..)) => Some(id),
+            _ => None,
+        })
         .collect();
-    let r: Result<_, Error> = irs
-        .iter()
-        .map(|def| {
-            def.get_fields()
-                .into_iter()
-                .map(|type_or_name| -> Result<(Type<'a>, Type<'a>), Error> {
-                    Ok((
-                        def.clone().into(),
-                        unify_type_or_name(&type_or_name, &table)?,
-                    ))
+    let scope = KindScope {
+        ir,
+        table: &table,
+        lengths: &lengths,
+        externs,
+    };
+    for (_, def) in ir.defs() {
+        match &def.kind {
+            DefKind::Session(s) => scope.check_session(s)?,
+            DefKind::Struct(fields) => fields.iter().try_for_each(|i| {
+                scope.check_type(&i.ty, Some(Kind::Type))?;
+                i.annotations.iter().try_for_each(|a| match &a.value {
+                    AnnotationValue::Const(r) => scope.resolve(r, Some(Kind::Constant)),
+                    AnnotationValue::Constant(_) => Ok(()),
                 })
-                .collect::<Result<Vec<_>, Error>>()
+            })?,
+            DefKind::Enum(variants) => variants
+                .iter()
+                .try_for_each(|i| scope.check_type(&i.ty, Some(Kind::Type)))?,
+            DefKind::Const(ty, _) => scope.check_type(ty, Some(Kind::Type))?,
+            DefKind::Alias(ty) => scope.check_type(ty, None)?,
+        }
+    }
+    Ok(())
+}
+
+struct KindScope<'t> {
+    ir: &'t Ir,
+    table: &'t [Kind],
+    /// constants usable as array lengths
+    lengths: &'t HashSet<DefId>,
+    externs: &'t [&'t str],
+}
+
+impl KindScope<'_> {
+    fn resolve(&self, r: &Ref, expected: Option<Kind>) -> Result<(), Error> {
+        let (kind, arity) = match r.target {
+            Target::Param => (Kind::Type, 0),
+            Target::Def(id) => (self.table[id.index()], self.ir.def(id).generics.len()),
+            Target::Unresolved if self.externs.contains(&self.ir.name(r.name)) => (Kind::Type, 0),
+            Target::Unresolved => return Err(Error::NameIsNotFound(Name::of(self.ir, r))),
+        };
+        if arity != r.args.len() {
+            return Err(Error::ArityMismatch {
+                name: Name::of(self.ir, r),
+                expected: arity,
+                found: r.args.len(),
+            });
+        }
+        match expected {
+            Some(expected) if expected != kind => Err(Error::KindMismatch {
+                name: Name::of(self.ir, r),
+                expected,
+                found: kind,
+            }),
+            _ => r
+                .args
+                .iter()
+                .try_for_each(|a| self.check_type(a, Some(Kind::Type))),
+        }
+    }
+
+    /// `expected` is `None` for message payloads, which may be data or a delegated session.
+    fn check_type(&self, ty: &Ty, expected: Option<Kind>) -> Result<(), Error> {
+        match ty {
+            Ty::Ref(r) => self.resolve(r, expected),
+            Ty::Session(s) => self.check_session(s),
+            Ty::Array(t, len) => {
+                if let Some(Length::Const(r)) = len {
+                    self.resolve(r, Some(Kind::Constant))?;
+                    if !matches!(r.target, Target::Def(id) if self.lengths.contains(&id)) {
+                        return Err(Error::InvalidArrayLength(Name::of(self.ir, r)));
+                    }
+                }
+                self.check_type(t, Some(Kind::Type))
+            }
+            Ty::Dict(_, t) | Ty::Option(t) => self.check_type(t, Some(Kind::Type)),
+            Ty::Simple(_) | Ty::Literal(_) => Ok(()),
+        }
+    }
+
+    fn check_session(&self, s: &SessionType) -> Result<(), Error> {
+        s.0.iter().try_for_each(|s| match s {
+            Session::Recv(t) | Session::Send(t) => self.check_type(t, None),
+            Session::Offer(branches) | Session::Choose(branches) => {
+                branches.iter().try_for_each(|b| match b {
+                    Branch::Ref(r) => self.resolve(r, Some(Kind::Session)),
+                    Branch::Session(s) => self.check_session(s),
+                })
+            }
+            Session::Endpoint => Ok(()),
         })
-        .collect::<Result<Vec<Vec<_>>, Error>>();
-    let r = r?.into_iter().flatten();
+    }
+}
+
+/// run every check pass over `ir`
+pub fn check(ir: &Ir) -> Result<(), Error> {
+    extern_check(ir, &[])
+}
+
+/// `check` with the types defined outside the IDL
+pub fn extern_check(ir: &Ir, externs: &[&str]) -> Result<(), Error> {
+    duplicate_checker(ir)?;
+    extern_kind_checker(ir, externs)?;
+    default_checker(ir)?;
+    const_checker(ir)?;
+    constraint_checker(ir)?;
+    infinite_size_checker(ir)?;
+    session_checker(ir)?;
+    Ok(())
+}
 
-    let mut graph: Graph<Type, ()> = Graph::new();
-    for (node0, node1) in r {
-        let node0 = graph.add_node(node0);
-        let node1 = graph.add_node(node1);
-        graph.add_edge(node0, node1, ());
+fn fields(ir: &Ir) -> impl Iterator<Item = &crate::ir::Field> {
+    ir.defs()
+        .filter_map(|(_, def)| match &def.kind {
+            DefKind::Struct(fields) => Some(fields),
+            _ => None,
+        })
+        .flatten()
+}
+
+/// check `field: T = default <const>` literals against the field type
+pub fn default_checker(ir: &Ir) -> Result<(), Error> {
+    fields(ir).try_for_each(|field| match &field.default {
+        Some(value) if !value_fits(ir, &field.ty, value) => Err(Error::InvalidDefault {
+            field: Name::new(ir.name(field.name), field.loc),
+            value: value.clone(),
+        }),
+        _ => Ok(()),
+    })
+}
+
+/// check `const NAME: T = <const>;` values against their type
+pub fn const_checker(ir: &Ir) -> Result<(), Error> {
+    ir.defs().try_for_each(|(_, def)| match &def.kind {
+        DefKind::Const(ty, value) if !value_fits(ir, ty, value) => Err(Error::InvalidConstant {
+            name: Name::new(ir.name(def.name), def.loc),
+            value: value.clone(),
+        }),
+        _ => Ok(()),
+    })
+}
+
+/// check refinement annotations like `#[range="0..150"]` are well formed
+/// and apply to the type of their field
+pub fn constraint_checker(ir: &Ir) -> Result<(), Error> {
+    for item in fields(ir) {
+        let field = Name::new(ir.name(item.name), item.loc);
+        for a in item.annotations.iter() {
+            let invalid = Error::InvalidConstraint {
+                field: field.clone(),
+                constraint: Name::new(ir.name(a.name), a.loc),
+            };
+            let not_applicable = Error::ConstraintNotApplicable {
+                field: field.clone(),
+                constraint: Name::new(ir.name(a.name), a.loc),
+            };
+            let constraint = match parser::constraint(
+                ir.name(a.name),
+                ir.annotation_value(a).ok_or(invalid.clone())?,
+            ) {
+                Some(c) => c.map_err(|_| invalid.clone())?,
+                None => continue,
+            };
+            // constraints on `option<T>` apply to the value when present
+            let mut ty = &item.ty;
+            while let Some(Ty::Option(t)) = ir.resolve_alias(ty) {
+                ty = t;
+            }
+            let resolved = ir.resolve_alias(ty).ok_or(not_applicable.clone())?;
+            match constraint {
+                Constraint::Range { start, end, .. } => {
+                    if !matches!(
+                        resolved,
+                        Ty::Simple(
+                            SimpleType::Int
+                                | SimpleType::Uint
+                                | SimpleType::Float
+                                | SimpleType::Double
+                        )
+                    ) {
+                        return Err(not_applicable);
+                    }
+                    if start
+                        .iter()
+                        .chain(end.iter())
+                        .any(|bound| !value_fits(ir, ty, bound))
+                    {
+                        return Err(invalid);
+                    }
+                }
+                Constraint::MinLen(_) | Constraint::MaxLen(_) => {
+                    if !matches!(
+                        resolved,
+                        Ty::Simple(SimpleType::String) | Ty::Array(_, _) | Ty::Dict(_, _)
+                    ) {
+                        return Err(not_applicable);
+                    }
+                }
+                Constraint::Pattern(pattern) => {
+                    if resolved != &Ty::Simple(SimpleType::String) {
+                        return Err(not_applicable);
+                    }
+                    Regex::new(&pattern).map_err(|_| invalid)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn value_fits(ir: &Ir, ty: &Ty, value: &Constant) -> bool {
+    let Some(ty) = ir.resolve_alias(ty) else {
+        return false;
+    };
+    match (ty, value) {
+        (Ty::Option(t), value) => value_fits(ir, t, value),
+        (Ty::Simple(SimpleType::Bool), Constant::Bool(_))
+        | (Ty::Simple(SimpleType::String), Constant::String(_))
+        | (Ty::Simple(SimpleType::Int), Constant::Int(_))
+        | (Ty::Simple(SimpleType::Uint), Constant::Uint(_) | Constant::Int(0..))
+        | (
+            Ty::Simple(SimpleType::Float | SimpleType::Double),
+            Constant::Float(_) | Constant::Int(_) | Constant::Uint(_),
+        ) => true,
+        (Ty::Simple(SimpleType::Int), Constant::Uint(u)) => i64::try_from(*u).is_ok(),
+        _ => false,
+    }
+}
+
+/// how a definition refers to another, from the strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Edge {
+    /// stored inline, the referrer is at least as large
+    Value,
+    /// stored inline in an option, which may be absent
+    Optional,
+    /// through a growable container, a `#[boxed]` item, a session or an
+    /// array length
+    Indirect,
+}
+
+/// whether any definition refers to itself, directly or not
+pub fn ring_checker(ir: &Ir) -> bool {
+    is_cyclic_directed(&ir2graph(ir))
+}
+
+/// one node per definition, the node of `DefId` `i` has index `i`, and an
+/// edge from each definition to every definition it refers to.
+/// generic parameters and unresolved names have no node.
+pub fn ir2graph(ir: &Ir) -> Graph<DefId, Edge> {
+    let params = param_edges(ir);
+    let mut graph = Graph::with_capacity(ir.len(), ir.len());
+    for (id, _) in ir.defs() {
+        graph.add_node(id);
+    }
+    for (id, def) in ir.defs() {
+        def_edges(ir, def, &params, &mut |r, edge| {
+            if let Target::Def(target) = r.target {
+                graph.add_edge(node(id), node(target), edge);
+            }
+        });
+    }
+    graph
+}
+
+/// how every definition stores each of its generic parameters, the
+/// strongest edge from its body to the parameter. an argument is stored
+/// the way its parameter is.
+pub fn param_edges(ir: &Ir) -> Vec<Vec<Edge>> {
+    let mut params = ir
+        .defs()
+        .map(|(_, def)| vec![Edge::Indirect; def.generics.len()])
+        .collect::<Vec<_>>();
+    // generic definitions passing their parameters on only strengthen the
+    // edges of each other, until nothing changes
+    loop {
+        let mut changed = false;
+        for (id, def) in ir.defs().filter(|(_, def)| !def.generics.is_empty()) {
+            let mut stored = params[id.index()].clone();
+            def_edges(ir, def, &params, &mut |r, edge| {
+                let param = def.generics.iter().position(|g| *g == r.name);
+                if let (Target::Param, Some(i)) = (r.target, param) {
+                    stored[i] = stored[i].min(edge);
+                }
+            });
+            if stored != params[id.index()] {
+                params[id.index()] = stored;
+                changed = true;
+            }
+        }
+        if !changed {
+            return params;
+        }
+    }
+}
+
+/// the references of the body of `def`
+fn def_edges(ir: &Ir, def: &Def, params: &[Vec<Edge>], f: &mut impl FnMut(&Ref, Edge)) {
+    let item = |annotations| match ir.annotated(annotations, "boxed") {
+        true => Edge::Indirect,
+        false => Edge::Value,
+    };
+    match &def.kind {
+        DefKind::Struct(fields) => fields
+            .iter()
+            .for_each(|i| edges(&i.ty, item(&i.annotations), params, f)),
+        DefKind::Enum(variants) => variants
+            .iter()
+            .for_each(|i| edges(&i.ty, item(&i.annotations), params, f)),
+        DefKind::Alias(ty) => edges(ty, Edge::Value, params, f),
+        DefKind::Const(ty, _) => edges(ty, Edge::Indirect, params, f),
+        DefKind::Session(s) => s.for_each_ref(&mut |r| f(r, Edge::Indirect)),
+    }
+}
+
+fn node(id: DefId) -> NodeIndex {
+    NodeIndex::new(id.index())
+}
+
+/// the references of `ty`, which is stored as `edge`
+fn edges(ty: &Ty, edge: Edge, params: &[Vec<Edge>], f: &mut impl FnMut(&Ref, Edge)) {
+    match ty {
+        Ty::Ref(r) => {
+            f(r, edge);
+            for (i, a) in r.args.iter().enumerate() {
+                let stored = match r.target {
+                    Target::Def(id) => params[id.index()].get(i).copied(),
+                    _ => None,
+                };
+                edges(a, edge.max(stored.unwrap_or(Edge::Indirect)), params, f);
+            }
+        }
+        Ty::Array(t, len) => {
+            if let Some(Length::Const(r)) = len {
+                f(r, Edge::Indirect);
+            }
+            // a fixed length array is stored inline
+            match len {
+                Some(_) => edges(t, edge, params, f),
+                None => edges(t, Edge::Indirect, params, f),
+            }
+        }
+        Ty::Option(t) if edge == Edge::Value => edges(t, Edge::Optional, params, f),
+        Ty::Option(t) => edges(t, edge, params, f),
+        Ty::Dict(_, t) => edges(t, Edge::Indirect, params, f),
+        Ty::Session(s) => s.for_each_ref(&mut |r| f(r, Edge::Indirect)),
+        Ty::Simple(_) | Ty::Literal(_) => {}
+    }
+}
+
+/// every definition after the definitions it refers to, and otherwise in
+/// the order they were defined, for backends which need a definition
+/// before its use. definitions in a cycle are ordered by their first use.
+pub fn toposort(ir: &Ir) -> Vec<DefId> {
+    let graph = ir2graph(ir);
+    let mut order = Vec::with_capacity(ir.len());
+    let mut dfs = DfsPostOrder::empty(&graph);
+    for root in graph.node_indices() {
+        dfs.move_to(root);
+        while let Some(n) = dfs.next(&graph) {
+            order.push(graph[n]);
+        }
+    }
+    order
+}
+
+/// reject structs which contain themselves in every value, through
+/// fields and aliases which are neither optional nor in a container.
+/// an enum can end the recursion with another variant, backends box it.
+pub fn infinite_size_checker(ir: &Ir) -> Result<(), Error> {
+    let graph = ir2graph(ir);
+    let graph = graph.filter_map(
+        |_, id| Some(*id),
+        |e, edge| {
+            let (from, _) = graph.edge_endpoints(e)?;
+            let from = &ir.def(graph[from]).kind;
+            (*edge == Edge::Value && matches!(from, DefKind::Struct(_) | DefKind::Alias(_)))
+                .then_some(*edge)
+        },
+    );
+    let recursive = cycles(&graph).into_iter().flatten().min();
+    match recursive {
+        Some(id) => {
+            let def = ir.def(id);
+            Err(Error::InfiniteSize(Name::new(ir.name(def.name), def.loc)))
+        }
+        None => Ok(()),
+    }
+}
+
+/// the definitions of each cycle of `graph`
+fn cycles<E>(graph: &Graph<DefId, E>) -> Vec<Vec<DefId>> {
+    tarjan_scc(graph)
+        .into_iter()
+        .filter(|scc| scc.len() > 1 || graph.contains_edge(scc[0], scc[0]))
+        .map(|scc| scc.into_iter().map(|n| graph[n]).collect())
+        .collect()
+}
+
+/// the cycle each definition is stored inline in, including through
+/// options, `None` for definitions which aren't recursive. a reference
+/// to a definition in the same cycle has to be boxed where the target
+/// stores values inline.
+pub fn inline_cycles(ir: &Ir) -> Vec<Option<usize>> {
+    let graph = ir2graph(ir).filter_map(
+        |_, id| Some(*id),
+        |_, edge| (*edge != Edge::Indirect).then_some(*edge),
+    );
+    let mut cycle = vec![None; ir.len()];
+    for (i, ids) in cycles(&graph).into_iter().enumerate() {
+        for id in ids {
+            cycle[id.index()] = Some(i);
+        }
+    }
+    cycle
+}
+
+/// definitions no `channel` refers to, directly or not. the channels are
+/// the entry points of a protocol, a schema without any has no unused
+/// definitions.
+pub fn unused(ir: &Ir) -> Vec<DefId> {
+    let graph = ir2graph(ir);
+    let mut used = vec![false; ir.len()];
+    let mut dfs = Dfs::empty(&graph);
+    for (id, def) in ir.defs() {
+        if matches!(def.kind, DefKind::Session(_)) {
+            dfs.move_to(node(id));
+            while let Some(n) = dfs.next(&graph) {
+                used[n.index()] = true;
+            }
+        }
+    }
+    if !used.contains(&true) {
+        return vec![];
+    }
+    ir.defs()
+        .map(|(id, _)| id)
+        .filter(|id| !used[id.index()])
+        .collect()
+}
+
+/// warnings about what `check` accepts, like unused definitions
+pub fn warnings(ir: &Ir) -> Vec<Diagnostic> {
+    unused(ir)
+        .into_iter()
+        .map(|id| {
+            let def = ir.def(id);
+            let mut diagnostic = Diagnostic::warning(
+                "unused-definition",
+                format!("`{}` is never used by a channel", ir.name(def.name)),
+            )
+            .with_span(def.loc.map(|loc| loc.span));
+            if let Some(file) = def.loc.and_then(|loc| ir.file(loc.file)) {
+                diagnostic = diagnostic.in_file(file);
+            }
+            diagnostic
+        })
+        .collect()
+}
+
+/// check every session of every definition reaches `end` or continues as
+/// a channel on each path, has nothing after `end` and no empty branch,
+/// and that no channel continues as itself before it communicates
+pub fn session_checker(ir: &Ir) -> Result<(), Error> {
+    for (_, def) in ir.defs() {
+        let mut sessions = vec![];
+        match &def.kind {
+            DefKind::Session(s) => sessions.push(s),
+            DefKind::Struct(fields) => fields
+                .iter()
+                .for_each(|i| ty_sessions(&i.ty, &mut sessions)),
+            DefKind::Enum(variants) => variants
+                .iter()
+                .for_each(|i| ty_sessions(&i.ty, &mut sessions)),
+            DefKind::Const(ty, _) | DefKind::Alias(ty) => ty_sessions(ty, &mut sessions),
+        }
+        sessions
+            .into_iter()
+            .try_for_each(well_formed)
+            .map_err(|e| e(Name::new(ir.name(def.name), def.loc)))?;
+    }
+    unguarded_recursion_checker(ir)
+}
+
+/// the sessions in `ty`, not the ones nested in them
+fn ty_sessions<'t>(ty: &'t Ty, sessions: &mut Vec<&'t SessionType>) {
+    match ty {
+        Ty::Session(s) => sessions.push(s),
+        Ty::Ref(r) => r.args.iter().for_each(|a| ty_sessions(a, sessions)),
+        Ty::Array(t, _) | Ty::Dict(_, t) | Ty::Option(t) => ty_sessions(t, sessions),
+        Ty::Simple(_) | Ty::Literal(_) => {}
+    }
+}
+
+/// the error, named by the caller, of the first ill formed part of `s`
+fn well_formed(s: &SessionType) -> Result<(), fn(Name) -> Error> {
+    let mut terminated = false;
+    for session in s.0.iter() {
+        if terminated {
+            return Err(Error::UnreachableAfterEnd);
+        }
+        match session {
+            Session::Recv(t) | Session::Send(t) => {
+                let mut payload = vec![];
+                ty_sessions(t, &mut payload);
+                payload.into_iter().try_for_each(well_formed)?;
+            }
+            Session::Offer(branches) | Session::Choose(branches) => {
+                if branches.is_empty() {
+                    return Err(Error::EmptyBranch);
+                }
+                for b in branches {
+                    if let Branch::Session(s) = b {
+                        well_formed(s)?;
+                    }
+                }
+                // every path continues in a branch
+                terminated = true;
+            }
+            Session::Endpoint => terminated = true,
+        }
+    }
+    match (s.0.is_empty(), terminated) {
+        (true, _) => Err(Error::EmptyBranch),
+        (false, false) => Err(Error::UnterminatedSession),
+        (false, true) => Ok(()),
+    }
+}
+
+/// reject channels which can continue as themselves, directly or through
+/// other channels, before sending or receiving anything
+pub fn unguarded_recursion_checker(ir: &Ir) -> Result<(), Error> {
+    let mut graph = Graph::<DefId, ()>::with_capacity(ir.len(), 0);
+    for (id, _) in ir.defs() {
+        graph.add_node(id);
+    }
+    for (id, def) in ir.defs() {
+        if let DefKind::Session(s) = &def.kind {
+            let mut next = vec![];
+            unguarded(s, &mut next);
+            for r in next {
+                if let Target::Def(target) = r.target {
+                    graph.add_edge(node(id), node(target), ());
+                }
+            }
+        }
+    }
+    match cycles(&graph).into_iter().flatten().min() {
+        Some(id) => {
+            let def = ir.def(id);
+            Err(Error::UnguardedRecursion(Name::new(
+                ir.name(def.name),
+                def.loc,
+            )))
+        }
+        None => Ok(()),
     }
-    Ok(graph)
 }
 
-fn unify_type_or_name<'a>(
-    i: &TypeOrName<'a>,
-    table: &HashMap<&str, Type<'a>>,
-) -> Result<Type<'a>, Error<'a>> {
-    match i {
-        TypeOrName::Name(n) => table.get(n).cloned().ok_or(Error::NameIsNotFound(n)),
-        TypeOrName::Type(_) => unimplemented!(),
+/// the channels `s` can continue as before it communicates, a branch at
+/// its start is the only way to continue without communicating
+fn unguarded<'t>(s: &'t SessionType, next: &mut Vec<&'t Ref>) {
+    if let Some(Session::Offer(branches) | Session::Choose(branches)) = s.0.first() {
+        for b in branches {
+            match b {
+                Branch::Ref(r) => next.push(r),
+                Branch::Session(s) => unguarded(s, next),
+            }
+        }
     }
 }
@@ -0,0 +1,59 @@
+//! Watch mode
+//!
+//! Filesystem change notification over the IDL roots. Changes are batched
+//! until the roots have been quiet for `DEBOUNCE`, see `build::Build::update`
+//! for regenerating the affected outputs.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// editors write a file in several steps
+pub const DEBOUNCE: Duration = Duration::from_millis(50);
+
+pub struct Watch {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+}
+
+impl Watch {
+    pub fn new(roots: &[PathBuf]) -> notify::Result<Self> {
+        let (tx, events) = channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        for root in roots {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+        }
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// the paths changed in the next batch, waiting at most `timeout` for
+    /// it to start; an empty batch on timeout or when the watcher stopped
+    pub fn next(&self, timeout: Option<Duration>) -> notify::Result<BTreeSet<PathBuf>> {
+        let mut paths = BTreeSet::new();
+        let first = match timeout {
+            Some(timeout) => self.events.recv_timeout(timeout).ok(),
+            None => self.events.recv().ok(),
+        };
+        let Some(event) = first else {
+            return Ok(paths);
+        };
+        add(&mut paths, event?);
+        while let Ok(event) = self.events.recv_timeout(DEBOUNCE) {
+            add(&mut paths, event?);
+        }
+        Ok(paths)
+    }
+}
+
+fn add(paths: &mut BTreeSet<PathBuf>, event: Event) {
+    // reading the sources to regenerate them must not trigger another batch
+    if !matches!(event.kind, EventKind::Access(_)) {
+        paths.extend(event.paths);
+    }
+}
@@ -0,0 +1,96 @@
+//! runs `limit-stream format` like a user or a CI job would
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const UNFORMATTED: &str = "struct A{a:int=0}\n";
+const FORMATTED: &str = "struct A {\n    a: int = 0,\n}\n";
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("limit-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn format(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_limit-stream"))
+        .arg("format")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[test]
+fn check_test() {
+    let dir = temp_dir("check");
+    fs::write(dir.join("a.lstr"), UNFORMATTED).unwrap();
+    fs::write(dir.join("b.lstr"), FORMATTED).unwrap();
+
+    let output = format(&dir, &["--check"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "./a.lstr\n");
+    // nothing is written
+    assert_eq!(fs::read_to_string(dir.join("a.lstr")).unwrap(), UNFORMATTED);
+
+    fs::write(dir.join("a.lstr"), FORMATTED).unwrap();
+    let output = format(&dir, &["--check"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn diff_test() {
+    let dir = temp_dir("diff");
+    fs::write(dir.join("a.lstr"), UNFORMATTED).unwrap();
+
+    let output = format(&dir, &["--diff", "-p", "a.lstr"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "--- a.lstr\n+++ a.lstr\n@@ -1 +1,3 @@\n-struct A{a:int=0}\n+struct A {\n+    a: int = 0,\n+}\n"
+    );
+    assert_eq!(fs::read_to_string(dir.join("a.lstr")).unwrap(), UNFORMATTED);
+
+    let output = format(&dir, &["--diff", "--check", "-p", "a.lstr"], "");
+    assert_eq!(output.status.code(), Some(1));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stdin_test() {
+    let dir = temp_dir("stdin");
+
+    let output = format(&dir, &["-p", "-"], UNFORMATTED);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), FORMATTED);
+
+    let output = format(&dir, &["-p", "-", "--check"], UNFORMATTED);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "<stdin>\n");
+
+    let output = format(&dir, &["-p", "-", "--check"], FORMATTED);
+    assert_eq!(output.status.code(), Some(0));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,176 @@
+use limit_stream::codegen::{idl2rust, rust::Rust, Codegen};
+use limit_stream::config::{Config, GenMode, Target};
+use limit_stream::ir::SessionType;
+use limit_stream::runtime::{Decode, Encode, Validate};
+
+fn rust() -> Rust {
+    Rust::new(&Target::default(), GenMode::Client)
+}
+
+#[test]
+fn literal_payload_test() {
+    let code = idl2rust("channel hello = send \"hi\" -> recv 1 -> end", &mut rust()).unwrap();
+    assert!(code.contains("pub struct L0;"));
+    assert!(code.contains("impl Literal for L0 {\n  type Value = Uint;"));
+    assert!(code.contains("impl Literal for L1 {\n  type Value = String;"));
+    assert!(code.contains("pub type hello = Next<Send<L1>, Next<Recv<L0>, Endpoint>>;"));
+}
+
+#[test]
+fn target_config_test() {
+    let config = Config::parse(
+        r#"
+[codegen.rust]
+tab_size = 4
+serialization = "none"
+
+[codegen.rust.types]
+uint = "u32"
+Timestamp = "std::time::SystemTime"
+"#,
+    )
+    .unwrap();
+    let mut rs = Rust::new(&config.target("rust"), config.project.gen_mode);
+    let code = idl2rust("struct User { id: uint = 0, at: Timestamp = 1 }", &mut rs).unwrap();
+    assert!(code.contains("    pub id: u32,\n    pub at: std::time::SystemTime,\n"));
+    assert!(!code.contains("impl Encode"));
+}
+
+#[test]
+fn recursive_test() {
+    let code = idl2rust(
+        "
+struct Tree { children: [Tree] = 0, left: option<Tree> = 1 }
+enum Expr { Num(int), Add(Pair), Neg(Expr) }
+struct Pair { a: Expr = 0, b: Expr = 1 }
+struct Blob { data: string = 0 #[boxed] }
+",
+        &mut rust(),
+    )
+    .unwrap();
+    assert!(code.contains("pub children: Vec<Tree>,\n  pub left: Option<Box<Tree>>,"));
+    assert!(code.contains("Add(Pair),\n  Neg(Box<Expr>),"));
+    assert!(code.contains("pub a: Box<Expr>,"));
+    assert!(code.contains("pub data: Box<String>,"));
+}
+
+#[test]
+fn codegen_error_test() {
+    let src = r#"struct User { age: uint = 0 #[range="x"] }"#;
+    // the checker rejects it before any code is written
+    let d = idl2rust(src, &mut rust()).unwrap_err();
+    assert_eq!(d.code, "invalid-constraint");
+    assert_eq!(&src[d.span.unwrap().range()], "range");
+
+    let empty = SessionType(vec![]);
+    let e = empty.generate(&mut rust()).unwrap_err();
+    assert_eq!(e.message, "empty session");
+}
+
+#[test]
+fn mock_test() {
+    let src = "channel sum = recv int -> send int -> end\nchannel Pair<T> = recv T -> end";
+    let code = idl2rust(src, &mut Rust::new(&Target::default(), GenMode::Mock)).unwrap();
+    assert!(code.contains(&format!("pub const IDL: &str = {:?};", src)));
+    assert!(code.contains(
+        "pub async fn mock_sum(io: impl Io, options: &Options) -> Result<(), Error> {\n  \
+         mock::run(mock::session(IDL, \"sum\")?, io, options).await\n}"
+    ));
+    assert!(!code.contains("mock_Pair"));
+    assert!(code.contains("fn main() -> std::io::Result<()> {\n  mock::main(IDL)\n}"));
+}
+
+#[test]
+fn ill_typed_test() {
+    for (src, code) in [
+        ("struct A { a: Missing = 0 }", "name-not-found"),
+        ("struct A { a: A = 0 }", "infinite-size"),
+        (
+            "struct A {}\nchannel c = end -> send A",
+            "unreachable-after-end",
+        ),
+    ] {
+        assert_eq!(idl2rust(src, &mut rust()).unwrap_err().code, code);
+    }
+}
+
+/// compiled with the tests, so the generated code has to type-check
+mod alias {
+    include!("gen/alias.rs");
+}
+
+#[test]
+fn alias_test() {
+    let code = idl2rust(include_str!("gen/alias.lstr"), &mut rust()).unwrap();
+    assert_eq!(code, include_str!("gen/alias.rs"));
+    assert_eq!((alias::X, alias::N), (1.0, "x"));
+    let player = alias::Player::default();
+    assert_eq!((player.s, player.name.as_str()), (1.0, "p"));
+    assert_eq!(player.best, Some(3.0));
+    player.validate().unwrap();
+    let player = alias::Player {
+        level: 10.0,
+        ..Default::default()
+    };
+    assert_eq!(player.validate().unwrap_err().field, "level");
+    let player = alias::Player {
+        best: Some(5.5),
+        ..Default::default()
+    };
+    assert_eq!(player.validate().unwrap_err().field, "best");
+}
+
+#[allow(dead_code)]
+mod recursive {
+    include!("gen/recursive.rs");
+}
+
+#[test]
+fn generic_recursive_test() {
+    // references back into a cycle are boxed, in generic arguments too
+    let code = idl2rust(include_str!("gen/recursive.lstr"), &mut rust()).unwrap();
+    assert_eq!(code, include_str!("gen/recursive.rs"));
+    assert!(code.contains("  Node(Wrap<Box<Tree>>) = 1,\n"));
+    assert!(code.contains("  pub trees: Pair<Option<Box<Forest>>>,\n"));
+    let leaf = || Box::new(recursive::Tree::Leaf(1));
+    let pair = recursive::Pair {
+        a: leaf(),
+        b: leaf(),
+    };
+    let tree = recursive::Tree::Node(recursive::Wrap {
+        inner: pair,
+        all: vec![leaf()],
+    });
+    let mut buf = vec![];
+    tree.encode(&mut buf);
+    assert_eq!(recursive::Tree::decode(&mut &buf[..]), Ok(tree));
+}
+
+/// the session code is compiled and run in `runtime_test`
+#[test]
+fn sum_test() {
+    let code = idl2rust(include_str!("gen/sum.lstr"), &mut rust()).unwrap();
+    assert_eq!(code, include_str!("gen/sum.rs"));
+}
+
+#[test]
+fn constraint_const_test() {
+    let code = idl2rust(
+        "const MAX: uint = 4;\nstruct S { tags: [string] = 0 #[max_len=MAX] }",
+        &mut rust(),
+    )
+    .unwrap();
+    assert!(code.contains("if !(v.length() <= 4) {"));
+}
+
+#[test]
+fn order_test() {
+    let code = idl2rust(
+        "struct Users { page: Page = 0 }\nstruct Page { n: uint = 0 }\nconst N: uint = 1;",
+        &mut rust(),
+    )
+    .unwrap();
+    let at = |name: &str| code.find(&format!("pub struct {} ", name)).unwrap();
+    assert!(at("Page") < at("Users"));
+    assert!(at("Users") < code.find("pub const N").unwrap());
+}
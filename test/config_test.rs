@@ -0,0 +1,86 @@
+use std::fs;
+use std::path::PathBuf;
+
+use limit_stream::config::{Config, GenMode, Serialization, CONFIG_FILE};
+use limit_stream::sources::Sources;
+
+#[test]
+fn discover_test() {
+    let root = std::env::temp_dir().join(format!("limit-config-{}", std::process::id()));
+    let nested = root.join("a/b");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(
+        root.join(CONFIG_FILE),
+        r#"
+[project]
+roots = ["idl"]
+gen_mode = "all"
+
+[codegen.rust]
+out_dir = "gen"
+serialization = "none"
+"#,
+    )
+    .unwrap();
+
+    let config = Config::discover(&nested).unwrap().unwrap();
+    assert_eq!(config.dir, root);
+    assert_eq!(config.roots(), vec![root.join("idl")]);
+    assert_eq!(config.project.gen_mode, GenMode::All);
+    let target = config.target("rust");
+    assert_eq!(target.out_dir, Some(root.join("gen")));
+    assert_eq!(target.serialization, Serialization::None);
+    assert_eq!(target.tab_size, 2);
+    assert_eq!(config.target("go").out_dir, None::<PathBuf>);
+
+    fs::write(root.join(CONFIG_FILE), "[project]\nroot = [\"idl\"]").unwrap();
+    assert!(Config::discover(&nested).is_err());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn sources_test() {
+    let root = std::env::temp_dir().join(format!("limit-sources-{}", std::process::id()));
+    for file in [
+        "a.lstr",
+        "Makefile",
+        "nested/deep/b.lstr",
+        "nested/notes.txt",
+        "vendor/c.lstr",
+        ".git/d.lstr",
+    ] {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+    let relative = |sources: Sources| {
+        sources
+            .files(&root)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.relative)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        relative(Sources::default()),
+        vec![
+            PathBuf::from("a.lstr"),
+            PathBuf::from("nested/deep/b.lstr"),
+            PathBuf::from("vendor/c.lstr"),
+        ]
+    );
+    assert_eq!(
+        relative(Sources::new(&["nested/**".to_string()], &[]).unwrap()),
+        vec![PathBuf::from("nested/deep/b.lstr")]
+    );
+    assert_eq!(
+        relative(Sources::new(&[], &["vendor/**".to_string()]).unwrap()),
+        vec![PathBuf::from("a.lstr"), PathBuf::from("nested/deep/b.lstr")]
+    );
+    assert!(Sources::new(&["[".to_string()], &[]).is_err());
+
+    let file = Sources::default().files(&root.join("a.lstr")).unwrap();
+    assert_eq!(file[0].relative, PathBuf::from("a.lstr"));
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,88 @@
+use limit_stream::diagnostic::{sarif, Lines, Span};
+use limit_stream::ir::{lower, Unit};
+use limit_stream::parser::parse;
+use limit_stream::type_checker::check;
+
+#[test]
+fn parse_error_test() {
+    let src = "struct A {\n    x: int = 0,\n}\nstruct B {\n    y: int = ,\n}\n";
+    let d = parse(src).unwrap_err();
+    assert_eq!(d.code, "syntax-error");
+    let span = d.span.unwrap();
+    assert_eq!(span.line, 5);
+    assert_eq!(&src[span.range()], "=");
+    assert_eq!(d.to_string(), "error[syntax-error]: 5:12: unexpected `=`");
+}
+
+#[test]
+fn type_error_test() {
+    let src = "struct User {\n    name: strin = 0,\n}\nstruct string_t {}\nstruct String {\n    s: User<int> = 0,\n}\n";
+    let defs = parse(src).unwrap();
+    let ir = lower(&[Unit::new(src, &defs)]);
+    let d = check(&ir).unwrap_err().diagnostic(&ir);
+    assert_eq!(d.code, "name-not-found");
+    let start = src.find("strin ").unwrap();
+    assert_eq!(d.span, Some(Span::new(src, start, start + 5)));
+    assert_eq!((d.span.unwrap().line, d.span.unwrap().column), (2, 11));
+    let fix = d.fix.unwrap();
+    assert_eq!(fix.replacement, "String");
+    assert_eq!(fix.span, d.span.unwrap());
+
+    let src = "struct User {}\nstruct S {\n    s: User<int> = 0,\n}\n";
+    let defs = parse(src).unwrap();
+    let ir = lower(&[Unit::new(src, &defs)]);
+    let d = check(&ir).unwrap_err().diagnostic(&ir);
+    assert_eq!(d.code, "arity-mismatch");
+    assert_eq!(d.span.unwrap().line, 3);
+    assert_eq!(d.related.len(), 1);
+    assert_eq!(d.related[0].span, Span::new(src, 7, 11));
+}
+
+#[test]
+fn sarif_test() {
+    let d = parse("channel a = recv ->")
+        .unwrap_err()
+        .in_file("a.lstr".as_ref());
+    let json = d.to_json();
+    assert_eq!(json["severity"], "error");
+    assert_eq!(json["file"], "a.lstr");
+    assert_eq!(json["span"]["line"], 1);
+
+    let log = sarif(&[d]);
+    assert_eq!(log["version"], "2.1.0");
+    let run = &log["runs"][0];
+    assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "syntax-error");
+    let result = &run["results"][0];
+    assert_eq!(result["ruleId"], "syntax-error");
+    assert_eq!(result["level"], "error");
+    let location = &result["locations"][0]["physicalLocation"];
+    assert_eq!(location["artifactLocation"]["uri"], "a.lstr");
+    assert_eq!(location["region"]["startLine"], 1);
+
+    // offsets are in bytes, columns in chars
+    let src = "const S: string = \"héllo\"; struct A { x: Missing = 0 }";
+    let defs = parse(src).unwrap();
+    let ir = lower(&[Unit::new(src, &defs)]);
+    let log = sarif(&[check(&ir).unwrap_err().diagnostic(&ir)]);
+    let run = &log["runs"][0];
+    assert_eq!(run["columnKind"], "unicodeCodePoints");
+    let region = &run["results"][0]["locations"][0]["physicalLocation"]["region"];
+    let missing = src.find("Missing").unwrap();
+    assert_eq!(region["byteOffset"], missing);
+    assert_eq!(region["byteLength"], "Missing".len());
+    assert_eq!(region["startColumn"], missing);
+    assert!(region.get("charOffset").is_none());
+}
+
+#[test]
+fn lines_test() {
+    let src = "struct A {}\n// é\nstruct B {}\n";
+    let lines = Lines::new(src);
+    let b = src.rfind('B').unwrap();
+    let span = lines.span(b, b + 1);
+    assert_eq!((span.line, span.column, span.end_column), (3, 8, 9));
+    // columns count chars
+    assert_eq!(lines.span(src.len() - 13, src.len() - 13).column, 5);
+    assert_eq!(lines.span(src.len(), src.len()).line, 4);
+    assert_eq!(lines.of(&src[b..b + 1]), Some(span));
+}
@@ -0,0 +1,383 @@
+use std::fs;
+
+use limit_stream::ast::{
+    Annotation, AnnotationValue, Append, ArrayLength, ConstDef, Constant, ContainerType, Def,
+    EnumDef, EnumItem, Macro, MacrodDef, Session, SessionDef, SessionOrName, SessionType,
+    SessionUnion, SimpleType, StructDef, StructItem, Type, TypeDef, TypeOrName,
+};
+use limit_stream::codegen::{
+    format_idl,
+    formatter::{Formatter, Style},
+    Codegen,
+};
+use limit_stream::config::Config;
+use limit_stream::parser::parse;
+use proptest::prelude::*;
+
+fn formatter() -> Formatter {
+    Formatter::new(Style::default())
+}
+
+fn format(defs: &[MacrodDef], fmt: &mut Formatter) -> String {
+    defs.iter()
+        .map(|d| d.generate(fmt).unwrap() + "\n")
+        .collect()
+}
+
+/// `src` formats to something that parses back to the same AST and formats to itself
+fn assert_roundtrip(src: &str) {
+    let formatted = format_idl(src, &mut formatter()).unwrap();
+    assert_eq!(parse(&formatted), parse(src), "{}", formatted);
+    assert_eq!(format_idl(&formatted, &mut formatter()).unwrap(), formatted);
+}
+
+#[test]
+fn examples_roundtrip_test() {
+    for entry in fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "lstr") {
+            assert_roundtrip(&fs::read_to_string(path).unwrap());
+        }
+    }
+}
+
+#[test]
+fn format_test() {
+    let src = r#"
+/// the answer
+#[rpc] const A: double = 1.0;
+struct S { a: string = 0 default "x\"y" #[max_len=3], b: int = default -5 }
+channel c = recv int -> // picks
+  choose | recv +1 -> end | offer | a | b
+"#;
+    assert_eq!(
+        format_idl(src, &mut formatter()).unwrap(),
+        r#"/// the answer
+#[rpc]
+const A: double = 1.0;
+struct S {
+    #[max_len=3]
+    a: string = 0 default "x\"y",
+    b: int = default -5,
+}
+channel c =
+    recv int ->
+    // picks
+    choose
+        | recv +1 -> end
+        | offer | a | b
+"#
+    );
+    assert_roundtrip(src);
+}
+
+#[test]
+fn width_test() {
+    let src = "channel sum = recv int -> offer | sum | recv Done -> send int -> end";
+    assert_eq!(
+        format_idl(src, &mut formatter()).unwrap(),
+        "channel sum = recv int -> offer | sum | recv Done -> send int -> end\n"
+    );
+    let mut narrow = Formatter::new(Style {
+        max_width: 40,
+        ..Style::default()
+    });
+    assert_eq!(
+        format_idl(src, &mut narrow).unwrap(),
+        "\
+channel sum =
+    recv int ->
+    offer
+        | sum
+        | recv Done -> send int -> end
+"
+    );
+}
+
+#[test]
+fn style_test() {
+    let src = "
+struct User { description: string = 2, name: string = 0, age: option<uint> = 1 default 3, tags: [string] }
+enum E { Admin(User) = 10, No(User) = 2 }
+";
+    let config = Config::parse(
+        "
+[format]
+tab_size = 2
+trailing_comma = false
+align_fields = true
+sort_fields = true
+",
+    )
+    .unwrap();
+    assert_eq!(
+        format_idl(src, &mut Formatter::new(config.format)).unwrap(),
+        "\
+struct User {
+  name:        string       = 0,
+  age:         option<uint> = 1 default 3,
+  description: string       = 2,
+  tags:        [string]
+}
+enum E {
+  Admin(User) = 10,
+  No(User)    = 2
+}
+"
+    );
+    assert!(Config::parse("[format]\nwidth = 80").is_err());
+}
+
+fn name() -> impl Strategy<Value = &'static str> {
+    prop::sample::select(vec!["a", "b_1", "User", "Page", "_x"])
+}
+
+fn simple_type() -> impl Strategy<Value = SimpleType> {
+    prop::sample::select(vec![
+        SimpleType::Bool,
+        SimpleType::Int,
+        SimpleType::Uint,
+        SimpleType::Float,
+        SimpleType::Double,
+        SimpleType::String,
+    ])
+}
+
+fn constant() -> impl Strategy<Value = Constant> {
+    prop_oneof![
+        any::<String>().prop_map(Constant::String),
+        any::<f64>()
+            .prop_filter("finite", |f| f.is_finite())
+            .prop_map(Constant::Float),
+        any::<i64>().prop_map(Constant::Int),
+        any::<u64>().prop_map(Constant::Uint),
+        any::<bool>().prop_map(Constant::Bool),
+    ]
+}
+
+fn annotation_value() -> impl Strategy<Value = AnnotationValue<'static>> {
+    prop_oneof![
+        constant().prop_map(AnnotationValue::Constant),
+        name().prop_map(AnnotationValue::Const),
+    ]
+}
+
+fn appends() -> impl Strategy<Value = Vec<Append<'static>>> {
+    let append = prop_oneof![
+        "// [a-z ]*".prop_map(|s| Append::LineComment(Box::leak(format!("{}\n", s).into()))),
+        "/// [a-z ]*".prop_map(|s| Append::DocsComment(Box::leak(format!("{}\n", s).into()))),
+        (name(), annotation_value()).prop_map(|(n, v)| Append::Annotation(Annotation(n, v))),
+    ];
+    prop::collection::vec(append, 0..3)
+}
+
+fn m<T: std::fmt::Debug>(
+    body: impl Strategy<Value = T>,
+) -> impl Strategy<Value = Macro<'static, T>> {
+    (appends(), body).prop_map(|(appends, body)| Macro {
+        appends,
+        body: Box::new(body),
+    })
+}
+
+/// types other than sessions, which would swallow the rest of an enclosing session
+fn type_or_name() -> impl Strategy<Value = TypeOrName<'static>> {
+    let leaf = prop_oneof![
+        name().prop_map(TypeOrName::Name),
+        simple_type().prop_map(|t| TypeOrName::Type(Box::new(Type::SimpleType(t)))),
+    ];
+    leaf.prop_recursive(3, 16, 3, |inner| {
+        let container = prop_oneof![
+            (
+                inner.clone(),
+                prop::option::of(prop_oneof![
+                    any::<u16>().prop_map(|n| ArrayLength::Literal(n as usize)),
+                    name().prop_map(ArrayLength::Const),
+                ])
+            )
+                .prop_map(|(t, len)| ContainerType::Array(Box::new(t), len)),
+            (simple_type(), inner.clone()).prop_map(|(k, v)| ContainerType::Dict(k, Box::new(v))),
+            inner
+                .clone()
+                .prop_map(|t| ContainerType::Option(Box::new(t))),
+        ];
+        prop_oneof![
+            (name(), prop::collection::vec(inner, 1..3))
+                .prop_map(|(n, args)| TypeOrName::Generic(n, args)),
+            container.prop_map(|c| TypeOrName::Type(Box::new(Type::ContainerType(c)))),
+        ]
+    })
+}
+
+fn payload() -> impl Strategy<Value = TypeOrName<'static>> {
+    prop_oneof![
+        type_or_name(),
+        constant().prop_map(|c| TypeOrName::Type(Box::new(Type::Constant(c)))),
+    ]
+}
+
+fn ends_in_union(s: &SessionType) -> bool {
+    matches!(
+        s.0.last().map(|m| m.body.as_ref()),
+        Some(Session::Offer(_) | Session::Choose(_))
+    )
+}
+
+/// the first item's comments and annotations would attach to whatever
+/// encloses the session, so it has none
+fn session_type(
+    last: impl Strategy<Value = Session<'static>>,
+) -> impl Strategy<Value = SessionType<'static>> {
+    let step = prop_oneof![
+        payload().prop_map(Session::Recv),
+        payload().prop_map(Session::Send),
+    ];
+    (prop::collection::vec(m(step), 0..3), m(last)).prop_map(|(mut steps, last)| {
+        steps.push(last);
+        steps[0].appends.clear();
+        SessionType(steps)
+    })
+}
+
+fn session() -> impl Strategy<Value = SessionType<'static>> {
+    session_type(Just(Session::Endpoint)).prop_recursive(3, 24, 3, |inner| {
+        let branch = prop_oneof![
+            name().prop_map(SessionOrName::Name),
+            (name(), prop::collection::vec(type_or_name(), 1..3))
+                .prop_map(|(n, args)| SessionOrName::Generic(n, args)),
+            inner.prop_map(|s| SessionOrName::Session(Box::new(s))),
+        ];
+        // a union before the last branch would take the following branches
+        let union = (prop::collection::vec(branch.clone(), 1..4), branch).prop_map(
+            |(mut branches, last)| {
+                for b in branches.iter_mut() {
+                    if let SessionOrName::Session(s) = b {
+                        if ends_in_union(s) {
+                            *s.0.last_mut().unwrap().body = Session::Endpoint;
+                        }
+                    }
+                }
+                branches.push(last);
+                SessionUnion(branches)
+            },
+        );
+        session_type(prop_oneof![
+            union.clone().prop_map(Session::Offer),
+            union.prop_map(Session::Choose),
+        ])
+    })
+}
+
+fn generics() -> impl Strategy<Value = Vec<&'static str>> {
+    prop::collection::vec(name(), 0..3)
+}
+
+fn def() -> impl Strategy<Value = Def<'static>> {
+    prop_oneof![
+        (name(), generics(), appends(), session()).prop_map(
+            |(name, generics, appends, session)| {
+                Def::SessionDef(SessionDef {
+                    name,
+                    generics,
+                    session: Macro {
+                        appends,
+                        body: Box::new(session),
+                    },
+                })
+            }
+        ),
+        (
+            name(),
+            generics(),
+            prop::collection::vec(
+                m((
+                    name(),
+                    type_or_name(),
+                    prop::option::of(any::<u64>()),
+                    prop::option::of(constant())
+                )
+                    .prop_map(|(n, t, tag, default)| StructItem(n, t, tag, default))),
+                0..4
+            )
+        )
+            .prop_map(|(name, generics, items)| Def::StructDef(StructDef {
+                name,
+                generics,
+                items
+            })),
+        (
+            name(),
+            generics(),
+            prop::collection::vec(
+                m((name(), type_or_name(), prop::option::of(any::<u64>()))
+                    .prop_map(|(n, t, tag)| EnumItem(n, t, tag))),
+                0..4
+            )
+        )
+            .prop_map(|(name, generics, items)| Def::EnumDef(EnumDef {
+                name,
+                generics,
+                items
+            })),
+        (name(), type_or_name(), constant())
+            .prop_map(|(name, ty, value)| Def::ConstDef(ConstDef { name, ty, value })),
+        (name(), generics(), type_or_name())
+            .prop_map(|(name, generics, ty)| Def::TypeDef(TypeDef { name, generics, ty })),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn roundtrip_test(
+        defs in prop::collection::vec(m(def()).prop_map(MacrodDef), 1..4),
+        max_width in 0usize..120,
+        trailing_comma in any::<bool>(),
+        align_fields in any::<bool>(),
+    ) {
+        let mut fmt = Formatter::new(Style {
+            max_width,
+            trailing_comma,
+            align_fields,
+            ..Style::default()
+        });
+        let formatted = format(&defs, &mut fmt);
+        prop_assert_eq!(parse(&formatted), Ok(defs), "{}", formatted);
+        prop_assert_eq!(format_idl(&formatted, &mut fmt), Ok(formatted));
+    }
+}
+
+#[test]
+fn sort_fields_encoding_test() {
+    use limit_stream::codegen::{idl2rust, rust::Rust};
+    use limit_stream::config::{GenMode, Target};
+    use limit_stream::ir::{lower, Unit};
+    use limit_stream::runtime::monitor;
+
+    let src = "struct User { description: string = 2, tags: [string], name: string = 0, age: option<uint> = 1 }\n\
+               channel c = send User -> end\n";
+    let sorted = format_idl(
+        src,
+        &mut Formatter::new(Style {
+            sort_fields: true,
+            ..Style::default()
+        }),
+    )
+    .unwrap();
+    assert_ne!(sorted, format_idl(src, &mut formatter()).unwrap());
+    let user = serde_json::json!({"description": "d", "tags": ["t"], "name": "n", "age": 3});
+    let encode = |src: &str| {
+        let ir = lower(&[Unit::new(src, &parse(src).unwrap())]);
+        let def = monitor::Protocol::from_ir(&ir, "c").unwrap();
+        let mut buf = vec![];
+        def.encode(&def.types[0].1, &user, &mut buf).unwrap();
+        buf
+    };
+    // tags, not the order of the fields, decide the encoding
+    assert_eq!(encode(src), encode(&sorted));
+    let codec = |src: &str| {
+        let code = idl2rust(src, &mut Rust::new(&Target::default(), GenMode::Client)).unwrap();
+        let start = code.find("impl Encode for User").unwrap();
+        code[start..code.find("value.validate()").unwrap()].to_string()
+    };
+    assert_eq!(codec(src), codec(&sorted));
+}
@@ -0,0 +1,74 @@
+use limit_stream::runtime::*;
+
+
+#[allow(non_camel_case_types)]
+pub type Score = Double;
+
+#[allow(non_camel_case_types)]
+pub type Name = String;
+
+#[allow(non_camel_case_types)]
+pub type MaybeScore = Option<Score>;
+
+pub const X: Score = 1.0;
+
+pub const N: &str = "x";
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Player {
+  pub s: Score,
+  pub name: Name,
+  pub best: MaybeScore,
+  pub level: Score,
+}
+impl Default for Player {
+  fn default() -> Self {
+    Self {
+      s: 1.0,
+      name: "p".to_string(),
+      best: Some(3.0),
+      level: 1.0,
+    }
+  }
+}
+#[allow(unused_comparisons)]
+impl Validate for Player {
+  fn validate(&self) -> Result<(), ValidationError> {
+    if let Some(v) = &self.best {
+      if !(0.0 <= *v && *v <= 5.0) {
+        return Err(ValidationError { field: "best", constraint: "range" });
+      }
+    }
+    {
+      let v = &self.level;
+      if !(0.0 <= *v && *v < 10.0) {
+        return Err(ValidationError { field: "level", constraint: "range" });
+      }
+    }
+    Ok(())
+  }
+}
+impl Encode for Player {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    encode_array_len(buf, 4);
+    self.s.encode(buf);
+    self.name.encode(buf);
+    self.best.encode(buf);
+    self.level.encode(buf);
+  }
+}
+impl Decode for Player {
+  fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+    decode_array_len(buf, 4)?;
+    let value = Self {
+      s: Decode::decode(buf)?,
+      name: Decode::decode(buf)?,
+      best: Decode::decode(buf)?,
+      level: Decode::decode(buf)?,
+    };
+    value.validate()?;
+    Ok(value)
+  }
+}
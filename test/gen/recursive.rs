@@ -0,0 +1,124 @@
+use limit_stream::runtime::*;
+
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pair<T> {
+  pub a: T,
+  pub b: T,
+}
+#[allow(unused_comparisons)]
+impl<T> Validate for Pair<T> {
+  fn validate(&self) -> Result<(), ValidationError> {
+    Ok(())
+  }
+}
+impl<T: Encode> Encode for Pair<T> {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    encode_array_len(buf, 2);
+    self.a.encode(buf);
+    self.b.encode(buf);
+  }
+}
+impl<T: Decode> Decode for Pair<T> {
+  fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+    decode_array_len(buf, 2)?;
+    let value = Self {
+      a: Decode::decode(buf)?,
+      b: Decode::decode(buf)?,
+    };
+    value.validate()?;
+    Ok(value)
+  }
+}
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wrap<T> {
+  pub inner: Pair<T>,
+  pub all: Vec<T>,
+}
+#[allow(unused_comparisons)]
+impl<T> Validate for Wrap<T> {
+  fn validate(&self) -> Result<(), ValidationError> {
+    Ok(())
+  }
+}
+impl<T: Encode> Encode for Wrap<T> {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    encode_array_len(buf, 2);
+    self.inner.encode(buf);
+    self.all.encode(buf);
+  }
+}
+impl<T: Decode> Decode for Wrap<T> {
+  fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+    decode_array_len(buf, 2)?;
+    let value = Self {
+      inner: Decode::decode(buf)?,
+      all: Decode::decode(buf)?,
+    };
+    value.validate()?;
+    Ok(value)
+  }
+}
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq)]
+#[repr(usize)]
+pub enum Tree {
+  Leaf(Int) = 0,
+  Node(Wrap<Box<Tree>>) = 1,
+}
+impl Encode for Tree {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    match self {
+      Self::Leaf(v) => encode_variant(buf, 0, v),
+      Self::Node(v) => encode_variant(buf, 1, v),
+    }
+  }
+}
+impl Decode for Tree {
+  fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+    match decode_variant(buf)? {
+      0 => Ok(Self::Leaf(Decode::decode(buf)?)),
+      1 => Ok(Self::Node(Decode::decode(buf)?)),
+      tag => Err(DecodeError::UnknownVariant(tag)),
+    }
+  }
+}
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forest {
+  pub trees: Pair<Option<Box<Forest>>>,
+  pub tree: Tree,
+}
+#[allow(unused_comparisons)]
+impl Validate for Forest {
+  fn validate(&self) -> Result<(), ValidationError> {
+    Ok(())
+  }
+}
+impl Encode for Forest {
+  fn encode(&self, buf: &mut Vec<u8>) {
+    encode_array_len(buf, 2);
+    self.trees.encode(buf);
+    self.tree.encode(buf);
+  }
+}
+impl Decode for Forest {
+  fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+    decode_array_len(buf, 2)?;
+    let value = Self {
+      trees: Decode::decode(buf)?,
+      tree: Decode::decode(buf)?,
+    };
+    value.validate()?;
+    Ok(value)
+  }
+}
@@ -0,0 +1,79 @@
+use limit_stream::runtime::*;
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+pub type E0 = Next<Send<Int>, Endpoint>;
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+pub enum E1<R = ()> {
+  Tsum(Chan<sum, R>),
+  TE0(Chan<E0, R>),
+}
+impl<R> Branches<R> for E1<R> {
+  fn tag(&self) -> u64 {
+    match self {
+      Self::Tsum(_) => 0,
+      Self::TE0(_) => 1,
+    }
+  }
+  fn branch(tag: u64, role: R) -> Option<Self> {
+    match tag {
+      0 => Some(Self::Tsum(Chan::new(role))),
+      1 => Some(Self::TE0(Chan::new(role))),
+      _ => None,
+    }
+  }
+  fn into_role(self) -> R {
+    match self {
+      Self::Tsum(c) => c.into_role(),
+      Self::TE0(c) => c.into_role(),
+    }
+  }
+}
+impl<R> Select for E1<R> {
+  type On<Q> = E1<Q>;
+}
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+pub type E2 = Next<Recv<String>, Endpoint>;
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+pub enum E3<R = ()> {
+  Tcount(Chan<count, R>),
+  TE2(Chan<E2, R>),
+}
+impl<R> Branches<R> for E3<R> {
+  fn tag(&self) -> u64 {
+    match self {
+      Self::Tcount(_) => 0,
+      Self::TE2(_) => 1,
+    }
+  }
+  fn branch(tag: u64, role: R) -> Option<Self> {
+    match tag {
+      0 => Some(Self::Tcount(Chan::new(role))),
+      1 => Some(Self::TE2(Chan::new(role))),
+      _ => None,
+    }
+  }
+  fn into_role(self) -> R {
+    match self {
+      Self::Tcount(c) => c.into_role(),
+      Self::TE2(c) => c.into_role(),
+    }
+  }
+}
+impl<R> Select for E3<R> {
+  type On<Q> = E3<Q>;
+}
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+pub type sum = Next<Recv<Int>, Offer<E1>>;
+
+#[rustfmt::skip]
+#[allow(non_camel_case_types)]
+pub type count = Next<Send<Uint>, Choose<E3>>;
@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use limit_stream::ir::{lower, DefKind, Target, Ty, Unit};
+use limit_stream::parser::parse;
+use limit_stream::type_checker::{check, Error};
+
+#[test]
+fn lower_test() {
+    let src = "/// a user\nstruct User<T> { id: T = 0, name: Name = 1 }";
+    let defs = parse(src).unwrap();
+    let ir = lower(&[Unit::new(src, &defs)]);
+    let user = ir.def(ir.lookup("User").unwrap());
+    assert_eq!(user.docs, vec!["a user"]);
+    assert_eq!(&src[user.loc.unwrap().span.range()], "User");
+    let DefKind::Struct(fields) = &user.kind else {
+        panic!("{:?}", user.kind)
+    };
+    let targets = fields
+        .iter()
+        .map(|f| match &f.ty {
+            Ty::Ref(r) => r.target,
+            ty => panic!("{:?}", ty),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(targets, vec![Target::Param, Target::Unresolved]);
+}
+
+#[test]
+fn multi_file_test() {
+    let (a, b) = ("struct User { name: Name = 0 }", "type Name = string;");
+    let (defs_a, defs_b) = (parse(a).unwrap(), parse(b).unwrap());
+    let ir = lower(&[
+        Unit::new(a, &defs_a).in_file(Path::new("a.lstr")),
+        Unit::new(b, &defs_b).in_file(Path::new("b.lstr")),
+    ]);
+    assert_eq!(check(&ir), Ok(()));
+    let name = ir.lookup("Name").unwrap();
+    let DefKind::Struct(fields) = &ir.def(ir.lookup("User").unwrap()).kind else {
+        panic!()
+    };
+    assert!(matches!(&fields[0].ty, Ty::Ref(r) if r.target == Target::Def(name)));
+
+    let c = "struct Group { owner: Usr = 0 }";
+    let defs_c = parse(c).unwrap();
+    let ir = lower(&[
+        Unit::new(a, &defs_a).in_file(Path::new("a.lstr")),
+        Unit::new(c, &defs_c).in_file(Path::new("c.lstr")),
+    ]);
+    let e = check(&ir).unwrap_err();
+    assert_eq!(e, Error::NameIsNotFound("Name".into()));
+    let d = e.diagnostic(&ir);
+    assert_eq!(d.file.as_deref(), Some(Path::new("a.lstr")));
+}
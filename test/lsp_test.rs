@@ -0,0 +1,210 @@
+//! drives the `limit-lsp` binary over stdio like an editor would
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use serde_json::{json, Value};
+
+const URI: &str = "file:///tmp/limit-lsp-test/hello.lstr";
+
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    id: i64,
+    /// notifications received while waiting for responses
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_limit-lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Self {
+            child,
+            stdin,
+            stdout,
+            id: 0,
+            notifications: vec![],
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            self.stdout.read_line(&mut header).unwrap();
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(n) = header.strip_prefix("Content-Length: ") {
+                length = n.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.id += 1;
+        let id = self.id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                assert!(message.get("error").is_none(), "{}", message);
+                return message["result"].clone();
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// the next diagnostics published
+    fn diagnostics(&mut self) -> Vec<Value> {
+        let message = match self.notifications.pop() {
+            Some(message) => message,
+            None => self.receive(),
+        };
+        assert_eq!(message["method"], "textDocument/publishDiagnostics");
+        message["params"]["diagnostics"].as_array().unwrap().clone()
+    }
+
+    fn at(&mut self, method: &str, line: u32, character: u32) -> Value {
+        let mut params = json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        });
+        if method == "textDocument/references" {
+            params["context"] = json!({ "includeDeclaration": true });
+        }
+        self.request(method, params)
+    }
+}
+
+const SRC: &str = "\
+/// a person
+struct User {
+    name: string = 0,
+}
+channel hello = send User -> offer | recv User -> end | hello
+";
+
+#[test]
+fn lsp_test() {
+    let mut client = Client::start();
+    let init = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(init["capabilities"]["hoverProvider"], true);
+    client.notify("initialized", json!({}));
+
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": { "uri": URI, "languageId": "limit", "version": 1, "text": "struct User {\n    name: strin = 0,\n}\n" } }),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "name-not-found");
+    assert_eq!(
+        diagnostics[0]["range"],
+        json!({ "start": { "line": 1, "character": 10 }, "end": { "line": 1, "character": 15 } })
+    );
+
+    client.notify(
+        "textDocument/didChange",
+        json!({ "textDocument": { "uri": URI, "version": 2 }, "contentChanges": [{ "text": SRC }] }),
+    );
+    assert!(client.diagnostics().is_empty());
+
+    // `User` in `send User`
+    let definition = client.at("textDocument/definition", 4, 22);
+    assert_eq!(
+        definition["range"]["start"],
+        json!({ "line": 1, "character": 7 })
+    );
+
+    let references = client.at("textDocument/references", 1, 9);
+    assert_eq!(references.as_array().unwrap().len(), 3);
+
+    let hover = client.at("textDocument/hover", 1, 8)["contents"]["value"].clone();
+    assert!(hover.as_str().unwrap().contains("a person"), "{}", hover);
+    let hover = client.at("textDocument/hover", 4, 10)["contents"]["value"].clone();
+    assert!(
+        hover
+            .as_str()
+            .unwrap()
+            .contains("channel hello = recv User -> choose"),
+        "{}",
+        hover
+    );
+
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let names = symbols
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["User", "hello"]);
+    assert_eq!(symbols[0]["children"][0]["name"], "name");
+
+    let completion = client.at("textDocument/completion", 2, 10);
+    let labels = completion
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["label"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert!(labels.contains(&"struct"));
+    assert!(labels.contains(&"User"));
+
+    let format = json!({ "textDocument": { "uri": URI }, "options": { "tabSize": 4, "insertSpaces": true } });
+    let edits = client.request("textDocument/formatting", format.clone());
+    assert_eq!(edits, json!([]));
+    client.notify(
+        "textDocument/didChange",
+        json!({ "textDocument": { "uri": URI, "version": 3 }, "contentChanges": [{ "text": "struct A { x: int = 0 }" }] }),
+    );
+    let edits = client.request("textDocument/formatting", format);
+    assert_eq!(edits[0]["newText"], "struct A {\n    x: int = 0,\n}\n");
+    assert_eq!(
+        edits[0]["range"]["end"],
+        json!({ "line": 0, "character": 23 })
+    );
+
+    // every syntax error at once, requests work on what parses
+    client.notify(
+        "textDocument/didChange",
+        json!({ "textDocument": { "uri": URI, "version": 4 }, "contentChanges": [{ "text": "struct A {\n    x: = 0,\n    y: int = 1,\n}\nenum B { b int }\n" }] }),
+    );
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    assert_eq!(symbols[0]["children"][0]["name"], "y");
+    assert_eq!(symbols[1]["name"], "B");
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 2);
+
+    client.request("shutdown", Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(client.child.wait().unwrap().success());
+}
@@ -0,0 +1,384 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use limit_stream::ir::{lower, Unit};
+use limit_stream::parser::parse;
+use limit_stream::runtime::mock::{self, Options};
+use limit_stream::runtime::monitor::{proxy, Action, Monitor, Protocol, Shape, Side, State};
+use limit_stream::runtime::trace::{read_trace, replay, validate, Recorder};
+use limit_stream::runtime::{encode_array_len, DecodeError, Encode, Error, Transport, Violation};
+use serde_json::json;
+use tokio::io::duplex;
+
+const SRC: &str = "
+struct Done { n: int, tags: [string] }
+channel sum = recv int -> offer | sum | recv Done -> send int -> end
+";
+
+fn def() -> Arc<Protocol> {
+    let defs = parse(SRC).unwrap();
+    let ir = lower(&[Unit::new(SRC, &defs)]);
+    Arc::new(Protocol::from_ir(&ir, "sum").unwrap())
+}
+
+struct Done(i64, Vec<String>);
+
+impl Encode for Done {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_array_len(buf, 2);
+        self.0.encode(buf);
+        self.1.encode(buf);
+    }
+}
+
+fn frame<T: Encode>(v: T) -> Vec<u8> {
+    let mut buf = vec![];
+    v.encode(&mut buf);
+    buf
+}
+
+#[test]
+fn protocol_test() {
+    let def = def();
+    assert_eq!(
+        def.types,
+        vec![(
+            "Done".to_string(),
+            Shape::Struct(vec![
+                ("n".to_string(), Shape::Int),
+                (
+                    "tags".to_string(),
+                    Shape::List(Box::new(Shape::String), None)
+                )
+            ])
+        )]
+    );
+    assert_eq!(
+        def.states,
+        vec![
+            State::Recv(Shape::Int, 1),
+            State::Offer(vec![0, 2]),
+            State::Recv(Shape::Def(0), 3),
+            State::Send(Shape::Int, 4),
+            State::End,
+        ]
+    );
+}
+
+#[test]
+fn generic_protocol_test() {
+    let def = mock::session(
+        "struct Page<T> { items: T = 0 }\nchannel pages = send Page<option<int>> -> end",
+        "pages",
+    )
+    .unwrap();
+    let items = Shape::Option(Box::new(Shape::Int));
+    assert_eq!(
+        def.types,
+        vec![(
+            "Page_Option_Int".to_string(),
+            Shape::Struct(vec![("items".to_string(), items)])
+        )]
+    );
+}
+
+#[test]
+fn monitor_test() {
+    let mut monitor = Monitor::new(def());
+    assert_eq!(monitor.turn(), Some(Side::Client));
+    assert!(matches!(
+        monitor.check(Side::Server, &frame(1i64)),
+        Err(Error::Protocol(Violation::OutOfTurn))
+    ));
+    assert!(matches!(
+        monitor.check(Side::Client, &frame("1".to_string())),
+        Err(Error::Decode(DecodeError::Malformed(_)))
+    ));
+    monitor.check(Side::Client, &frame(1i64)).unwrap();
+    assert!(matches!(
+        monitor.check(Side::Client, &frame(2u64)),
+        Err(Error::Protocol(Violation::UnknownBranch(2)))
+    ));
+    monitor.check(Side::Client, &frame(1u64)).unwrap();
+    // `Done` with a field missing
+    assert!(matches!(
+        monitor.check(Side::Client, &frame(vec![5i64])),
+        Err(Error::Decode(DecodeError::LengthMismatch {
+            expected: 2,
+            found: 1
+        }))
+    ));
+    let done = frame(Done(5, vec!["a".to_string()]));
+    monitor.check(Side::Client, &done).unwrap();
+    monitor.check(Side::Server, &frame(6i64)).unwrap();
+    assert_eq!(monitor.turn(), None);
+}
+
+#[tokio::test]
+async fn proxy_test() {
+    let (client, a) = duplex(64);
+    let (b, server) = duplex(64);
+    let mut client = Transport::new(client);
+    let mut server = Transport::new(server);
+    let peers = async {
+        client.send(&1i64).await?;
+        client.send(&1u64).await?;
+        client.send(&Done(5, vec!["a".to_string()])).await?;
+        assert_eq!(server.recv::<i64>().await?, 1);
+        assert_eq!(server.recv::<u64>().await?, 1);
+        server.send(&6i64).await?;
+        client.recv::<i64>().await
+    };
+    let (res, total) = tokio::join!(
+        proxy(Monitor::new(def()), a, b, |_| Action::Terminate),
+        peers
+    );
+    res.unwrap();
+    assert_eq!(total.unwrap(), 6);
+
+    // the client picks a branch that doesn't exist
+    let (client, a) = duplex(64);
+    let (b, _server) = duplex(64);
+    let mut client = Transport::new(client);
+    client.send(&1i64).await.unwrap();
+    client.send(&7u64).await.unwrap();
+    let mut reported = vec![];
+    let res = proxy(Monitor::new(def()), a, b, |e| {
+        reported.push(e.to_string());
+        Action::Terminate
+    })
+    .await;
+    assert!(matches!(
+        res,
+        Err(Error::Protocol(Violation::UnknownBranch(7)))
+    ));
+    assert_eq!(reported, vec!["protocol violation: unknown branch 7"]);
+}
+
+#[tokio::test]
+async fn proxy_out_of_turn_test() {
+    // the server sends before the client's `recv int`
+    let (_client, a) = duplex(64);
+    let (b, server) = duplex(64);
+    let mut server = Transport::new(server);
+    server.send(&6i64).await.unwrap();
+    let mut reported = vec![];
+    let res = proxy(Monitor::new(def()), a, b, |e| {
+        reported.push(e.to_string());
+        Action::Terminate
+    })
+    .await;
+    assert!(matches!(res, Err(Error::Protocol(Violation::OutOfTurn))));
+    assert_eq!(reported, vec!["protocol violation: message out of turn"]);
+}
+
+#[tokio::test]
+async fn proxy_forward_test() {
+    let (client, a) = duplex(64);
+    let (b, server) = duplex(64);
+    let peers = async move {
+        let mut client = Transport::new(client);
+        let mut server = Transport::new(server);
+        client.send(&1i64).await?;
+        client.send(&"left".to_string()).await?;
+        assert_eq!(server.recv::<i64>().await?, 1);
+        server.recv::<String>().await
+    };
+    let mut reported = 0;
+    let (res, after) = tokio::join!(
+        proxy(Monitor::new(def()), a, b, |_| {
+            reported += 1;
+            Action::Forward
+        }),
+        peers
+    );
+    res.unwrap();
+    assert_eq!(after.unwrap(), "left");
+    assert_eq!(reported, 1);
+}
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// the client's side of a whole session, the server answers `total`
+async fn session(total: i64) -> Vec<u8> {
+    let (client, server) = duplex(64);
+    let trace = Shared::default();
+    let mut client = Transport::new(client);
+    let mut server =
+        Transport::new(server).record(Side::Server, Recorder::new(def(), trace.clone()));
+    client.send(&1i64).await.unwrap();
+    client.send(&1u64).await.unwrap();
+    client.send(&Done(5, vec!["a".to_string()])).await.unwrap();
+    server.recv::<i64>().await.unwrap();
+    server.recv::<u64>().await.unwrap();
+    server.recv_frame().await.unwrap();
+    server.send(&total).await.unwrap();
+    let trace = trace.0.lock().unwrap().clone();
+    trace
+}
+
+#[tokio::test]
+async fn trace_test() {
+    let events = read_trace(&session(6).await[..]).unwrap();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[1].branch, Some(1));
+    assert_eq!(events[2].payload, Some(json!({"n": 5, "tags": ["a"]})));
+    assert_eq!(events[3].from, Side::Server);
+    assert!(events.iter().all(|e| e.violation.is_none()));
+    validate(def(), &events).unwrap();
+
+    let mut tampered = events.clone();
+    tampered[1].frame = "07".to_string();
+    assert!(matches!(
+        validate(def(), &tampered),
+        Err((1, Error::Protocol(Violation::UnknownBranch(7))))
+    ));
+
+    // play the client against a server answering 7
+    let (client, server) = duplex(64);
+    let live = async move {
+        let mut server = Transport::new(server);
+        server.recv::<i64>().await?;
+        server.recv::<u64>().await?;
+        server.recv_frame().await?;
+        server.send(&7i64).await
+    };
+    let (differs, live) = tokio::join!(replay(def(), &events, Side::Client, client), live);
+    live.unwrap();
+    assert_eq!(differs.unwrap(), vec![3]);
+}
+
+#[tokio::test]
+async fn mock_test() {
+    let options = Options {
+        side: Side::Client,
+        fixtures: [
+            ("int".to_string(), vec![json!(3)]),
+            ("Done".to_string(), vec![json!({"n": 5, "tags": ["a"]})]),
+        ]
+        .into(),
+        script: vec![1],
+        ..Options::default()
+    };
+    let (client, server) = duplex(64);
+    let live = async move {
+        let mut server = Transport::new(server);
+        let n = server.recv::<i64>().await?;
+        let tag = server.recv::<u64>().await?;
+        let done = server.recv_frame().await?.to_vec();
+        server.send(&8i64).await?;
+        Ok::<_, Error>((n, tag, done))
+    };
+    let (mocked, live) = tokio::join!(mock::run(def(), client, &options), live);
+    mocked.unwrap();
+    let (n, tag, done) = live.unwrap();
+    assert_eq!((n, tag), (3, 1));
+    assert_eq!(done, frame(Done(5, vec!["a".to_string()])));
+
+    // two mocks sending samples and random branches
+    let (client, server) = duplex(64);
+    let client_options = Options {
+        side: Side::Client,
+        seed: 1,
+        ..Options::default()
+    };
+    let server_options = Options::default();
+    let (client, server) = tokio::join!(
+        mock::run(def(), client, &client_options),
+        mock::run(def(), server, &server_options)
+    );
+    client.unwrap();
+    server.unwrap();
+}
+
+#[tokio::test]
+async fn mock_recursive_test() {
+    let def = mock::session(
+        "enum Tree { Node(Pair) = 0, Leaf(int) = 1 }\n\
+         struct Pair { a: Tree = 0, b: Tree = 1 }\n\
+         channel tree = send Tree -> end",
+        "tree",
+    )
+    .unwrap();
+    // samples past the depth limit take the variant which ends soonest
+    for seed in 0..20 {
+        let (client, server) = duplex(1 << 16);
+        let client_options = Options {
+            side: Side::Client,
+            ..Options::default()
+        };
+        let server_options = Options {
+            seed,
+            ..Options::default()
+        };
+        let (client, server) = tokio::join!(
+            mock::run(Arc::clone(&def), client, &client_options),
+            mock::run(Arc::clone(&def), server, &server_options)
+        );
+        client.unwrap();
+        server.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn mock_constraint_test() {
+    let def = mock::session(
+        r#"struct Account {
+             id: uint = 0 #[range="10..=20"],
+             name: string = 1 #[pattern="[a-z]{3}-[0-9]+"] #[max_len=6],
+             tags: [string] = 2 #[min_len=2],
+             score: option<double> = 3 #[range="0..1"],
+           }
+           channel account = send Account -> end"#,
+        "account",
+    )
+    .unwrap();
+    let State::Send(shape, _) = &def.states[0] else {
+        panic!("{:?}", def.states[0]);
+    };
+    for seed in 0..20 {
+        let (client, server) = duplex(1 << 16);
+        let options = Options {
+            seed,
+            ..Options::default()
+        };
+        let live = async {
+            let mut client = Transport::new(client);
+            let mut frame = client.recv_frame().await?;
+            Ok::<_, Error>(def.value(shape, &mut frame)?)
+        };
+        let (mocked, account) = tokio::join!(mock::run(Arc::clone(&def), server, &options), live);
+        mocked.unwrap();
+        let account = account.unwrap();
+        assert!((10..=20).contains(&account["id"].as_u64().unwrap()));
+        let name = account["name"].as_str().unwrap();
+        assert!(name.len() <= 6 && name[..4].ends_with('-'), "{}", name);
+        assert!(name[4..].chars().all(|c| c.is_ascii_digit()), "{}", name);
+        assert!(account["tags"].as_array().unwrap().len() >= 2);
+        let score = account["score"].as_f64().unwrap_or(0.0);
+        assert!((0.0..1.0).contains(&score));
+    }
+
+    // no string is both matched and short enough
+    let def = mock::session(
+        r#"struct Name { name: string = 0 #[pattern="[a-z]{3}"] #[max_len=2] }
+           channel name = send Name -> end"#,
+        "name",
+    )
+    .unwrap();
+    let (_client, server) = duplex(64);
+    let e = mock::run(def, server, &Options::default())
+        .await
+        .unwrap_err();
+    assert_eq!(e.to_string(), "can't sample a string matching `[a-z]{3}`");
+}
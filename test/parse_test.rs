@@ -1,8 +1,12 @@
 use limit_stream::ast::{
-    Annotation, Constant, EnumDef, Session, SessionDef, SessionType, SimpleType, StructDef,
-    Type, TypeOrName,
+    Annotation, AnnotationValue, Append, ArrayLength, ConstDef, Constant, ContainerType, Def,
+    EnumDef, EnumItem, Macro, Session, SessionDef, SessionOrName, SessionType, SessionUnion,
+    SimpleType, StructDef, StructItem, Type, TypeDef, TypeOrName,
+};
+use limit_stream::parser::{
+    _type, const_def, enum_def, enum_item, name, parse, parse_recovering, session_def, string_lit,
+    struct_def, struct_item, type_def, type_or_name, Node,
 };
-use limit_stream::parser::{_type, enum_def, enum_item, session_def, struct_def, struct_item};
 
 macro_rules! gen_test {
     ($parse: expr, $testname: ident, $src: expr, $result: expr) => {
@@ -13,6 +17,21 @@ macro_rules! gen_test {
     };
 }
 
+fn m<T>(body: T) -> Macro<'static, T> {
+    Macro {
+        appends: vec![],
+        body: Box::new(body),
+    }
+}
+
+fn simple(t: SimpleType) -> TypeOrName<'static> {
+    TypeOrName::Type(Box::new(Type::SimpleType(t)))
+}
+
+fn constant(c: Constant) -> TypeOrName<'static> {
+    TypeOrName::Type(Box::new(Type::Constant(c)))
+}
+
 gen_test!(
     _type,
     type_test,
@@ -21,17 +40,11 @@ send int ->
 send int ->
 end
 ",
-    Type::Session(SessionType(vec![
-        Session::Recv(TypeOrName::Type(Box::new(Type::SimpleType(
-            SimpleType::String
-        )))),
-        Session::Send(TypeOrName::Type(Box::new(Type::SimpleType(
-            SimpleType::Int
-        )))),
-        Session::Send(TypeOrName::Type(Box::new(Type::SimpleType(
-            SimpleType::Int
-        )))),
-        Session::Endpoint,
+    Type::SessionType(SessionType(vec![
+        m(Session::Recv(simple(SimpleType::String))),
+        m(Session::Send(simple(SimpleType::Int))),
+        m(Session::Send(simple(SimpleType::Int))),
+        m(Session::Endpoint),
     ]))
 );
 
@@ -41,18 +54,13 @@ gen_test!(
     "channel a = recv 1 -> recv 2 -> send 3 -> end",
     SessionDef {
         name: "a",
-        session: SessionType(vec![
-            Session::Recv(TypeOrName::Type(Box::new(Type::Constant(Constant::Uint(
-                1
-            ))))),
-            Session::Recv(TypeOrName::Type(Box::new(Type::Constant(Constant::Uint(
-                2
-            ))))),
-            Session::Send(TypeOrName::Type(Box::new(Type::Constant(Constant::Uint(
-                3
-            ))))),
-            Session::Endpoint,
-        ])
+        generics: vec![],
+        session: m(SessionType(vec![
+            m(Session::Recv(constant(Constant::Uint(1)))),
+            m(Session::Recv(constant(Constant::Uint(2)))),
+            m(Session::Send(constant(Constant::Uint(3)))),
+            m(Session::Endpoint),
+        ]))
     }
 );
 
@@ -60,7 +68,33 @@ gen_test!(
     struct_item,
     struct_item_test,
     "user: User = 0",
-    ("user", (TypeOrName::Name("User"), Some(0)))
+    StructItem("user", TypeOrName::Name("User"), Some(0), None)
+);
+
+gen_test!(
+    struct_item,
+    optional_struct_item_test,
+    r#"nick?: string = 3 default "anon""#,
+    StructItem(
+        "nick",
+        TypeOrName::Type(Box::new(Type::ContainerType(ContainerType::Option(
+            Box::new(simple(SimpleType::String))
+        )))),
+        Some(3),
+        Some(Constant::String("anon".to_string()))
+    )
+);
+
+gen_test!(
+    struct_item,
+    default_struct_item_test,
+    "retries: uint = default 3",
+    StructItem(
+        "retries",
+        simple(SimpleType::Uint),
+        None,
+        Some(Constant::Uint(3))
+    )
 );
 
 gen_test!(
@@ -75,28 +109,21 @@ struct User {
 ",
     StructDef {
         name: "User",
-        records: vec![
-            (
+        generics: vec![],
+        items: vec![
+            m(StructItem(
                 "name",
-                (
-                    TypeOrName::Type(Box::new(Type::SimpleType(SimpleType::String))),
-                    Some(0)
-                )
-            ),
-            (
-                "age",
-                (
-                    TypeOrName::Type(Box::new(Type::SimpleType(SimpleType::Uint))),
-                    Some(1)
-                )
-            ),
-            (
+                simple(SimpleType::String),
+                Some(0),
+                None
+            )),
+            m(StructItem("age", simple(SimpleType::Uint), Some(1), None)),
+            m(StructItem(
                 "desc",
-                (
-                    TypeOrName::Type(Box::new(Type::SimpleType(SimpleType::String))),
-                    Some(2)
-                )
-            ),
+                simple(SimpleType::String),
+                Some(2),
+                None
+            )),
         ]
     }
 );
@@ -106,7 +133,7 @@ gen_test!(
     enum_item_test,
     "Admin(user) = 0
 ",
-    ("Admin", (TypeOrName::Name("user"), Some(0)))
+    EnumItem("Admin", TypeOrName::Name("user"), Some(0))
 );
 
 gen_test!(
@@ -121,10 +148,177 @@ enum usertype {
 ",
     EnumDef {
         name: "usertype",
+        generics: vec![],
+        items: vec![
+            m(EnumItem("Admin", TypeOrName::Name("user"), Some(0))),
+            m(EnumItem("Normal", TypeOrName::Name("user"), Some(1))),
+            m(EnumItem("Visitor", TypeOrName::Name("visitor"), Some(2))),
+        ]
+    }
+);
+
+gen_test!(name, name_with_keyword_prefix_test, "endpoint", "endpoint");
+
+gen_test!(
+    string_lit,
+    string_lit_test,
+    r#""a \"b\"" rest"#,
+    "a \"b\"".to_string()
+);
+
+gen_test!(string_lit, empty_string_lit_test, "\"\"", String::new());
+
+gen_test!(
+    struct_def,
+    generic_struct_def_test,
+    "struct Page<T> { items: T = 0, next: Page<T> = 1 }",
+    StructDef {
+        name: "Page",
+        generics: vec!["T"],
         items: vec![
-            ("Admin", (TypeOrName::Name("user"), Some(0))),
-            ("Normal", (TypeOrName::Name("user"), Some(1))),
-            ("Visitor", (TypeOrName::Name("visitor"), Some(2))),
+            m(StructItem("items", TypeOrName::Name("T"), Some(0), None)),
+            m(StructItem(
+                "next",
+                TypeOrName::Generic("Page", vec![TypeOrName::Name("T")]),
+                Some(1),
+                None
+            )),
         ]
     }
 );
+
+gen_test!(
+    type_or_name,
+    generic_instance_test,
+    "Result<Page<User>, string>",
+    TypeOrName::Generic(
+        "Result",
+        vec![
+            TypeOrName::Generic("Page", vec![TypeOrName::Name("User")]),
+            simple(SimpleType::String),
+        ]
+    )
+);
+
+gen_test!(
+    session_def,
+    generic_session_def_test,
+    "channel Paginate<T> = send uint -> offer | Paginate<T> | recv Page<T> -> end",
+    SessionDef {
+        name: "Paginate",
+        generics: vec!["T"],
+        session: m(SessionType(vec![
+            m(Session::Send(simple(SimpleType::Uint))),
+            m(Session::Offer(SessionUnion(vec![
+                SessionOrName::Generic("Paginate", vec![TypeOrName::Name("T")]),
+                SessionOrName::Session(Box::new(SessionType(vec![
+                    m(Session::Recv(TypeOrName::Generic(
+                        "Page",
+                        vec![TypeOrName::Name("T")]
+                    ))),
+                    m(Session::Endpoint),
+                ]))),
+            ]))),
+        ]))
+    }
+);
+
+gen_test!(
+    const_def,
+    const_def_test,
+    "const MAX_RETRIES: uint = 3;",
+    ConstDef {
+        name: "MAX_RETRIES",
+        ty: simple(SimpleType::Uint),
+        value: Constant::Uint(3)
+    }
+);
+
+gen_test!(
+    type_def,
+    type_def_test,
+    "type Ids = [UserId; MAX_RETRIES];",
+    TypeDef {
+        name: "Ids",
+        generics: vec![],
+        ty: TypeOrName::Type(Box::new(Type::ContainerType(ContainerType::Array(
+            Box::new(TypeOrName::Name("UserId")),
+            Some(ArrayLength::Const("MAX_RETRIES"))
+        ))))
+    }
+);
+
+gen_test!(
+    struct_def,
+    constrained_struct_def_test,
+    r#"struct User { age: uint = 0 #[range="0..=150"] }"#,
+    StructDef {
+        name: "User",
+        generics: vec![],
+        items: vec![Macro {
+            appends: vec![Append::Annotation(Annotation(
+                "range",
+                AnnotationValue::Constant(Constant::String("0..=150".to_string()))
+            ))],
+            body: Box::new(StructItem("age", simple(SimpleType::Uint), Some(0), None)),
+        }]
+    }
+);
+
+#[test]
+fn recovering_test() {
+    let src = "\
+struct A {
+    x: int = 0,
+    y: = 1,
+    z: string = 2,
+}
+channel c = send ->
+enum E {
+    a(int) = 0,
+    b int = 1,
+}
+/// docs
+struct B {}
+";
+    let parsed = parse_recovering(src);
+    let lines = parsed
+        .errors
+        .iter()
+        .map(|e| e.span.unwrap().line)
+        .collect::<Vec<_>>();
+    assert_eq!(lines, [3, 6, 9]);
+    assert_eq!(parsed.defs.len(), 4);
+    assert_eq!(parsed.defs[1], Node::Error("channel c = send ->"));
+    let defs = parsed.defs().map(|d| &d.0).collect::<Vec<_>>();
+    match defs[0].body.as_ref() {
+        Def::StructDef(d) => assert_eq!(
+            d.items.iter().map(|i| i.body.0).collect::<Vec<_>>(),
+            ["x", "z"]
+        ),
+        def => panic!("{:?}", def),
+    }
+    match defs[1].body.as_ref() {
+        Def::EnumDef(d) => assert_eq!(d.items.len(), 1),
+        def => panic!("{:?}", def),
+    }
+    assert_eq!(defs[2].appends, [Append::DocsComment("/// docs\n")]);
+    assert_eq!(parse(src), Err(parsed.errors[0].clone()));
+
+    // an unclosed body ends at the next definition
+    let parsed = parse_recovering("struct A {\n    x: int = 0,\nstruct B {}\n");
+    assert_eq!(parsed.errors.len(), 1);
+    assert_eq!(parsed.errors[0].message, "expected `}`");
+    assert_eq!(parsed.defs().count(), 2);
+
+    // an unclosed `(` in an item doesn't hide the end of the body
+    let parsed = parse_recovering("struct A {\n    x: (int = 0\n}\nenum E {\n    b int = 1,\n}\n");
+    let lines = parsed
+        .errors
+        .iter()
+        .map(|e| e.span.unwrap().line)
+        .collect::<Vec<_>>();
+    assert_eq!(lines, [2, 5]);
+    assert!(parsed.errors.iter().all(|e| e.message != "expected `}`"));
+    assert_eq!(parsed.defs().count(), 2);
+}
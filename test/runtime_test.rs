@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use limit_stream::runtime::{
+    block_on, connect, decode_literal, encode_literal, matches_pattern, Chan, Client, Decode,
+    DecodeError, Encode, Error, Length, Link, Literal, Server, Transport, Uint, ValidationError,
+    Violation,
+};
+use tokio::io::duplex;
+
+fn roundtrip<T: Encode + Decode + PartialEq + std::fmt::Debug>(v: T) {
+    let mut buf = vec![];
+    v.encode(&mut buf);
+    let mut rest = &buf[..];
+    assert_eq!(T::decode(&mut rest), Ok(v));
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn wire_roundtrip_test() {
+    roundtrip(true);
+    roundtrip(-3i64);
+    roundtrip(u64::MAX);
+    roundtrip(1.5f64);
+    roundtrip("limit \"stream\"".to_string());
+    roundtrip(Some(3u64));
+    roundtrip(None::<u64>);
+    roundtrip(vec![Some("a".to_string()), None]);
+    roundtrip([1u64, 2, 3]);
+    roundtrip(Box::new(Some(1u64)));
+    roundtrip(HashMap::from([("a".to_string(), vec![1u64])]));
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Magic;
+
+impl Literal for Magic {
+    type Value = Uint;
+    fn value() -> Uint {
+        0x1157
+    }
+}
+
+#[test]
+fn literal_test() {
+    let mut buf = vec![];
+    encode_literal::<Magic>(&mut buf);
+    assert_eq!(decode_literal::<Magic>(&mut &buf[..]), Ok(Magic));
+
+    let mut buf = vec![];
+    42u64.encode(&mut buf);
+    assert_eq!(
+        decode_literal::<Magic>(&mut &buf[..]),
+        Err(DecodeError::UnexpectedLiteral {
+            expected: "4439".to_string(),
+            found: "42".to_string()
+        })
+    );
+}
+
+#[test]
+fn validate_test() {
+    assert_eq!("héllo".to_string().length(), 5);
+    assert_eq!([0u8; 3].length(), 3);
+    assert!(matches_pattern("abc", "[a-z]+"));
+    // patterns match the whole string
+    assert!(!matches_pattern("abc1", "[a-z]+"));
+
+    let e = ValidationError {
+        field: "age",
+        constraint: "range",
+    };
+    assert_eq!(DecodeError::from(e).to_string(), "field age violates range");
+}
+
+/// generated from `gen/sum.lstr`, so the session code has to type-check
+#[allow(dead_code)]
+mod sum {
+    include!("gen/sum.rs");
+}
+
+async fn serve<L: Link<i64> + Link<u64>>(
+    mut chan: Chan<sum::sum, Server<L>>,
+) -> Result<i64, Error> {
+    let mut total = 0;
+    loop {
+        let (n, next) = chan.recv().await?;
+        total += n;
+        match next.offer().await? {
+            sum::E1::Tsum(more) => chan = more,
+            sum::E1::TE0(done) => {
+                done.send(total).await?.close();
+                return Ok(total);
+            }
+        }
+    }
+}
+
+async fn add<L: Link<i64> + Link<u64>>(mut chan: Chan<sum::sum, Client<L>>) -> Result<i64, Error> {
+    for n in [1, 2] {
+        chan = chan.send(n).await?.choose(sum::E1::Tsum).await?;
+    }
+    let chan = chan.send(3).await?.choose(sum::E1::TE0).await?;
+    let (total, end) = chan.recv().await?;
+    end.close();
+    Ok(total)
+}
+
+#[tokio::test]
+async fn session_test() {
+    let (a, b) = duplex(64);
+    let (server, client) = tokio::join!(serve(Chan::server(a)), add(Chan::client(b)));
+    assert_eq!(server.unwrap(), 6);
+    assert_eq!(client.unwrap(), 6);
+}
+
+#[tokio::test]
+async fn memory_test() {
+    let (client, server) = connect::<sum::sum>();
+    let (server, client) = tokio::join!(serve(server), add(client));
+    assert_eq!(server.unwrap(), 6);
+    assert_eq!(client.unwrap(), 6);
+
+    let (client, server) = connect::<sum::sum>();
+    drop(client);
+    assert!(matches!(serve(server).await, Err(Error::Disconnected)));
+}
+
+/// the server sends `0, 1, 2` and picks when to stop
+#[tokio::test]
+async fn choose_test() {
+    let (client, server) = connect::<sum::count>();
+    let count = async move {
+        let mut chan = server;
+        for n in 0..2 {
+            chan = chan.send(n).await?.choose(sum::E3::Tcount).await?;
+        }
+        let chan = chan.send(2).await?.choose(sum::E3::TE2).await?;
+        let (reply, end) = chan.recv().await?;
+        end.close();
+        Ok::<_, Error>(reply)
+    };
+    let listen = async move {
+        let mut chan = client;
+        let mut seen = vec![];
+        loop {
+            let (n, next) = chan.recv().await?;
+            seen.push(n);
+            match next.offer().await? {
+                sum::E3::Tcount(more) => chan = more,
+                sum::E3::TE2(done) => {
+                    done.send(format!("{:?}", seen)).await?.close();
+                    return Ok::<_, Error>(seen);
+                }
+            }
+        }
+    };
+    let (reply, seen) = tokio::join!(count, listen);
+    assert_eq!(seen.unwrap(), [0, 1, 2]);
+    assert_eq!(reply.unwrap(), "[0, 1, 2]");
+}
+
+#[test]
+fn memory_thread_test() {
+    let (client, server) = connect::<sum::sum>();
+    let server = std::thread::spawn(move || block_on(serve(server)));
+    assert_eq!(block_on(add(client)).unwrap(), 6);
+    assert_eq!(server.join().unwrap().unwrap(), 6);
+}
+
+#[tokio::test]
+async fn session_error_test() {
+    // the peer hangs up
+    let (a, b) = duplex(64);
+    drop(b);
+    assert!(matches!(
+        serve(Chan::server(a)).await,
+        Err(Error::Disconnected)
+    ));
+
+    // a branch that doesn't exist
+    let (a, b) = duplex(64);
+    let mut peer = Transport::new(b);
+    peer.send(&1i64).await.unwrap();
+    peer.send(&7u64).await.unwrap();
+    assert!(matches!(
+        serve(Chan::server(a)).await,
+        Err(Error::Protocol(Violation::UnknownBranch(7)))
+    ));
+
+    // a string where an int is expected
+    let (a, b) = duplex(64);
+    let mut peer = Transport::new(b);
+    peer.send(&"one".to_string()).await.unwrap();
+    assert!(matches!(
+        serve(Chan::server(a)).await,
+        Err(Error::Decode(DecodeError::Malformed(_)))
+    ));
+}
@@ -0,0 +1,407 @@
+use std::path::Path;
+
+use limit_stream::ast::Constant;
+use limit_stream::ir::{lower, DefKind, Ir, Session, SessionType, Unit};
+use limit_stream::monomorphize::monomorphize;
+use limit_stream::parser::parse;
+use limit_stream::type_checker::{
+    check, constraint_checker, default_checker, duplicate_checker, infinite_size_checker, ir2graph,
+    kind_checker, ring_checker, session_checker, toposort, unused, Error, Kind, Namespace,
+};
+
+fn defs(src: &str) -> Ir {
+    lower(&[Unit::new(src, &parse(src).unwrap())])
+}
+
+#[test]
+fn generic_kind_test() {
+    let irs = defs(
+        "
+struct Page<T> { items: T = 0 }
+struct User { name: string = 0 }
+channel Paginate<T> = send uint -> offer | Paginate<T> | recv Page<T> -> end
+channel Users = recv Page<User> -> end
+",
+    );
+    assert_eq!(kind_checker(&irs), Ok(()));
+}
+
+#[test]
+fn duplicate_test() {
+    let src = "struct A { x: int = 0 }\nenum A { B(int) = 0 }";
+    let irs = defs(src);
+    let e = duplicate_checker(&irs).unwrap_err();
+    assert_eq!(
+        e,
+        Error::Duplicate {
+            namespace: Namespace::Definition,
+            name: "A".into(),
+            first: "A".into()
+        }
+    );
+    let d = e.diagnostic(&irs);
+    assert_eq!(d.code, "duplicate-definition");
+    assert_eq!(d.span.unwrap().line, 2);
+    assert_eq!(d.related[0].span.line, 1);
+
+    for (src, namespace, name, code) in [
+        (
+            "struct A { x: int = 0, x: string = 1 }",
+            Namespace::Field,
+            "x",
+            "duplicate-field",
+        ),
+        (
+            "enum E { B(int) = 0, B(string) = 1 }",
+            Namespace::Variant,
+            "B",
+            "duplicate-variant",
+        ),
+    ] {
+        let irs = defs(src);
+        let e = check(&irs).unwrap_err();
+        assert_eq!(
+            e,
+            Error::Duplicate {
+                namespace,
+                name: name.into(),
+                first: name.into()
+            }
+        );
+        assert_eq!(e.diagnostic(&irs).code, code);
+    }
+
+    // the first definition is reported in its own file
+    let (a, b) = ("struct A { x: int = 0 }", "\nenum A { B(int) = 0 }");
+    let (a_defs, b_defs) = (parse(a).unwrap(), parse(b).unwrap());
+    let irs = lower(&[
+        Unit::new(a, &a_defs).in_file(Path::new("a.lstr")),
+        Unit::new(b, &b_defs).in_file(Path::new("b.lstr")),
+    ]);
+    let d = check(&irs).unwrap_err().diagnostic(&irs);
+    assert_eq!(d.file.as_deref(), Some(Path::new("b.lstr")));
+    assert_eq!(d.related[0].file.as_deref(), Some(Path::new("a.lstr")));
+    assert_eq!(
+        d.to_string(),
+        "error[duplicate-definition]: b.lstr:2:6: `A` is defined more than once\n  \
+         note: a.lstr:1:8: `A` is first defined here"
+    );
+}
+
+#[test]
+fn arity_mismatch_test() {
+    let irs = defs("struct Page<T> { items: T = 0 } struct Users { page: Page = 0 }");
+    assert_eq!(
+        kind_checker(&irs),
+        Err(Error::ArityMismatch {
+            name: "Page".into(),
+            expected: 1,
+            found: 0
+        })
+    );
+}
+
+#[test]
+fn kind_mismatch_test() {
+    let irs = defs("channel Ping = send int -> end struct Wrap { ping: Ping = 0 }");
+    assert_eq!(
+        kind_checker(&irs),
+        Err(Error::KindMismatch {
+            name: "Ping".into(),
+            expected: Kind::Type,
+            found: Kind::Session
+        })
+    );
+    let irs = defs("channel Loop<T> = offer | T | end");
+    assert_eq!(
+        kind_checker(&irs),
+        Err(Error::KindMismatch {
+            name: "T".into(),
+            expected: Kind::Session,
+            found: Kind::Type
+        })
+    );
+}
+
+#[test]
+fn monomorphize_test() {
+    let irs = defs(
+        "
+struct Page<T> { items: T = 0 }
+struct User { name: string = 0 }
+channel Users = recv Page<User> -> recv Page<uint> -> recv Page<User> -> end
+",
+    );
+    let mono = monomorphize(&irs).unwrap();
+    let mono_names = mono
+        .defs()
+        .map(|(_, d)| mono.name(d.name))
+        .collect::<Vec<_>>();
+    assert_eq!(mono_names, vec!["User", "Page_User", "Page_Uint", "Users"]);
+
+    // containers are spelled out in the instance names
+    let irs = defs(
+        "
+struct Page<T> { items: T = 0 }
+struct User { name: string = 0 }
+const N: uint = 2;
+channel Users = recv Page<option<int>> -> recv Page<[User]> -> recv Page<dict<string, [User; N]>> -> end
+",
+    );
+    let mono = monomorphize(&irs).unwrap();
+    let mono_names = mono
+        .defs()
+        .map(|(_, d)| mono.name(d.name))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        mono_names,
+        vec![
+            "User",
+            "N",
+            "Page_Option_Int",
+            "Page_List_User",
+            "Page_Dict_String_Array_N_User",
+            "Users"
+        ]
+    );
+}
+
+#[test]
+fn default_test() {
+    let irs = defs(
+        r#"struct Config {
+  retries: uint = 0 default 3,
+  ratio: double = 1 default 1,
+  name?: string = 2 default "x",
+  verbose: bool = default false,
+}"#,
+    );
+    assert_eq!(default_checker(&irs), Ok(()));
+
+    let irs = defs("struct Config { retries: uint = default -3 }");
+    assert_eq!(
+        default_checker(&irs),
+        Err(Error::InvalidDefault {
+            field: "retries".into(),
+            value: Constant::Int(-3)
+        })
+    );
+    let irs = defs(r#"struct Config { name: string = default 1 }"#);
+    assert!(default_checker(&irs).is_err());
+}
+
+#[test]
+fn const_and_alias_test() {
+    let irs = defs(
+        "
+const MAX: uint = 3;
+type UserId = uint;
+type Ids = [UserId; MAX];
+type Proto = recv Ids -> end;
+struct User { id: UserId = 0 default 1 }
+channel Login = offer | Proto | end
+",
+    );
+    assert_eq!(check(&irs), Ok(()));
+
+    let irs = defs("type A = B; type B = A;");
+    assert_eq!(kind_checker(&irs), Err(Error::RecursiveAlias("A".into())));
+
+    let irs = defs("const NAME: string = \"x\"; type Names = [string; NAME];");
+    assert_eq!(
+        kind_checker(&irs),
+        Err(Error::InvalidArrayLength("NAME".into()))
+    );
+
+    let irs = defs("const MAX: uint = 3; struct S { max: MAX = 0 }");
+    assert_eq!(
+        kind_checker(&irs),
+        Err(Error::KindMismatch {
+            name: "MAX".into(),
+            expected: Kind::Type,
+            found: Kind::Constant
+        })
+    );
+
+    let irs = defs("const MAX: uint = -3;");
+    assert_eq!(
+        check(&irs),
+        Err(Error::InvalidConstant {
+            name: "MAX".into(),
+            value: Constant::Int(-3)
+        })
+    );
+}
+
+#[test]
+fn constraint_test() {
+    let irs = defs(
+        r#"
+type Age = uint;
+struct User {
+  name: string = 0 #[min_len=1] #[pattern="[a-z]+"],
+  age?: Age = 1 #[range="0..=150"],
+  tags: [string] = 2 #[max_len=4],
+}
+"#,
+    );
+    assert_eq!(check(&irs), Ok(()));
+
+    let irs = defs(r#"struct S { name: string = 0 #[range="0..1"] }"#);
+    assert_eq!(
+        constraint_checker(&irs),
+        Err(Error::ConstraintNotApplicable {
+            field: "name".into(),
+            constraint: "range".into()
+        })
+    );
+
+    let irs = defs(r#"struct S { age: uint = 0 #[range="-1.."] }"#);
+    assert_eq!(
+        constraint_checker(&irs),
+        Err(Error::InvalidConstraint {
+            field: "age".into(),
+            constraint: "range".into()
+        })
+    );
+
+    let irs = defs(r#"struct S { name: string = 0 #[pattern="("] }"#);
+    assert_eq!(
+        constraint_checker(&irs),
+        Err(Error::InvalidConstraint {
+            field: "name".into(),
+            constraint: "pattern".into()
+        })
+    );
+}
+
+#[test]
+fn constraint_const_test() {
+    let irs = defs("const MAX: uint = 4;\nstruct S { tags: [string] = 0 #[max_len=MAX] }");
+    assert_eq!(check(&irs), Ok(()));
+
+    let irs = defs("const MAX: string = \"4\";\nstruct S { tags: [string] = 0 #[max_len=MAX] }");
+    assert_eq!(
+        check(&irs),
+        Err(Error::InvalidConstraint {
+            field: "tags".into(),
+            constraint: "max_len".into()
+        })
+    );
+
+    let irs = defs("struct S { tags: [string] = 0 #[max_len=S] }");
+    assert_eq!(
+        check(&irs),
+        Err(Error::KindMismatch {
+            name: "S".into(),
+            expected: Kind::Constant,
+            found: Kind::Type
+        })
+    );
+
+    let irs = defs("struct S { tags: [string] = 0 #[max_len=MAX] }");
+    assert_eq!(check(&irs), Err(Error::NameIsNotFound("MAX".into())));
+}
+
+#[test]
+fn graph_test() {
+    let irs = defs(
+        "
+channel Login = send User -> offer | Login | recv Users -> end
+struct Users { users: [User] = 0, first: option<User> = 1 }
+struct User { name: Name = 0, tags: [Name; MAX] = 1 }
+type Name = string;
+const MAX: uint = 4;
+struct Unused { user: User = 0 }
+",
+    );
+    let graph = ir2graph(&irs);
+    assert_eq!(graph.node_count(), irs.len());
+    assert!(ring_checker(&irs));
+    let names = |ids: Vec<_>| {
+        ids.into_iter()
+            .map(|id| irs.name(irs.def(id).name))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        names(toposort(&irs)),
+        ["Name", "MAX", "User", "Users", "Login", "Unused"]
+    );
+    assert_eq!(names(unused(&irs)), ["Unused"]);
+    assert_eq!(infinite_size_checker(&irs), Ok(()));
+
+    let irs = defs("struct A { b: B = 0 } struct B { a: A = 0 } enum E { A(A), E(E) }");
+    assert_eq!(
+        infinite_size_checker(&irs),
+        Err(Error::InfiniteSize("A".into()))
+    );
+    let irs =
+        defs("struct A { b: B = 0 } enum B { A(A), Nil(bool) } struct C { c: C = 0 #[boxed] }");
+    assert_eq!(infinite_size_checker(&irs), Ok(()));
+    let irs = defs("struct C { c: [C; 2] = 0 }");
+    assert_eq!(check(&irs), Err(Error::InfiniteSize("C".into())));
+    let irs = defs("struct List { next: option<List> = 0, all: dict<string, List> = 1 }");
+    assert_eq!(check(&irs), Ok(()));
+    assert!(unused(&irs).is_empty());
+
+    // a generic argument is stored the way the target stores its parameter
+    let pair = "struct Pair<T> { a: T = 0, b: T = 1 } struct Wrap<T> { p: Pair<T> = 0 }";
+    let irs = defs(&format!("{} struct Tree {{ k: Wrap<Tree> = 0 }}", pair));
+    assert_eq!(check(&irs), Err(Error::InfiniteSize("Tree".into())));
+    let irs = defs(&format!(
+        "{} struct Tree {{ k: Pair<option<Tree>> = 0 }}",
+        pair
+    ));
+    assert_eq!(check(&irs), Ok(()));
+    let irs = defs("struct List<T> { items: [T] = 0 } struct Tree { k: List<Tree> = 0 }");
+    assert_eq!(check(&irs), Ok(()));
+}
+
+#[test]
+fn session_test() {
+    let irs = defs(
+        "
+channel A = send int -> offer | A | recv int -> end
+channel B = offer | A | end
+type Reply = recv string -> end;
+",
+    );
+    assert_eq!(check(&irs), Ok(()));
+
+    let irs = defs("channel A = end -> send int");
+    assert_eq!(
+        session_checker(&irs),
+        Err(Error::UnreachableAfterEnd("A".into()))
+    );
+    // every path continues in a branch of `offer`/`choose`
+    let irs = defs("channel A = offer | A | B -> send int -> end\nchannel B = end");
+    assert_eq!(
+        session_checker(&irs),
+        Err(Error::UnreachableAfterEnd("A".into()))
+    );
+    let irs = defs("channel A = send int -> recv int");
+    assert_eq!(
+        session_checker(&irs),
+        Err(Error::UnterminatedSession("A".into()))
+    );
+    let irs = defs("channel A = offer | send int | end");
+    assert_eq!(
+        session_checker(&irs),
+        Err(Error::UnterminatedSession("A".into()))
+    );
+    let irs = defs("type Reply = recv string;");
+    assert_eq!(
+        session_checker(&irs),
+        Err(Error::UnterminatedSession("Reply".into()))
+    );
+    let irs = defs("channel A = offer | B | end channel B = choose | A | send int -> end");
+    assert_eq!(
+        session_checker(&irs),
+        Err(Error::UnguardedRecursion("A".into()))
+    );
+
+    let mut irs = defs("channel A = end");
+    let a = irs.lookup("A").unwrap();
+    irs.def_mut(a).kind = DefKind::Session(SessionType(vec![Session::Offer(vec![])]));
+    assert_eq!(session_checker(&irs), Err(Error::EmptyBranch("A".into())));
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use limit_stream::build::{Build, Outcome};
+use limit_stream::codegen::rust::Rust;
+use limit_stream::config::{GenMode, Target};
+use limit_stream::sources::Sources;
+use limit_stream::watch::Watch;
+
+const USER: &str = "struct User {\n    name: string = 0,\n}\n";
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("limit-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("idl/nested")).unwrap();
+    dir
+}
+
+fn build(dir: &Path) -> Build {
+    Build {
+        rust: Rust::new(&Target::default(), GenMode::Client),
+        inputs: vec![dir.join("idl")],
+        out: Some(dir.join("gen")),
+        sources: Sources::default(),
+    }
+}
+
+#[test]
+fn build_test() {
+    let dir = temp_dir("build");
+    let idl = dir.join("idl");
+    fs::write(idl.join("a.lstr"), USER).unwrap();
+    fs::write(idl.join("nested/b.lstr"), USER).unwrap();
+    fs::write(idl.join("bad.lstr"), "struct {").unwrap();
+    let build = build(&dir);
+
+    let reports = build.all();
+    assert_eq!(reports.len(), 3);
+    assert_eq!(reports.iter().filter(|r| r.is_err()).count(), 1);
+    assert!(reports[1].is_err());
+    assert_eq!(reports[1].source, idl.join("bad.lstr"));
+    assert!(dir.join("gen/a.rs").is_file());
+    assert!(dir.join("gen/nested/b.rs").is_file());
+
+    fs::remove_file(idl.join("a.lstr")).unwrap();
+    let reports = build.update([idl.join("a.lstr").as_path()]);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].outcome, Outcome::Removed(dir.join("gen/a.rs")));
+    assert!(!dir.join("gen/a.rs").exists());
+
+    // only IDL files of the inputs
+    assert!(build.update([idl.join("nested/b.rs").as_path()]).is_empty());
+    assert!(build
+        .update([dir.join("gen/nested/b.rs").as_path()])
+        .is_empty());
+
+    // ill-typed files aren't generated
+    fs::write(idl.join("a.lstr"), "struct A { a: Missing = 0 }").unwrap();
+    let reports = build.update([idl.join("a.lstr").as_path()]);
+    assert!(matches!(&reports[0].outcome, Outcome::Failed(d) if d.code == "name-not-found"));
+    assert!(!dir.join("gen/a.rs").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn watch_test() {
+    let dir = temp_dir("watch");
+    let idl = dir.join("idl");
+    let build = build(&dir);
+    let watch = Watch::new(&build.inputs).unwrap();
+    let source = idl.join("nested/c.lstr");
+
+    let next = |content: &str| {
+        fs::write(&source, content).unwrap();
+        loop {
+            let paths = watch.next(Some(Duration::from_secs(5))).unwrap();
+            assert!(!paths.is_empty(), "no change notified");
+            if paths.contains(&source) {
+                return build.update(paths.iter().map(PathBuf::as_path));
+            }
+        }
+    };
+
+    let reports = next(USER);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(
+        reports[0].outcome,
+        Outcome::Generated(dir.join("gen/nested/c.rs"))
+    );
+    assert!(dir.join("gen/nested/c.rs").is_file());
+
+    // errors are reported, the watch goes on
+    let reports = next("struct {");
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}